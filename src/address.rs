@@ -0,0 +1,238 @@
+use openssl::ec::EcPoint;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::crypto::{hash160, CryptoError, KeyPair, SCHNORR_PUBLIC_KEY_SIZE};
+use crate::script::Script;
+
+/// version byte of addresses that pay to the hash of an ECDSA public key
+pub const ADDRESS_VERSION: u8 = 0x35;
+/// version byte of addresses that pay to a Schnorr public key
+pub const SCHNORR_ADDRESS_VERSION: u8 = 0x3f;
+pub const ADDRESS_HASH_LENGTH: usize = 20;
+const CHECKSUM_LENGTH: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressError {
+    InvalidBase58,
+    InvalidLength(usize),
+    InvalidChecksum,
+    UnknownVersion(u8),
+}
+
+impl fmt::Display for AddressError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "address error: {:?}", self)
+    }
+}
+
+impl std::error::Error for AddressError {}
+
+/// a version byte and payload, written as Base58Check
+/// base58(version + payload + sha256(sha256(version + payload))[..4])
+/// the payload is the hash160 of an ECDSA public key or the x-only Schnorr public key
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub struct Address {
+    version: u8,
+    payload: Vec<u8>,
+}
+
+fn payload_length(version: u8) -> Option<usize> {
+    match version {
+        ADDRESS_VERSION => Some(ADDRESS_HASH_LENGTH),
+        SCHNORR_ADDRESS_VERSION => Some(SCHNORR_PUBLIC_KEY_SIZE),
+        _ => None,
+    }
+}
+
+impl Address {
+    pub fn from_public_key(public_key: &EcPoint) -> Result<Self, CryptoError> {
+        Ok(Self::from_public_key_bytes(&KeyPair::public_key_to_bytes(
+            public_key,
+        )?))
+    }
+
+    pub fn from_public_key_bytes(public_key: &[u8]) -> Self {
+        Self {
+            version: ADDRESS_VERSION,
+            payload: hash160(public_key),
+        }
+    }
+
+    pub fn from_schnorr_key(public_key: &[u8]) -> Option<Self> {
+        if public_key.len() != SCHNORR_PUBLIC_KEY_SIZE {
+            return None;
+        }
+
+        Some(Self {
+            version: SCHNORR_ADDRESS_VERSION,
+            payload: public_key.to_vec(),
+        })
+    }
+
+    /// the address of a pay to public key hash or pay to Schnorr public key script
+    pub fn from_script(script: &Script) -> Option<Self> {
+        if let Some(hash) = script.as_public_key_hash() {
+            return (hash.len() == ADDRESS_HASH_LENGTH).then_some(Self {
+                version: ADDRESS_VERSION,
+                payload: hash,
+            });
+        }
+
+        Self::from_schnorr_key(&script.as_public_key()?)
+    }
+
+    /// the script that locks coins to this address
+    pub fn script_pubkey(&self) -> Script {
+        match self.version {
+            SCHNORR_ADDRESS_VERSION => Script::pay_to_public_key(&self.payload),
+            _ => Script::pay_to_public_key_hash(&self.payload),
+        }
+    }
+
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+}
+
+fn checksum(data: &[u8]) -> [u8; CHECKSUM_LENGTH] {
+    let hash = Sha256::digest(&Sha256::digest(data));
+
+    let mut checksum = [0; CHECKSUM_LENGTH];
+    checksum.copy_from_slice(&hash[..CHECKSUM_LENGTH]);
+    checksum
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut data = vec![self.version];
+        data.extend(&self.payload);
+        data.extend(checksum(&data));
+
+        write!(f, "{}", bs58::encode(data).into_string())
+    }
+}
+
+impl FromStr for Address {
+    type Err = AddressError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let data = bs58::decode(s)
+            .into_vec()
+            .map_err(|_| AddressError::InvalidBase58)?;
+
+        if data.len() <= CHECKSUM_LENGTH {
+            return Err(AddressError::InvalidLength(data.len()));
+        }
+
+        let (payload, check) = data.split_at(data.len() - CHECKSUM_LENGTH);
+        if checksum(payload) != check {
+            return Err(AddressError::InvalidChecksum);
+        }
+
+        let version = payload[0];
+        let length = payload_length(version).ok_or(AddressError::UnknownVersion(version))?;
+        if payload.len() != 1 + length {
+            return Err(AddressError::InvalidLength(data.len()));
+        }
+
+        Ok(Self {
+            version,
+            payload: payload[1..].to_vec(),
+        })
+    }
+}
+
+impl From<Address> for String {
+    fn from(address: Address) -> Self {
+        address.to_string()
+    }
+}
+
+impl TryFrom<String> for Address {
+    type Error = AddressError;
+
+    fn try_from(str: String) -> Result<Self, Self::Error> {
+        str.parse()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_address_roundtrip() {
+        let public_key = KeyPair::generate()
+            .unwrap()
+            .private_key
+            .to_public_key()
+            .unwrap();
+        let address = Address::from_public_key(&public_key).unwrap();
+        let encoded = address.to_string();
+
+        assert!(encoded.starts_with('N'));
+        assert_eq!(encoded.parse::<Address>(), Ok(address.clone()));
+        assert_eq!(
+            Address::from_script(&address.script_pubkey()),
+            Some(address)
+        );
+
+        let schnorr_key = KeyPair::generate()
+            .unwrap()
+            .private_key
+            .to_schnorr_key()
+            .unwrap()
+            .public_key();
+        let address = Address::from_schnorr_key(&schnorr_key).unwrap();
+        assert_eq!(address.to_string().parse::<Address>(), Ok(address.clone()));
+        assert_eq!(
+            address.script_pubkey(),
+            Script::pay_to_public_key(&schnorr_key)
+        );
+        assert_eq!(
+            Address::from_script(&address.script_pubkey()),
+            Some(address)
+        );
+    }
+
+    #[test]
+    fn test_reject_malformed() {
+        let public_key = KeyPair::generate()
+            .unwrap()
+            .private_key
+            .to_public_key()
+            .unwrap();
+        let encoded = Address::from_public_key(&public_key).unwrap().to_string();
+
+        // a typo breaks the checksum
+        let mut typo = encoded.clone().into_bytes();
+        typo[5] = if typo[5] == b'2' { b'3' } else { b'2' };
+        assert_eq!(
+            String::from_utf8(typo).unwrap().parse::<Address>(),
+            Err(AddressError::InvalidChecksum)
+        );
+
+        assert_eq!("0OIl".parse::<Address>(), Err(AddressError::InvalidBase58));
+        assert!(encoded[..encoded.len() - 1].parse::<Address>().is_err());
+        assert!(KeyPair::public_key_to_hex(&public_key)
+            .unwrap()
+            .parse::<Address>()
+            .is_err());
+
+        let mut other_version = vec![0x00];
+        other_version.extend([0; ADDRESS_HASH_LENGTH]);
+        other_version.extend(checksum(&other_version));
+        assert_eq!(
+            bs58::encode(other_version).into_string().parse::<Address>(),
+            Err(AddressError::UnknownVersion(0x00))
+        );
+    }
+}
@@ -0,0 +1,88 @@
+//! signs a partially signed transaction with the keys of a wallet, without a node or network
+//!
+//! usage: sign_psbt KEYSTORE PSBT_FILE [OUTPUT_FILE] [--sighash ALL|NONE|SINGLE[|ANYONECANPAY]]
+//!
+//! the passphrase is read from WALLET_PASSPHRASE or the first line of stdin
+
+use std::env;
+use std::fs;
+use std::io::{self, BufRead};
+use std::process;
+
+use naivecoin_rs::address::Address;
+use naivecoin_rs::psbt::PartiallySignedTransaction;
+use naivecoin_rs::transaction::{parse_sighash, SIGHASH_ALL};
+use naivecoin_rs::wallet::Wallet;
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
+}
+
+fn main() {
+    env_logger::init();
+
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let hash_type = match args.iter().position(|arg| arg == "--sighash") {
+        Some(i) if i + 1 < args.len() => {
+            let sighash = args.remove(i + 1);
+            args.remove(i);
+            parse_sighash(&sighash).unwrap_or_else(|| fail("invalid sighash"))
+        }
+        Some(_) => fail("--sighash needs a value"),
+        None => SIGHASH_ALL,
+    };
+    let (keystore, input, output) = match args.as_slice() {
+        [keystore, input] => (keystore, input, None),
+        [keystore, input, output] => (keystore, input, Some(output)),
+        _ => fail("usage: sign_psbt KEYSTORE PSBT_FILE [OUTPUT_FILE] [--sighash SIGHASH]"),
+    };
+
+    let contents = fs::read_to_string(input).unwrap_or_else(|e| fail(&e.to_string()));
+    let mut psbt: PartiallySignedTransaction =
+        serde_json::from_str(&contents).unwrap_or_else(|e| fail(&format!("invalid psbt: {}", e)));
+    psbt.check().unwrap_or_else(|e| fail(&e.to_string()));
+
+    let passphrase = env::var("WALLET_PASSPHRASE").unwrap_or_else(|_| {
+        let mut line = String::new();
+        io::stdin()
+            .lock()
+            .read_line(&mut line)
+            .unwrap_or_else(|e| fail(&e.to_string()));
+        line.trim_end_matches(['\r', '\n']).to_owned()
+    });
+    let mut wallet = Wallet::new(keystore.to_owned());
+    wallet
+        .unlock(&passphrase, None)
+        .unwrap_or_else(|e| fail(&e.to_string()));
+    let private_keys = wallet
+        .get_private_keys()
+        .unwrap_or_else(|| fail("could not decrypt the keys"));
+
+    // the signer can't ask a node, so show what is being paid before signing
+    for tx_out in psbt.tx.tx_outs.iter() {
+        let receiver = Address::from_script(&tx_out.script_pubkey).map_or_else(
+            || tx_out.script_pubkey.to_hex(),
+            |address| address.to_string(),
+        );
+        eprintln!("pay {} to {}", tx_out.amount, receiver);
+    }
+    match psbt.fee() {
+        Some(fee) => eprintln!("fee {}", fee),
+        None => fail("the outputs are worth more than the inputs"),
+    }
+
+    let signed = psbt.sign(&private_keys, hash_type);
+    eprintln!(
+        "signed {} of {} inputs, complete: {}",
+        signed,
+        psbt.tx.tx_ins.len(),
+        psbt.is_complete()
+    );
+
+    let signed_psbt = serde_json::to_string_pretty(&psbt).unwrap();
+    match output {
+        Some(output) => fs::write(output, signed_psbt).unwrap_or_else(|e| fail(&e.to_string())),
+        None => println!("{}", signed_psbt),
+    }
+}
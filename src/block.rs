@@ -3,16 +3,23 @@ use sha2::{Digest, Sha256};
 use std::sync::RwLockWriteGuard;
 
 use crate::{
+    address::Address,
     chain::BlockChain,
-    crypto::KeyPair,
+    coin_selection::CoinControl,
+    crypto::{tagged_hash, CryptoError, PrivateKey},
     difficulter::{simple::SimpleDifficulter, Difficulter},
-    transaction::{Transaction, UnspentTxOut},
+    signed_message::{sign_digest, verify_digest},
+    slashing::{self, DoubleSignEvidence},
+    transaction::{Transaction, TxOut, UnspentTxOut},
     transaction_pool::TransactionPool,
     validator::Validator,
     // validator::{pow::PowValidator, Validator},
     wallet::Wallet,
 };
 
+/// block signatures are hashed under their own tag, so they can't be reused as anything else
+const BLOCK_TAG: &[u8] = b"naivecoin/block";
+
 #[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
 pub struct Block {
     pub index: u32,
@@ -23,7 +30,41 @@ pub struct Block {
     pub nonce: u32,
     pub difficulty: u32,
     pub miner_address: String,
-    pub miner_balance: u64, // TODO: calculate this with unspent_tx_outs so people don't fake it
+    /// the coin age of the staked output, validators recompute it from the UTXO set at the
+    /// previous block
+    pub miner_balance: u64,
+    /// signature of the hash by the key of miner_address, proves the stake belongs to the miner.
+    /// empty for proof of work blocks
+    #[serde(default)]
+    pub signature: String,
+    /// proofs that other miners signed two blocks at the same height, their stake is burned
+    #[serde(default)]
+    pub evidence: Vec<DoubleSignEvidence>,
+}
+
+/// the part of a block its miner signs, enough to prove a miner signed two blocks at one height
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct SignedHeader {
+    pub index: u32,
+    pub hash: String,
+    pub miner_address: String,
+    pub signature: String,
+}
+
+impl SignedHeader {
+    /// the height and the hash, which commits to everything else in the block
+    pub fn signature_digest(&self) -> Vec<u8> {
+        let mut data = self.index.to_be_bytes().to_vec();
+        data.extend(self.hash.as_bytes());
+
+        tagged_hash(BLOCK_TAG, &data)
+    }
+
+    pub fn has_valid_signature(&self) -> bool {
+        self.miner_address
+            .parse::<Address>()
+            .is_ok_and(|address| verify_digest(&address, &self.signature_digest(), &self.signature))
+    }
 }
 
 impl Block {
@@ -37,9 +78,36 @@ impl Block {
             &self.difficulty,
             &self.nonce,
             &self.miner_address,
+            &self.miner_balance,
+            &self.evidence,
         )
     }
 
+    pub fn header(&self) -> SignedHeader {
+        SignedHeader {
+            index: self.index,
+            hash: self.hash.clone(),
+            miner_address: self.miner_address.clone(),
+            signature: self.signature.clone(),
+        }
+    }
+
+    /// what the miner signs
+    pub fn signature_digest(&self) -> Vec<u8> {
+        self.header().signature_digest()
+    }
+
+    /// sign the block with the key of miner_address, the hash has to be set first
+    pub fn sign(&mut self, private_key: &PrivateKey, address: &Address) -> Result<(), CryptoError> {
+        self.signature = sign_digest(private_key, address, &self.signature_digest())?;
+
+        Ok(())
+    }
+
+    pub fn has_valid_signature(&self) -> bool {
+        self.header().has_valid_signature()
+    }
+
     /// check if the next block is valid for the given previous block
     pub fn is_valid_next_block(
         next: &Block,
@@ -48,13 +116,16 @@ impl Block {
         validator: &impl Validator,
         unspent_tx_outs: &[UnspentTxOut],
     ) -> bool {
-        if validator.is_valid(prev, next, chain, unspent_tx_outs) {
+        if validator.is_valid(prev, next, chain, unspent_tx_outs)
+            && slashing::has_valid_evidence(next, chain)
+        {
             return true;
         }
 
         false
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn calculate_hash_from_data(
         index: &u32,
         previous_hash: &str,
@@ -63,6 +134,8 @@ impl Block {
         difficulty: &u32,
         nonce: &u32,
         miner_address: &String,
+        miner_balance: &u64,
+        evidence: &[DoubleSignEvidence],
     ) -> String {
         let mut hasher = Sha256::new();
 
@@ -75,24 +148,32 @@ impl Block {
         hasher.update(difficulty.to_be_bytes());
         hasher.update(nonce.to_be_bytes());
         hasher.update(miner_address.as_bytes());
+        hasher.update(miner_balance.to_be_bytes());
+        for e in evidence.iter() {
+            hasher.update(e.id())
+        }
 
         format!("{:x}", hasher.finalize())
     }
 
+    /// the coinbase claims the fees, which the pool transactions pay.
+    /// None if the validator can't produce a block, like a staker whose wallet is locked
     pub fn generate_next(
         chain: &BlockChain,
         wallet: &Wallet,
         tx_pool: &TransactionPool,
+        evidence: Vec<DoubleSignEvidence>,
+        fees: u64,
         validator: &impl Validator,
-    ) -> Self {
-        let public_key = wallet.get_public_key();
-
+    ) -> Option<Self> {
         let coinbase_tx = Transaction::get_coinbase_tx(
-            KeyPair::public_key_to_hex(&public_key),
+            wallet.get_address().ok()?.script_pubkey(),
             (chain.get_latest().unwrap().index + 1) as u64,
+            fees,
         );
         Self::generate_next_raw(
-            vec![vec![coinbase_tx], tx_pool.0.clone()].concat(),
+            [vec![coinbase_tx], tx_pool.0.clone()].concat(),
+            evidence,
             chain,
             validator,
         )
@@ -101,40 +182,48 @@ impl Block {
     /// generate the next block with given block_data
     pub fn generate_next_raw(
         block_data: Vec<Transaction>,
+        evidence: Vec<DoubleSignEvidence>,
         chain: &BlockChain,
         validator: &impl Validator,
-    ) -> Self {
+    ) -> Option<Self> {
         let prev_block = chain.get_latest().unwrap();
         let difficulty = SimpleDifficulter::get_difficulty(chain);
 
-        validator.find_block(&prev_block, block_data, difficulty)
+        validator.find_block(chain, &prev_block, block_data, evidence, difficulty)
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn generate_next_with_transaction(
-        receiver_addr: String,
-        amount: u64,
+        tx_outs: Vec<TxOut>,
         chain: &BlockChain,
         wallet: &Wallet,
         pool: &TransactionPool,
+        evidence: Vec<DoubleSignEvidence>,
         unspent_tx_outs: RwLockWriteGuard<Vec<UnspentTxOut>>, // TODO: remove state from validator
+        coin_control: &CoinControl,
         validator: &impl Validator,
     ) -> Option<Self> {
-        let public_key = wallet.get_public_key();
-        let private_key = wallet.get_private_key();
+        // a locked wallet can't sign
+        let private_keys = wallet.get_private_keys()?;
+        let change_script = wallet.get_change_address().ok()?.script_pubkey();
 
-        let coinbase_tx = Transaction::get_coinbase_tx(
-            KeyPair::public_key_to_hex(&public_key),
+        if let Some(tx) = Wallet::create_transaction(
+            tx_outs,
+            &private_keys,
+            change_script,
+            &unspent_tx_outs,
+            pool,
+            0,
             (chain.get_latest().unwrap().index + 1) as u64,
-        );
-        if let Some(tx) =
-            Wallet::create_transaction(receiver_addr, amount, &private_key, &unspent_tx_outs, pool)
-        {
+            coin_control,
+        ) {
+            let coinbase_tx = Transaction::get_coinbase_tx(
+                wallet.get_address().ok()?.script_pubkey(),
+                (chain.get_latest().unwrap().index + 1) as u64,
+                tx.get_fee(&unspent_tx_outs)?,
+            );
             drop(unspent_tx_outs);
-            return Some(Self::generate_next_raw(
-                vec![coinbase_tx, tx],
-                chain,
-                validator,
-            ));
+            return Self::generate_next_raw(vec![coinbase_tx, tx], evidence, chain, validator);
         }
         None
     }
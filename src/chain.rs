@@ -3,6 +3,7 @@ use log::{error, info};
 use crate::block::Block;
 use crate::difficulter::simple::{SimpleDifficulter, START_DIFFICULTY};
 use crate::difficulter::Difficulter;
+use crate::slashing;
 use crate::transaction::{Transaction, UnspentTxOut};
 use crate::transaction_pool::TransactionPool;
 use crate::validator::Validator;
@@ -37,11 +38,15 @@ impl BlockChain {
             validator,
             unspent_tx_outs,
         ) {
-            if let Some(ret_val) =
-                Transaction::process_transaction(&new.data, unspent_tx_outs, &(new.index as u64))
-            {
-                self.blocks.push(new);
+            if let Some(ret_val) = Transaction::process_transaction(
+                &new.data,
+                unspent_tx_outs,
+                &(new.index as u64),
+                &new.timestamp,
+            ) {
                 *unspent_tx_outs = ret_val;
+                slashing::burn(&new.evidence, unspent_tx_outs);
+                self.blocks.push(new);
                 pool.update(unspent_tx_outs);
             }
         } else {
@@ -59,7 +64,7 @@ impl BlockChain {
     ) {
         let new_chain = BlockChain { blocks: new_blocks };
 
-        if let Some(new_unspent_tx_outs) = new_chain.is_valid(validator, unspent_tx_outs) {
+        if let Some(new_unspent_tx_outs) = new_chain.is_valid(validator) {
             if SimpleDifficulter::get_accumulated_difficulty(&new_chain)
                 > SimpleDifficulter::get_accumulated_difficulty(self)
             {
@@ -89,6 +94,8 @@ impl BlockChain {
             nonce: 0,
             miner_address: String::new(),
             miner_balance: 0,
+            signature: String::new(),
+            evidence: vec![],
         };
 
         let hash = g.calculate_hash();
@@ -97,13 +104,10 @@ impl BlockChain {
         g
     }
 
-    /// check if the complete chain is valid
+    /// check if the complete chain is valid, every block is validated against the UTXO set at
+    /// its parent
     // TODO: return result
-    fn is_valid(
-        &self,
-        validator: &impl Validator,
-        old_u_tx_outs: &[UnspentTxOut],
-    ) -> Option<Vec<UnspentTxOut>> {
+    fn is_valid(&self, validator: &impl Validator) -> Option<Vec<UnspentTxOut>> {
         if *self.blocks.first().unwrap() != BlockChain::get_genesis() {
             return None;
         }
@@ -118,7 +122,7 @@ impl BlockChain {
                 self.blocks.get(i - 1).unwrap(),
                 &current_chain,
                 validator,
-                old_u_tx_outs,
+                &new_unspent_tx_outs,
             ) {
                 return None;
             }
@@ -127,8 +131,10 @@ impl BlockChain {
                 &self.blocks.get(i).unwrap().data,
                 &new_unspent_tx_outs,
                 &(self.blocks.get(i).unwrap().index as u64),
+                &self.blocks.get(i).unwrap().timestamp,
             ) {
                 new_unspent_tx_outs = x;
+                slashing::burn(&self.blocks[i].evidence, &mut new_unspent_tx_outs);
             } else {
                 return None;
             }
@@ -150,7 +156,6 @@ mod tests {
     #[test]
     fn test_is_valid() {
         let validator = PowValidator {};
-        let unspent_tx_outs: Vec<UnspentTxOut> = vec![];
 
         let mut chain = BlockChain {
             blocks: vec![BlockChain::get_genesis()],
@@ -166,11 +171,13 @@ mod tests {
             nonce: 0,
             miner_address: String::new(),
             miner_balance: 0,
+            signature: String::new(),
+            evidence: vec![],
         };
         second.hash = second.calculate_hash();
         // Don't add new blocks like this there is a dedicated function for this called add
         chain.blocks.push(second.clone());
-        assert!(chain.is_valid(&validator, &unspent_tx_outs).is_some());
+        assert!(chain.is_valid(&validator).is_some());
 
         let mut third = Block {
             index: 2,
@@ -182,11 +189,13 @@ mod tests {
             nonce: 0,
             miner_address: String::new(),
             miner_balance: 0,
+            signature: String::new(),
+            evidence: vec![],
         };
         third.hash = third.calculate_hash();
         let mut c1 = chain.clone();
         c1.blocks.push(third);
-        assert!(c1.is_valid(&validator, &unspent_tx_outs).is_none());
+        assert!(c1.is_valid(&validator).is_none());
 
         let mut forth = Block {
             index: 2,
@@ -198,10 +207,12 @@ mod tests {
             nonce: 0,
             miner_address: String::new(),
             miner_balance: 0,
+            signature: String::new(),
+            evidence: vec![],
         };
         forth.hash = forth.calculate_hash();
         chain.blocks.push(forth);
-        assert!(chain.is_valid(&validator, &unspent_tx_outs).is_none());
+        assert!(chain.is_valid(&validator).is_none());
     }
 
     #[test]
@@ -223,6 +234,8 @@ mod tests {
                     nonce: 0,
                     miner_address: String::new(),
                     miner_balance: 0,
+                    signature: String::new(),
+                    evidence: vec![],
                 },
             ],
         };
@@ -239,6 +252,8 @@ mod tests {
             nonce: 0,
             miner_address: String::new(),
             miner_balance: 0,
+            signature: String::new(),
+            evidence: vec![],
         };
         new_block.hash = new_block.calculate_hash();
         let mut new_chain = original.clone();
@@ -0,0 +1,64 @@
+use std::cmp::Reverse;
+
+use super::{input_size, CoinSelector, Selection, SelectionTarget};
+use crate::transaction::UnspentTxOut;
+
+/// give up after trying this many combinations
+const MAX_TRIES: usize = 100_000;
+
+/// searches for inputs that pay the target without change, within the cost of a change output.
+/// no change output means a smaller transaction and no link to a change address
+pub struct BranchAndBound;
+
+impl CoinSelector for BranchAndBound {
+    fn select(utxos: &[UnspentTxOut], target: &SelectionTarget) -> Option<Selection> {
+        let mut candidates: Vec<(u64, &UnspentTxOut)> = utxos
+            .iter()
+            .filter_map(|utxo| Some((target.effective_value(utxo)?, utxo)))
+            .collect();
+        candidates.sort_by_key(|(value, _)| Reverse(*value));
+
+        let lower = target.amount.checked_add(target.fee(target.base_size))?;
+        let change_input_size = candidates
+            .first()
+            .map_or(0, |(_, utxo)| input_size(&utxo.script_pubkey));
+        let upper = lower.checked_add(target.cost_of_change(change_input_size))?;
+
+        // what the candidates from an index on can add at most
+        let mut remaining: Vec<u64> = vec![0; candidates.len() + 1];
+        for i in (0..candidates.len()).rev() {
+            remaining[i] = remaining[i + 1].saturating_add(candidates[i].0);
+        }
+        if remaining[0] < lower {
+            return None;
+        }
+
+        // depth first, including a candidate before excluding it
+        let mut included: Vec<usize> = vec![];
+        let mut value: u64 = 0;
+        let mut index = 0;
+        for _ in 0..MAX_TRIES {
+            let backtrack = if value > upper || value.saturating_add(remaining[index]) < lower {
+                true
+            } else if value >= lower {
+                let inputs = included.iter().map(|&i| candidates[i].1.clone()).collect();
+                return target.finish(inputs);
+            } else {
+                index >= candidates.len()
+            };
+
+            if backtrack {
+                // exclude the last included candidate and continue after it
+                let last = included.pop()?;
+                value -= candidates[last].0;
+                index = last + 1;
+            } else {
+                included.push(index);
+                value += candidates[index].0;
+                index += 1;
+            }
+        }
+
+        None
+    }
+}
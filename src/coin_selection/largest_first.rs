@@ -0,0 +1,27 @@
+use std::cmp::Reverse;
+
+use super::{CoinSelector, Selection, SelectionTarget};
+use crate::transaction::UnspentTxOut;
+
+/// spends the biggest outputs first, which needs the fewest inputs
+pub struct LargestFirst;
+
+impl CoinSelector for LargestFirst {
+    fn select(utxos: &[UnspentTxOut], target: &SelectionTarget) -> Option<Selection> {
+        let mut candidates: Vec<&UnspentTxOut> = utxos
+            .iter()
+            .filter(|utxo| target.effective_value(utxo).is_some())
+            .collect();
+        candidates.sort_by_key(|utxo| Reverse(utxo.amount));
+
+        let mut inputs = vec![];
+        for utxo in candidates {
+            inputs.push(utxo.clone());
+            if let Some(selection) = target.finish(inputs.clone()) {
+                return Some(selection);
+            }
+        }
+
+        None
+    }
+}
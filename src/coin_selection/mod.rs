@@ -0,0 +1,330 @@
+pub mod branch_and_bound;
+pub mod largest_first;
+pub mod privacy;
+pub mod random_improve;
+
+use openssl::rand::rand_bytes;
+use serde::{Deserialize, Serialize};
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::script::Script;
+use crate::transaction::UnspentTxOut;
+
+use self::branch_and_bound::BranchAndBound;
+use self::largest_first::LargestFirst;
+use self::privacy::Privacy;
+use self::random_improve::RandomImprove;
+
+/// transaction count, output count and lock time
+const TX_BASE_SIZE: usize = 3 * 8;
+/// tx_out_id, tx_out_index, sequence and the length of the script_sig
+const TX_IN_BASE_SIZE: usize = 8 + 64 + 8 + 4 + 8;
+/// amount and the length of the script_pubkey
+const TX_OUT_BASE_SIZE: usize = 8 + 8;
+/// push of a DER signature with the hash type
+const SIGNATURE_PUSH_SIZE: usize = 1 + 73;
+/// push of a compressed public key
+const PUBLIC_KEY_PUSH_SIZE: usize = 1 + 33;
+
+/// picks the outputs that pay for a transaction
+pub trait CoinSelector {
+    fn select(utxos: &[UnspentTxOut], target: &SelectionTarget) -> Option<Selection>;
+}
+
+/// what the selected outputs have to pay for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SelectionTarget {
+    /// sum of the outputs that are paid
+    pub amount: u64,
+    /// in coins per 1000 bytes
+    pub fee_rate: u64,
+    /// size of the transaction without inputs and change
+    pub base_size: usize,
+    /// size the change output adds to the transaction
+    pub change_size: usize,
+}
+
+impl SelectionTarget {
+    pub fn new(
+        tx_out_scripts: &[&Script],
+        change_script: &Script,
+        amount: u64,
+        fee_rate: u64,
+    ) -> Self {
+        SelectionTarget {
+            amount,
+            fee_rate,
+            base_size: TX_BASE_SIZE + tx_out_scripts.iter().map(|s| tx_out_size(s)).sum::<usize>(),
+            change_size: tx_out_size(change_script),
+        }
+    }
+
+    /// fees are rounded up so a transaction never pays less than the fee rate
+    pub fn fee(&self, size: usize) -> u64 {
+        (size as u64 * self.fee_rate).div_ceil(1000)
+    }
+
+    /// what spending the output adds to the transaction, None if it costs more than it's worth
+    pub fn effective_value(&self, utxo: &UnspentTxOut) -> Option<u64> {
+        utxo.amount
+            .checked_sub(self.fee(input_size(&utxo.script_pubkey)))
+            .filter(|&value| value > 0)
+    }
+
+    /// fee of adding a change output now and spending it later. change below this isn't worth it
+    pub fn cost_of_change(&self, change_input_size: usize) -> u64 {
+        self.fee(self.change_size) + self.fee(change_input_size)
+    }
+
+    /// decide whether the inputs cover the target and whether the rest is worth a change output
+    /// leftovers that are too small for change go to the fee
+    pub fn finish(&self, inputs: Vec<UnspentTxOut>) -> Option<Selection> {
+        let total = inputs
+            .iter()
+            .try_fold(0u64, |total, utxo| total.checked_add(utxo.amount))?;
+        let size = self.base_size
+            + inputs
+                .iter()
+                .map(|utxo| input_size(&utxo.script_pubkey))
+                .sum::<usize>();
+
+        let available = total.checked_sub(self.amount)?;
+        if available < self.fee(size) {
+            return None;
+        }
+        let fee_with_change = self.fee(size + self.change_size);
+        let change = available.saturating_sub(fee_with_change);
+
+        // the change will be spent from an address of the same type as the inputs
+        let change_input_size = inputs
+            .first()
+            .map_or(0, |utxo| input_size(&utxo.script_pubkey));
+        if change > self.fee(change_input_size) {
+            Some(Selection {
+                inputs,
+                change,
+                fee: fee_with_change,
+            })
+        } else {
+            Some(Selection {
+                inputs,
+                change: 0,
+                fee: available,
+            })
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Selection {
+    pub inputs: Vec<UnspentTxOut>,
+    /// 0 if the transaction doesn't need a change output
+    pub change: u64,
+    pub fee: u64,
+}
+
+/// reference to a transaction output, used to spend exactly the outputs the user picked
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutPoint {
+    pub tx_out_id: String,
+    pub tx_out_index: u64,
+}
+
+impl OutPoint {
+    pub fn matches(&self, utxo: &UnspentTxOut) -> bool {
+        self.tx_out_id == utxo.tx_out_id && self.tx_out_index == utxo.tx_out_index
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CoinSelection {
+    LargestFirst,
+    /// exact match without change, falls back to largest first
+    #[default]
+    BranchAndBound,
+    RandomImprove,
+    Privacy,
+}
+
+impl CoinSelection {
+    pub fn select(&self, utxos: &[UnspentTxOut], target: &SelectionTarget) -> Option<Selection> {
+        match self {
+            CoinSelection::LargestFirst => LargestFirst::select(utxos, target),
+            CoinSelection::BranchAndBound => BranchAndBound::select(utxos, target)
+                .or_else(|| LargestFirst::select(utxos, target)),
+            CoinSelection::RandomImprove => RandomImprove::select(utxos, target),
+            CoinSelection::Privacy => Privacy::select(utxos, target),
+        }
+    }
+}
+
+impl FromStr for CoinSelection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "largestFirst" => Ok(CoinSelection::LargestFirst),
+            "branchAndBound" => Ok(CoinSelection::BranchAndBound),
+            "randomImprove" => Ok(CoinSelection::RandomImprove),
+            "privacy" => Ok(CoinSelection::Privacy),
+            _ => Err(format!("unknown coin selection: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for CoinSelection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            CoinSelection::LargestFirst => "largestFirst",
+            CoinSelection::BranchAndBound => "branchAndBound",
+            CoinSelection::RandomImprove => "randomImprove",
+            CoinSelection::Privacy => "privacy",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// how the wallet picks the inputs of a transaction
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CoinControl {
+    pub strategy: CoinSelection,
+    /// in coins per 1000 bytes
+    pub fee_rate: u64,
+    /// spend exactly these outputs instead of selecting them
+    pub inputs: Option<Vec<OutPoint>>,
+}
+
+impl CoinControl {
+    pub fn select(&self, utxos: &[UnspentTxOut], target: &SelectionTarget) -> Option<Selection> {
+        match &self.inputs {
+            Some(out_points) => {
+                let inputs = out_points
+                    .iter()
+                    .map(|out_point| utxos.iter().find(|utxo| out_point.matches(utxo)).cloned())
+                    .collect::<Option<Vec<UnspentTxOut>>>()?;
+                target.finish(inputs)
+            }
+            None => self.strategy.select(utxos, target),
+        }
+    }
+}
+
+fn tx_out_size(script_pubkey: &Script) -> usize {
+    TX_OUT_BASE_SIZE + script_pubkey.0.len()
+}
+
+/// estimated size of a signed input that spends script_pubkey
+pub fn input_size(script_pubkey: &Script) -> usize {
+    let script_sig_size = if script_pubkey.as_public_key().is_some() {
+        SIGNATURE_PUSH_SIZE
+    } else if let Some((required, _)) = script_pubkey.as_multisig() {
+        // OP_0 for the extra item that OP_CHECKMULTISIG pops
+        1 + required * SIGNATURE_PUSH_SIZE
+    } else {
+        SIGNATURE_PUSH_SIZE + PUBLIC_KEY_PUSH_SIZE
+    };
+
+    TX_IN_BASE_SIZE + script_sig_size
+}
+
+/// uniformly random index below n
+fn random_index(n: usize) -> usize {
+    let mut bytes = [0; 8];
+    rand_bytes(&mut bytes).expect("no randomness");
+    (u64::from_be_bytes(bytes) % n as u64) as usize
+}
+
+fn shuffle<T>(items: &mut [T]) {
+    for i in (1..items.len()).rev() {
+        items.swap(i, random_index(i + 1));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utxo(id: &str, amount: u64) -> UnspentTxOut {
+        UnspentTxOut {
+            tx_out_id: id.to_string(),
+            tx_out_index: 0,
+            address: None,
+            script_pubkey: Script::pay_to_public_key_hash(&[0; 20]),
+            amount,
+            block_index: 0,
+            timestamp: 0,
+            is_coinbase: false,
+        }
+    }
+
+    fn target(amount: u64, fee_rate: u64) -> SelectionTarget {
+        let script = Script::pay_to_public_key_hash(&[1; 20]);
+        SelectionTarget::new(&[&script], &script, amount, fee_rate)
+    }
+
+    #[test]
+    fn test_strategies() {
+        let utxos = vec![utxo("a", 10), utxo("b", 30), utxo("c", 25), utxo("d", 5)];
+
+        // without fees branch and bound finds the exact match
+        let selection = CoinSelection::BranchAndBound
+            .select(&utxos, &target(35, 0))
+            .unwrap();
+        assert_eq!(selection.change, 0);
+        assert_eq!(selection.fee, 0);
+        assert_eq!(selection.inputs.iter().map(|u| u.amount).sum::<u64>(), 35);
+
+        let selection = CoinSelection::LargestFirst
+            .select(&utxos, &target(35, 0))
+            .unwrap();
+        assert_eq!(selection.inputs.len(), 2);
+        assert_eq!(selection.inputs[0].amount, 30);
+        assert_eq!(selection.change, 20);
+
+        for strategy in [
+            CoinSelection::LargestFirst,
+            CoinSelection::BranchAndBound,
+            CoinSelection::RandomImprove,
+            CoinSelection::Privacy,
+        ] {
+            let selection = strategy.select(&utxos, &target(40, 0)).unwrap();
+            let total: u64 = selection.inputs.iter().map(|u| u.amount).sum();
+            assert_eq!(total, 40 + selection.change + selection.fee);
+
+            assert!(strategy.select(&utxos, &target(71, 0)).is_none());
+        }
+    }
+
+    #[test]
+    fn test_fees() {
+        let utxos = vec![utxo("a", 10), utxo("b", 30)];
+        let target = target(30, 10);
+        let fee_one_input = target.fee(target.base_size + input_size(&utxos[0].script_pubkey));
+        assert!(fee_one_input > 0);
+
+        // the 30 coin output can't pay the fee on its own
+        let selection = CoinSelection::LargestFirst.select(&utxos, &target).unwrap();
+        assert_eq!(selection.inputs.len(), 2);
+        assert!(selection.fee > fee_one_input);
+        assert_eq!(
+            selection.inputs.iter().map(|u| u.amount).sum::<u64>(),
+            30 + selection.change + selection.fee
+        );
+
+        // coin control spends exactly the outputs it is given
+        let coin_control = CoinControl {
+            inputs: Some(vec![OutPoint {
+                tx_out_id: String::from("b"),
+                tx_out_index: 0,
+            }]),
+            ..Default::default()
+        };
+        assert!(coin_control.select(&utxos, &target).is_none());
+
+        let selection = coin_control.select(&utxos, &self::target(30, 0)).unwrap();
+        assert_eq!(selection.inputs, vec![utxos[1].clone()]);
+    }
+}
@@ -0,0 +1,47 @@
+use std::collections::HashMap;
+
+use super::{CoinSelector, Selection, SelectionTarget};
+use crate::script::Script;
+use crate::transaction::UnspentTxOut;
+
+/// spends all outputs of an address together, so the address isn't linked to another
+/// transaction later. uses the fewest addresses, preferring the smallest one that pays on its own
+pub struct Privacy;
+
+impl CoinSelector for Privacy {
+    fn select(utxos: &[UnspentTxOut], target: &SelectionTarget) -> Option<Selection> {
+        let mut groups: HashMap<&Script, Vec<UnspentTxOut>> = HashMap::new();
+        for utxo in utxos
+            .iter()
+            .filter(|utxo| target.effective_value(utxo).is_some())
+        {
+            groups
+                .entry(&utxo.script_pubkey)
+                .or_default()
+                .push(utxo.clone());
+        }
+
+        let mut groups: Vec<(u64, Vec<UnspentTxOut>)> = groups
+            .into_values()
+            .map(|group| (group.iter().map(|utxo| utxo.amount).sum(), group))
+            .collect();
+        groups.sort_by_key(|(total, _)| *total);
+
+        if let Some(selection) = groups
+            .iter()
+            .find_map(|(_, group)| target.finish(group.clone()))
+        {
+            return Some(selection);
+        }
+
+        let mut inputs = vec![];
+        for (_, group) in groups.iter().rev() {
+            inputs.extend(group.iter().cloned());
+            if let Some(selection) = target.finish(inputs.clone()) {
+                return Some(selection);
+            }
+        }
+
+        None
+    }
+}
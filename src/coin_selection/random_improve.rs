@@ -0,0 +1,49 @@
+use super::{shuffle, CoinSelector, Selection, SelectionTarget};
+use crate::transaction::UnspentTxOut;
+
+/// picks random outputs until the target is paid, then keeps adding random outputs while that
+/// brings the change closer to the amount. change of a similar size as the payment hides which
+/// output is the payment, and spreads the wallet over outputs of useful sizes
+pub struct RandomImprove;
+
+impl CoinSelector for RandomImprove {
+    fn select(utxos: &[UnspentTxOut], target: &SelectionTarget) -> Option<Selection> {
+        let mut candidates: Vec<&UnspentTxOut> = utxos
+            .iter()
+            .filter(|utxo| target.effective_value(utxo).is_some())
+            .collect();
+        shuffle(&mut candidates);
+
+        let mut inputs = vec![];
+        let mut candidates = candidates.into_iter();
+        let mut selection = loop {
+            inputs.push(candidates.next()?.clone());
+            if let Some(selection) = target.finish(inputs.clone()) {
+                break selection;
+            }
+        };
+
+        let ideal = target.amount.saturating_mul(2);
+        let limit = target.amount.saturating_mul(3);
+        let mut total: u64 = inputs.iter().map(|utxo| utxo.amount).sum();
+        for utxo in candidates {
+            let new_total = total.saturating_add(utxo.amount);
+            if new_total > limit || new_total.abs_diff(ideal) >= total.abs_diff(ideal) {
+                continue;
+            }
+
+            inputs.push(utxo.clone());
+            match target.finish(inputs.clone()) {
+                Some(improved) => {
+                    selection = improved;
+                    total = new_total;
+                }
+                None => {
+                    inputs.pop();
+                }
+            }
+        }
+
+        Some(selection)
+    }
+}
@@ -7,54 +7,126 @@ use openssl::{
     nid::Nid,
     pkey::Private,
 };
+use ripemd160::Ripemd160;
+use sha2::{Digest, Sha256};
 use std::{
-    error::Error,
-    fs::{self, File},
+    fmt,
+    fs::{self, OpenOptions},
     io::{self, Write},
+    os::unix::fs::OpenOptionsExt,
     path::Path,
 };
 
 const CURVE: Nid = Nid::X9_62_PRIME256V1;
 
+/// SEC1 public key encodings, 0x02/0x03 + x for compressed and 0x04 + x + y for uncompressed
+pub const COMPRESSED_PUBLIC_KEY_SIZE: usize = 33;
+pub const UNCOMPRESSED_PUBLIC_KEY_SIZE: usize = 65;
+/// BIP-340 Schnorr public keys are the x coordinate of a secp256k1 point
+pub const SCHNORR_PUBLIC_KEY_SIZE: usize = 32;
+pub const SCHNORR_SIGNATURE_SIZE: usize = 64;
+pub const PRIVATE_KEY_SIZE: usize = 32;
+
+const SCHNORR_KEY_TAG: &[u8] = b"naivecoin/schnorr-key";
+const SCHNORR_CHALLENGE_TAG: &[u8] = b"BIP0340/challenge";
+
+#[derive(Debug)]
+pub enum CryptoError {
+    InvalidHex(hex::FromHexError),
+    /// the bytes are not an accepted public key encoding or not a point on the curve
+    InvalidPublicKey,
+    InvalidSignature,
+    InvalidPrivateKey,
+    Openssl(ErrorStack),
+    Io(io::Error),
+}
+
+impl fmt::Display for CryptoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CryptoError::InvalidHex(e) => write!(f, "invalid hex: {}", e),
+            CryptoError::InvalidPublicKey => write!(f, "invalid public key"),
+            CryptoError::InvalidSignature => write!(f, "invalid signature"),
+            CryptoError::InvalidPrivateKey => write!(f, "invalid private key"),
+            CryptoError::Openssl(e) => write!(f, "openssl error: {}", e),
+            CryptoError::Io(e) => write!(f, "io error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for CryptoError {}
+
+impl From<hex::FromHexError> for CryptoError {
+    fn from(e: hex::FromHexError) -> Self {
+        CryptoError::InvalidHex(e)
+    }
+}
+
+impl From<ErrorStack> for CryptoError {
+    fn from(e: ErrorStack) -> Self {
+        CryptoError::Openssl(e)
+    }
+}
+
+impl From<io::Error> for CryptoError {
+    fn from(e: io::Error) -> Self {
+        CryptoError::Io(e)
+    }
+}
+
 // higher level abstraction for the parts of openssl that will be used in this project
 pub struct KeyPair {
     pub private_key: PrivateKey,
 }
 
 impl KeyPair {
-    pub fn generate() -> Self {
-        let group = Self::get_group();
-        let key = EcKey::generate(&group).unwrap();
+    pub fn generate() -> Result<Self, CryptoError> {
+        let group = Self::get_group()?;
+        let key = EcKey::generate(&group)?;
 
-        Self {
+        Ok(Self {
             private_key: PrivateKey { key },
+        })
+    }
+
+    pub fn public_key_from_hex(str: &str) -> Result<EcPoint, CryptoError> {
+        Self::public_key_from_bytes(&hex::decode(str)?)
+    }
+
+    pub fn public_key_to_hex(public_key: &EcPoint) -> Result<String, CryptoError> {
+        Ok(hex::encode(Self::public_key_to_bytes(public_key)?))
+    }
+
+    /// consensus rule for ECDSA public keys in scripts: compressed is the canonical form,
+    /// uncompressed is still accepted and every other encoding (e.g. hybrid 0x06/0x07) is invalid
+    /// 32 byte keys in scripts are Schnorr keys and don't go through this check
+    pub fn is_valid_public_key_encoding(bytes: &[u8]) -> bool {
+        match bytes.first() {
+            Some(0x02) | Some(0x03) => bytes.len() == COMPRESSED_PUBLIC_KEY_SIZE,
+            Some(0x04) => bytes.len() == UNCOMPRESSED_PUBLIC_KEY_SIZE,
+            _ => false,
         }
     }
 
-    pub fn public_key_from_hex(str: &String) -> Result<EcPoint, ErrorStack> {
-        let mut ctx = bn::BigNumContext::new().unwrap();
-        EcPoint::from_bytes(
-            &Self::get_group(),
-            hex::decode(str.as_bytes()).unwrap().as_slice(),
-            &mut ctx,
-        )
+    /// decodes both compressed and uncompressed keys
+    pub fn public_key_from_bytes(bytes: &[u8]) -> Result<EcPoint, CryptoError> {
+        if !Self::is_valid_public_key_encoding(bytes) {
+            return Err(CryptoError::InvalidPublicKey);
+        }
+
+        let group = Self::get_group()?;
+        let mut ctx = bn::BigNumContext::new()?;
+        EcPoint::from_bytes(&group, bytes, &mut ctx).map_err(|_| CryptoError::InvalidPublicKey)
     }
 
-    pub fn public_key_to_hex(public_key: &EcPoint) -> String {
-        let mut ctx = bn::BigNumContext::new().unwrap();
-        hex::encode(
-            public_key
-                .to_bytes(
-                    &Self::get_group(),
-                    ec::PointConversionForm::UNCOMPRESSED,
-                    &mut ctx,
-                )
-                .unwrap(),
-        )
+    pub fn public_key_to_bytes(public_key: &EcPoint) -> Result<Vec<u8>, CryptoError> {
+        let group = Self::get_group()?;
+        let mut ctx = bn::BigNumContext::new()?;
+        Ok(public_key.to_bytes(&group, ec::PointConversionForm::COMPRESSED, &mut ctx)?)
     }
 
-    pub fn get_group() -> EcGroup {
-        EcGroup::from_curve_name(CURVE).unwrap()
+    pub fn get_group() -> Result<EcGroup, CryptoError> {
+        Ok(EcGroup::from_curve_name(CURVE)?)
     }
 }
 
@@ -63,67 +135,243 @@ pub struct Signature {
 }
 
 impl Signature {
-    pub fn from_string(str: &String) -> Result<Self, Box<dyn Error>> {
-        let signature = EcdsaSig::from_der(hex::decode(str)?.as_slice())?;
+    pub fn from_string(str: &str) -> Result<Self, CryptoError> {
+        Self::from_der(&hex::decode(str)?)
+    }
+
+    pub fn from_der(der: &[u8]) -> Result<Self, CryptoError> {
+        let signature = EcdsaSig::from_der(der).map_err(|_| CryptoError::InvalidSignature)?;
         Ok(Self { signature })
     }
 
-    pub fn verify(&self, data: &[u8], public_key: EcPoint) -> Result<bool, ErrorStack> {
-        let key = EcKey::from_public_key(&KeyPair::get_group(), &public_key)?;
+    pub fn to_der(&self) -> Result<Vec<u8>, CryptoError> {
+        Ok(self.signature.to_der()?)
+    }
+
+    pub fn verify(&self, data: &[u8], public_key: &EcPoint) -> Result<bool, CryptoError> {
+        let group = KeyPair::get_group()?;
+        let key = EcKey::from_public_key(&group, public_key)
+            .map_err(|_| CryptoError::InvalidPublicKey)?;
 
-        self.signature.verify(data, &key)
+        Ok(self.signature.verify(data, &key)?)
     }
 
-    pub fn from_sign(data: &[u8], private_key: &PrivateKey) -> Result<Self, ErrorStack> {
+    pub fn from_sign(data: &[u8], private_key: &PrivateKey) -> Result<Self, CryptoError> {
         let sig = EcdsaSig::sign(data, &private_key.key)?;
 
         Ok(Self { signature: sig })
     }
 
-    pub fn to_encoded(&self) -> String {
-        hex::encode(self.signature.to_der().unwrap())
+    pub fn to_encoded(&self) -> Result<String, CryptoError> {
+        Ok(hex::encode(self.to_der()?))
+    }
+}
+
+/// BIP-340 Schnorr key over secp256k1, its signatures are smaller than the DER encoded ECDSA ones
+/// and can't be changed by a third party without invalidating them
+pub struct SchnorrKey {
+    key: k256::schnorr::SigningKey,
+}
+
+impl SchnorrKey {
+    /// the x-only public key
+    pub fn public_key(&self) -> Vec<u8> {
+        self.key.verifying_key().to_bytes().to_vec()
+    }
+
+    /// sign a 32 byte digest
+    pub fn sign(&self, digest: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        let mut aux_rand = [0; 32];
+        openssl::rand::rand_bytes(&mut aux_rand)?;
+
+        let signature = self
+            .key
+            .sign_raw(digest, &aux_rand)
+            .map_err(|_| CryptoError::InvalidPrivateKey)?;
+
+        Ok(signature.to_bytes().to_vec())
     }
 }
 
+pub fn is_valid_schnorr_public_key(public_key: &[u8]) -> bool {
+    k256::schnorr::VerifyingKey::from_bytes(public_key).is_ok()
+}
+
+/// verify a BIP-340 signature of a 32 byte digest
+pub fn schnorr_verify(
+    digest: &[u8],
+    signature: &[u8],
+    public_key: &[u8],
+) -> Result<bool, CryptoError> {
+    let public_key = k256::schnorr::VerifyingKey::from_bytes(public_key)
+        .map_err(|_| CryptoError::InvalidPublicKey)?;
+    let signature =
+        k256::schnorr::Signature::try_from(signature).map_err(|_| CryptoError::InvalidSignature)?;
+
+    Ok(public_key.verify_raw(digest, &signature).is_ok())
+}
+
+/// a Schnorr signature of a digest waiting for batch verification
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchnorrCheck {
+    pub digest: Vec<u8>,
+    pub signature: Vec<u8>,
+    pub public_key: Vec<u8>,
+}
+
+/// verify all the signatures at once, faster than one by one but only tells if all of them are valid
+/// checks s_i * G = R_i + e_i * P_i for all i with random weights a_i:
+/// sum(a_i * R_i) + sum(a_i * e_i * P_i) - sum(a_i * s_i) * G = 0
+pub fn schnorr_batch_verify(checks: &[SchnorrCheck]) -> Result<bool, CryptoError> {
+    use k256::elliptic_curve::{
+        bigint::U256, group::Group, ops::LinearCombinationExt, ops::Reduce, point::DecompactPoint,
+        PrimeField,
+    };
+    use k256::{AffinePoint, FieldBytes, ProjectivePoint, Scalar};
+
+    let mut terms = Vec::with_capacity(2 * checks.len() + 1);
+    let mut s_sum = Scalar::ZERO;
+
+    for (i, check) in checks.iter().enumerate() {
+        if check.public_key.len() != SCHNORR_PUBLIC_KEY_SIZE {
+            return Err(CryptoError::InvalidPublicKey);
+        }
+        if check.signature.len() != SCHNORR_SIGNATURE_SIZE {
+            return Err(CryptoError::InvalidSignature);
+        }
+
+        let public_key: Option<AffinePoint> =
+            AffinePoint::decompact(FieldBytes::from_slice(&check.public_key)).into();
+        let public_key = public_key.ok_or(CryptoError::InvalidPublicKey)?;
+
+        let (r_bytes, s_bytes) = check.signature.split_at(SCHNORR_PUBLIC_KEY_SIZE);
+        let r: Option<AffinePoint> = AffinePoint::decompact(FieldBytes::from_slice(r_bytes)).into();
+        let s: Option<Scalar> = Scalar::from_repr(*FieldBytes::from_slice(s_bytes)).into();
+        let (r, s) = match (r, s) {
+            (Some(r), Some(s)) => (r, s),
+            _ => return Ok(false),
+        };
+
+        let tag = sha256(SCHNORR_CHALLENGE_TAG);
+        let e = sha256(&[&tag, &tag, r_bytes, &check.public_key, &check.digest].concat());
+        let e = <Scalar as Reduce<U256>>::reduce_bytes(FieldBytes::from_slice(&e));
+
+        // the first weight can be 1, the others have to be unpredictable
+        let a = if i == 0 {
+            Scalar::ONE
+        } else {
+            let mut random = [0; 32];
+            openssl::rand::rand_bytes(&mut random)?;
+            <Scalar as Reduce<U256>>::reduce_bytes(FieldBytes::from_slice(&random))
+        };
+
+        s_sum += a * s;
+        terms.push((ProjectivePoint::from(r), a));
+        terms.push((ProjectivePoint::from(public_key), a * e));
+    }
+    terms.push((ProjectivePoint::GENERATOR, -s_sum));
+
+    Ok(bool::from(
+        ProjectivePoint::lincomb_ext(terms.as_slice()).is_identity(),
+    ))
+}
+
+/// SHA256 of the data
+pub fn sha256(data: &[u8]) -> Vec<u8> {
+    Sha256::digest(data).to_vec()
+}
+
+/// SHA256(SHA256(tag) || SHA256(tag) || data) like BIP-340, so digests of different kinds of
+/// data can never collide and a signature of one can't be reused as another
+pub fn tagged_hash(tag: &[u8], data: &[u8]) -> Vec<u8> {
+    let tag = sha256(tag);
+    sha256(&[tag.as_slice(), tag.as_slice(), data].concat())
+}
+
+/// RIPEMD160(SHA256(data)), used to shorten public keys
+pub fn hash160(data: &[u8]) -> Vec<u8> {
+    Ripemd160::digest(&sha256(data)).to_vec()
+}
+
+#[derive(Clone)]
 pub struct PrivateKey {
     key: EcKey<Private>,
 }
 
 impl PrivateKey {
-    pub fn to_public_key(&self) -> EcPoint {
-        self.key
-            .public_key()
-            .to_owned(&KeyPair::get_group())
-            .unwrap()
+    pub fn to_public_key(&self) -> Result<EcPoint, CryptoError> {
+        let group = KeyPair::get_group()?;
+        Ok(self.key.public_key().to_owned(&group)?)
     }
 
-    pub fn to_pem(&self) -> Result<Vec<u8>, ErrorStack> {
-        self.key.private_key_to_pem()
+    /// the Schnorr key is derived from the same secret so a wallet only has to store one key
+    pub fn to_schnorr_key(&self) -> Result<SchnorrKey, CryptoError> {
+        let secret = sha256(&[SCHNORR_KEY_TAG, &self.key.private_key().to_vec()].concat());
+        let key = k256::schnorr::SigningKey::from_bytes(&secret)
+            .map_err(|_| CryptoError::InvalidPrivateKey)?;
+
+        Ok(SchnorrKey { key })
     }
 
-    pub fn from_pem(pem: &[u8]) -> Self {
-        Self {
-            key: EcKey::private_key_from_pem(pem).unwrap(),
+    /// the 32 byte big endian private scalar
+    pub fn to_bytes(&self) -> Result<Vec<u8>, CryptoError> {
+        Ok(self
+            .key
+            .private_key()
+            .to_vec_padded(PRIVATE_KEY_SIZE as i32)?)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, CryptoError> {
+        let group = KeyPair::get_group()?;
+        let mut ctx = bn::BigNumContext::new()?;
+        let mut order = bn::BigNum::new()?;
+        group.order(&mut order, &mut ctx)?;
+
+        let private_number = bn::BigNum::from_slice(bytes)?;
+        if bytes.len() != PRIVATE_KEY_SIZE
+            || private_number.num_bits() == 0
+            || private_number >= order
+        {
+            return Err(CryptoError::InvalidPrivateKey);
         }
+
+        let mut public_key = EcPoint::new(&group)?;
+        public_key.mul_generator(&group, &private_number, &ctx)?;
+        let key = EcKey::from_private_components(&group, &private_number, &public_key)?;
+
+        Ok(Self { key })
     }
 
-    pub fn read_file_pem(path: &Path) -> io::Result<Self> {
-        let pem = fs::read_to_string(path)?;
-        Ok(Self::from_pem(pem.as_bytes()))
+    pub fn to_pem(&self) -> Result<Vec<u8>, CryptoError> {
+        Ok(self.key.private_key_to_pem()?)
     }
 
-    pub fn write_file_pem(&self, path: &Path) -> io::Result<()> {
-        let pem = self.to_pem().unwrap();
+    pub fn from_pem(pem: &[u8]) -> Result<Self, CryptoError> {
+        let key = EcKey::private_key_from_pem(pem).map_err(|_| CryptoError::InvalidPrivateKey)?;
 
-        let prefix = path.parent().unwrap();
-        std::fs::create_dir_all(prefix).unwrap();
-        let mut f = File::create(path)?;
-        info!(
-            "writing private key to path: {}",
-            path.as_os_str().to_str().unwrap()
-        );
+        Ok(Self { key })
+    }
+
+    pub fn read_file_pem(path: &Path) -> Result<Self, CryptoError> {
+        let pem = fs::read(path)?;
+        Self::from_pem(&pem)
+    }
 
-        f.write_all(&pem)
+    pub fn write_file_pem(&self, path: &Path) -> Result<(), CryptoError> {
+        let pem = self.to_pem()?;
+
+        if let Some(prefix) = path.parent() {
+            fs::create_dir_all(prefix)?;
+        }
+        let mut f = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(path)?;
+        info!("writing private key to path: {}", path.display());
+
+        Ok(f.write_all(&pem)?)
     }
 }
 
@@ -133,14 +381,122 @@ mod tests {
 
     #[test]
     fn test_public_key_encode_decode() {
-        let pair = KeyPair::generate();
+        let pair = KeyPair::generate().unwrap();
 
-        let encoded_pub_key = KeyPair::public_key_to_hex(&pair.private_key.to_public_key());
+        let encoded_pub_key =
+            KeyPair::public_key_to_hex(&pair.private_key.to_public_key().unwrap()).unwrap();
         let decoded_pub_key = KeyPair::public_key_from_hex(&encoded_pub_key).unwrap();
 
         assert_eq!(
             encoded_pub_key,
-            KeyPair::public_key_to_hex(&decoded_pub_key)
+            KeyPair::public_key_to_hex(&decoded_pub_key).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_public_key_encodings() {
+        let public_key = KeyPair::generate()
+            .unwrap()
+            .private_key
+            .to_public_key()
+            .unwrap();
+        let compressed = KeyPair::public_key_to_bytes(&public_key).unwrap();
+        assert_eq!(compressed.len(), COMPRESSED_PUBLIC_KEY_SIZE);
+        assert!(KeyPair::is_valid_public_key_encoding(&compressed));
+
+        let group = KeyPair::get_group().unwrap();
+        let mut ctx = bn::BigNumContext::new().unwrap();
+        let uncompressed = public_key
+            .to_bytes(&group, ec::PointConversionForm::UNCOMPRESSED, &mut ctx)
+            .unwrap();
+        assert!(KeyPair::is_valid_public_key_encoding(&uncompressed));
+
+        // both forms decode to the same key which is encoded back as compressed
+        let decoded = KeyPair::public_key_from_hex(&hex::encode(&uncompressed)).unwrap();
+        assert_eq!(KeyPair::public_key_to_bytes(&decoded).unwrap(), compressed);
+
+        let hybrid = public_key
+            .to_bytes(&group, ec::PointConversionForm::HYBRID, &mut ctx)
+            .unwrap();
+        assert!(!KeyPair::is_valid_public_key_encoding(&hybrid));
+        assert!(KeyPair::public_key_from_bytes(&hybrid).is_err());
+        assert!(!KeyPair::is_valid_public_key_encoding(&compressed[1..]));
+        assert!(!KeyPair::is_valid_public_key_encoding(
+            &[&[0x04], &compressed[1..]].concat()
+        ));
+    }
+
+    #[test]
+    fn test_schnorr_sign_verify() {
+        let private_key = KeyPair::generate().unwrap().private_key;
+        let key = private_key.to_schnorr_key().unwrap();
+        let public_key = key.public_key();
+        assert_eq!(public_key.len(), SCHNORR_PUBLIC_KEY_SIZE);
+        // the same secret always gives the same key
+        assert_eq!(
+            private_key.to_schnorr_key().unwrap().public_key(),
+            public_key
         );
+
+        let digest = sha256(b"message");
+        let signature = key.sign(&digest).unwrap();
+        assert_eq!(signature.len(), SCHNORR_SIGNATURE_SIZE);
+        assert!(schnorr_verify(&digest, &signature, &public_key).unwrap());
+        assert!(!schnorr_verify(&sha256(b"other"), &signature, &public_key).unwrap());
+
+        let mut changed = signature.clone();
+        changed[63] ^= 1;
+        assert!(!schnorr_verify(&digest, &changed, &public_key).unwrap_or(false));
+        assert!(matches!(
+            schnorr_verify(&digest, &signature[1..], &public_key),
+            Err(CryptoError::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn test_schnorr_batch_verify() {
+        let mut checks: Vec<SchnorrCheck> = (0..5)
+            .map(|i| {
+                let key = KeyPair::generate()
+                    .unwrap()
+                    .private_key
+                    .to_schnorr_key()
+                    .unwrap();
+                let digest = sha256(&[i]);
+                SchnorrCheck {
+                    signature: key.sign(&digest).unwrap(),
+                    digest,
+                    public_key: key.public_key(),
+                }
+            })
+            .collect();
+        assert!(schnorr_batch_verify(&checks).unwrap());
+        assert!(schnorr_batch_verify(&checks[..1]).unwrap());
+        assert!(schnorr_batch_verify(&[]).unwrap());
+
+        // one wrong signature fails the whole batch
+        checks[3].digest = sha256(b"other");
+        assert!(!schnorr_batch_verify(&checks).unwrap());
+    }
+
+    #[test]
+    fn test_malformed_input() {
+        assert!(matches!(
+            KeyPair::public_key_from_hex("not hex"),
+            Err(CryptoError::InvalidHex(_))
+        ));
+        // right encoding but not a point on the curve
+        assert!(matches!(
+            KeyPair::public_key_from_bytes(&[[0x04].as_slice(), &[0x01; 64]].concat()),
+            Err(CryptoError::InvalidPublicKey)
+        ));
+        assert!(matches!(
+            Signature::from_der(&[0x30, 0x01]),
+            Err(CryptoError::InvalidSignature)
+        ));
+        assert!(matches!(
+            PrivateKey::from_pem(b"not a pem"),
+            Err(CryptoError::InvalidPrivateKey)
+        ));
     }
 }
@@ -70,6 +70,8 @@ mod tests {
             difficulty: 0,
             miner_address: String::new(),
             miner_balance: 0,
+            signature: String::new(),
+            evidence: vec![],
         };
         // the blocks are not validated so we can easily make up blocks
         let mut chain = BlockChain {
@@ -100,6 +102,8 @@ mod tests {
             difficulty: 0,
             miner_address: String::new(),
             miner_balance: 0,
+            signature: String::new(),
+            evidence: vec![],
         };
         // the blocks are not validated so we can easily make up blocks
         chain = BlockChain {
@@ -129,6 +133,8 @@ mod tests {
             difficulty: 5,
             miner_address: String::new(),
             miner_balance: 0,
+            signature: String::new(),
+            evidence: vec![],
         };
         // the blocks are not validated so we can easily make up blocks
         chain = BlockChain {
@@ -143,6 +149,8 @@ mod tests {
                     difficulty: 5,
                     miner_address: String::new(),
                     miner_balance: 0,
+                    signature: String::new(),
+                    evidence: vec![],
                 },
                 block.clone(),
                 block.clone(),
@@ -0,0 +1,155 @@
+use log::{info, warn};
+
+use crate::block::Block;
+use crate::chain::BlockChain;
+use crate::slashing::{is_slashed, DoubleSignEvidence, MAX_EVIDENCE_PER_BLOCK};
+
+/// evidence of double signing waiting to be put in a block
+#[derive(Debug, Default)]
+pub struct EvidencePool(Vec<DoubleSignEvidence>);
+
+impl EvidencePool {
+    /// returns false if the evidence is invalid, too old or already known
+    pub fn add(&mut self, evidence: DoubleSignEvidence, chain: &BlockChain) -> bool {
+        if !evidence.is_valid() {
+            warn!("invalid double signing evidence {}", evidence.id());
+            return false;
+        }
+
+        if evidence.is_expired(next_index(chain)) {
+            warn!("double signing evidence {} is too old", evidence.id());
+            return false;
+        }
+
+        if self.contains(&evidence) || is_slashed(chain, evidence.offender(), evidence.index()) {
+            return false;
+        }
+
+        info!(
+            "{} signed two blocks at height {}",
+            evidence.offender(),
+            evidence.index()
+        );
+        self.0.push(evidence);
+        true
+    }
+
+    /// compare blocks received from a peer with the blocks held at the same heights,
+    /// returns the new evidence
+    pub fn detect(&mut self, chain: &BlockChain, blocks: &[Block]) -> Vec<DoubleSignEvidence> {
+        let mut found = vec![];
+
+        for block in blocks {
+            let Some(held) = chain.blocks.get(block.index as usize) else {
+                continue;
+            };
+            if held.hash == block.hash || held.miner_address != block.miner_address {
+                continue;
+            }
+
+            let evidence = DoubleSignEvidence::new(held.header(), block.header());
+            // proof of work blocks aren't signed, they can fork freely
+            if evidence.is_valid() && self.add(evidence.clone(), chain) {
+                found.push(evidence);
+            }
+        }
+
+        found
+    }
+
+    /// evidence that isn't in the chain yet and is recent enough for the next block
+    pub fn pending(&self, chain: &BlockChain) -> Vec<DoubleSignEvidence> {
+        let index = next_index(chain);
+        self.0
+            .iter()
+            .filter(|e| !e.is_expired(index) && !is_slashed(chain, e.offender(), e.index()))
+            .take(MAX_EVIDENCE_PER_BLOCK)
+            .cloned()
+            .collect()
+    }
+
+    pub fn get_all(&self) -> &[DoubleSignEvidence] {
+        &self.0
+    }
+
+    fn contains(&self, evidence: &DoubleSignEvidence) -> bool {
+        self.0
+            .iter()
+            .any(|e| e.offender() == evidence.offender() && e.index() == evidence.index())
+    }
+}
+
+fn next_index(chain: &BlockChain) -> u32 {
+    chain.blocks.len() as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::address::Address;
+    use crate::crypto::KeyPair;
+    use crate::slashing::EVIDENCE_MAX_AGE;
+
+    #[test]
+    fn test_detect() {
+        let key = KeyPair::generate().unwrap().private_key;
+        let address = Address::from_public_key(&key.to_public_key().unwrap()).unwrap();
+        let signed = |hash: &str| {
+            let mut block = BlockChain::get_genesis();
+            block.index = 1;
+            block.hash = hash.to_owned();
+            block.miner_address = address.to_string();
+            block.sign(&key, &address).unwrap();
+            block
+        };
+
+        let mut chain = BlockChain::default();
+        chain.blocks.push(signed("aa"));
+        let mut pool = EvidencePool::default();
+
+        // the block we hold and one that is ahead of the chain don't conflict
+        let mut ahead = signed("cc");
+        ahead.index = 2;
+        assert!(pool.detect(&chain, &[signed("aa"), ahead]).is_empty());
+
+        let found = pool.detect(&chain, &[signed("bb")]);
+        assert_eq!(found.len(), 1);
+        assert!(found[0].is_valid());
+        assert_eq!(pool.pending(&chain), found);
+
+        // reported once, and not again after it is in the chain
+        assert!(pool.detect(&chain, &[signed("bb")]).is_empty());
+        let mut block = signed("dd");
+        block.index = 2;
+        block.evidence = found;
+        chain.blocks.push(block);
+        assert!(pool.pending(&chain).is_empty());
+    }
+
+    #[test]
+    fn test_stale_evidence() {
+        let key = KeyPair::generate().unwrap().private_key;
+        let address = Address::from_public_key(&key.to_public_key().unwrap()).unwrap();
+        let signed = |hash: &str| {
+            let mut block = BlockChain::get_genesis();
+            block.index = 1;
+            block.hash = hash.to_owned();
+            block.miner_address = address.to_string();
+            block.sign(&key, &address).unwrap();
+            block
+        };
+        let evidence = DoubleSignEvidence::new(signed("aa").header(), signed("bb").header());
+
+        let mut chain = BlockChain::default();
+        chain.blocks.push(signed("aa"));
+        let mut pool = EvidencePool::default();
+        assert!(pool.add(evidence.clone(), &chain));
+
+        // once the next block is too far past the offense the evidence is dropped
+        while chain.blocks.len() <= 1 + EVIDENCE_MAX_AGE as usize {
+            chain.blocks.push(BlockChain::get_genesis());
+        }
+        assert!(pool.pending(&chain).is_empty());
+        assert!(!EvidencePool::default().add(evidence, &chain));
+    }
+}
@@ -0,0 +1,129 @@
+use openssl::{
+    bn::{BigNum, BigNumContext},
+    hash::MessageDigest,
+    pkey::PKey,
+    sign::Signer,
+};
+
+use crate::crypto::{CryptoError, KeyPair, PrivateKey, PRIVATE_KEY_SIZE};
+
+/// indexes from here on derive hardened children, which need the parent private key
+pub const HARDENED: u32 = 1 << 31;
+
+/// SLIP-10 master key for the P-256 curve
+const MASTER_KEY_TAG: &[u8] = b"Nist256p1 seed";
+
+/// a private key and chain code from which child keys are derived, SLIP-10 (BIP-32 for other curves)
+#[derive(Clone)]
+pub struct ExtendedKey {
+    private_key: Vec<u8>,
+    chain_code: Vec<u8>,
+}
+
+impl ExtendedKey {
+    pub fn from_seed(seed: &[u8]) -> Result<Self, CryptoError> {
+        let mut data = seed.to_vec();
+        loop {
+            let i = hmac_sha512(MASTER_KEY_TAG, &data)?;
+            let (il, ir) = i.split_at(PRIVATE_KEY_SIZE);
+
+            // an invalid key happens with negligible probability, the spec says to hash again
+            if PrivateKey::from_bytes(il).is_ok() {
+                return Ok(ExtendedKey {
+                    private_key: il.to_vec(),
+                    chain_code: ir.to_vec(),
+                });
+            }
+            data = i;
+        }
+    }
+
+    pub fn derive(&self, index: u32) -> Result<Self, CryptoError> {
+        let mut data = if index >= HARDENED {
+            [&[0], self.private_key.as_slice()].concat()
+        } else {
+            let public_key = self.to_private_key()?.to_public_key()?;
+            KeyPair::public_key_to_bytes(&public_key)?
+        };
+        data.extend(index.to_be_bytes());
+
+        let group = KeyPair::get_group()?;
+        let mut ctx = BigNumContext::new()?;
+        let mut order = BigNum::new()?;
+        group.order(&mut order, &mut ctx)?;
+
+        let parent = BigNum::from_slice(&self.private_key)?;
+
+        loop {
+            let i = hmac_sha512(&self.chain_code, &data)?;
+            let (il, ir) = i.split_at(PRIVATE_KEY_SIZE);
+
+            let tweak = BigNum::from_slice(il)?;
+            let mut child = BigNum::new()?;
+            child.mod_add(&tweak, &parent, &order, &mut ctx)?;
+
+            if tweak < order && child.num_bits() != 0 {
+                return Ok(ExtendedKey {
+                    private_key: child.to_vec_padded(PRIVATE_KEY_SIZE as i32)?,
+                    chain_code: ir.to_vec(),
+                });
+            }
+            data = [&[1], ir, &index.to_be_bytes()].concat();
+        }
+    }
+
+    pub fn derive_path(&self, path: &[u32]) -> Result<Self, CryptoError> {
+        path.iter()
+            .try_fold(self.clone(), |key, &index| key.derive(index))
+    }
+
+    pub fn to_private_key(&self) -> Result<PrivateKey, CryptoError> {
+        PrivateKey::from_bytes(&self.private_key)
+    }
+
+    pub fn chain_code(&self) -> &[u8] {
+        &self.chain_code
+    }
+}
+
+fn hmac_sha512(key: &[u8], data: &[u8]) -> Result<Vec<u8>, CryptoError> {
+    let key = PKey::hmac(key)?;
+    let mut signer = Signer::new(MessageDigest::sha512(), &key)?;
+
+    Ok(signer.sign_oneshot_to_vec(data)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // SLIP-10 test vector 1 for nist256p1
+    #[test]
+    fn test_slip10_vector() {
+        let seed = hex::decode("000102030405060708090a0b0c0d0e0f").unwrap();
+        let master = ExtendedKey::from_seed(&seed).unwrap();
+        assert_eq!(
+            hex::encode(master.chain_code()),
+            "beeb672fe4621673f722f38529c07392fecaa61015c80c34f29ce8b41b3cb6ea"
+        );
+        assert_eq!(
+            hex::encode(master.to_private_key().unwrap().to_bytes().unwrap()),
+            "612091aaa12e22dd2abef664f8a01a82cae99ad7441b7ef8110424915c268bc2"
+        );
+
+        let child = master.derive_path(&[HARDENED, 1]).unwrap();
+        let hardened = master.derive(HARDENED).unwrap();
+        assert_eq!(
+            hex::encode(hardened.to_private_key().unwrap().to_bytes().unwrap()),
+            "6939694369114c67917a182c59ddb8cafc3004e63ca5d3b84403ba8613debc0c"
+        );
+        assert_eq!(
+            hex::encode(child.chain_code()),
+            "4187afff1aafa8445010097fb99d23aee9f599450c7bd140b6826ac22ba21d0c"
+        );
+        assert_eq!(
+            hex::encode(child.to_private_key().unwrap().to_bytes().unwrap()),
+            "284e9d38d07d21e4e281b645089a94f4cf5a5a81369acf151a1c3a57f18b2129"
+        );
+    }
+}
@@ -0,0 +1,389 @@
+use serde::{Deserialize, Serialize};
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use crate::block::Block;
+use crate::keystore::KeystoreError;
+use crate::script::Script;
+use crate::transaction::{Transaction, TxOut};
+use crate::transaction_pool::TransactionPool;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Direction {
+    Incoming,
+    Outgoing,
+    /// every output goes back to the wallet, only the fee is spent
+    SelfTransfer,
+}
+
+/// a transaction that pays to or spends from the wallet
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub tx_id: String,
+    pub direction: Direction,
+    /// sum of the outputs that pay to the wallet
+    pub received: u64,
+    /// sum of the wallet outputs that are spent
+    pub sent: u64,
+    /// only known for transactions that spend from the wallet
+    pub fee: Option<u64>,
+    pub is_coinbase: bool,
+    /// None while the transaction is in the pool
+    pub block_index: Option<u64>,
+    pub timestamp: Option<u64>,
+    /// number of blocks from the one containing the transaction to the tip, 0 while pending
+    pub confirmations: u64,
+    pub label: Option<String>,
+}
+
+impl HistoryEntry {
+    pub fn is_pending(&self) -> bool {
+        self.block_index.is_none()
+    }
+}
+
+/// walk the chain and the pool and collect the transactions that touch my_scripts, newest first
+pub fn wallet_history(
+    blocks: &[Block],
+    pool: &TransactionPool,
+    my_scripts: &HashSet<Script>,
+    labels: &Labels,
+) -> Vec<HistoryEntry> {
+    let mut index = HistoryIndex::default();
+    index.update(blocks, my_scripts);
+    let (_, history) = index.page(pool, labels, 0, usize::MAX);
+
+    history
+}
+
+/// the transactions of the wallet in the chain, extended with the blocks added since the last
+/// update so a page of the history doesn't walk the whole chain again
+#[derive(Debug, Default)]
+pub struct HistoryIndex {
+    /// the index is built again when the wallet has other scripts
+    my_scripts: HashSet<Script>,
+    /// (index, hash) of the last indexed block, a chain that doesn't contain it is indexed again
+    tip: Option<(u64, String)>,
+    /// the unspent outputs of the indexed blocks, so the value of an input can be looked up
+    tx_outs: HashMap<(String, u64), TxOut>,
+    /// oldest first, without confirmations and labels because they change without new
+    /// transactions
+    confirmed: Vec<HistoryEntry>,
+}
+
+impl HistoryIndex {
+    pub fn update(&mut self, blocks: &[Block], my_scripts: &HashSet<Script>) {
+        let extends = self.tip.as_ref().is_some_and(|(index, hash)| {
+            blocks
+                .get(*index as usize)
+                .is_some_and(|block| block.hash == *hash)
+        });
+        if !extends || self.my_scripts != *my_scripts {
+            *self = HistoryIndex {
+                my_scripts: my_scripts.clone(),
+                ..HistoryIndex::default()
+            };
+        }
+
+        let start = self
+            .tip
+            .as_ref()
+            .map_or(0, |(index, _)| *index as usize + 1);
+        for block in blocks.iter().skip(start) {
+            let confirmed = Some((block.index as u64, block.timestamp));
+            for tx in block.data.iter() {
+                let spent: Vec<Option<TxOut>> = tx
+                    .tx_ins
+                    .iter()
+                    .map(|tx_in| {
+                        self.tx_outs
+                            .remove(&(tx_in.tx_out_id.clone(), tx_in.tx_out_index))
+                    })
+                    .collect();
+                add_tx_outs(&mut self.tx_outs, tx);
+
+                if let Some(entry) = history_entry(tx, &spent, confirmed, &self.my_scripts) {
+                    self.confirmed.push(entry);
+                }
+            }
+        }
+        if let Some(block) = blocks.last() {
+            self.tip = Some((block.index as u64, block.hash.clone()));
+        }
+    }
+
+    /// the number of entries and the entries from offset to offset + limit, newest first.
+    /// the pool is the newest
+    pub fn page(
+        &self,
+        pool: &TransactionPool,
+        labels: &Labels,
+        offset: usize,
+        limit: usize,
+    ) -> (usize, Vec<HistoryEntry>) {
+        // outputs of pool transactions can be spent by later ones
+        let mut pool_tx_outs = HashMap::new();
+        let mut pending = vec![];
+        for tx in pool.0.iter() {
+            let spent: Vec<Option<TxOut>> = tx
+                .tx_ins
+                .iter()
+                .map(|tx_in| {
+                    let out_point = (tx_in.tx_out_id.clone(), tx_in.tx_out_index);
+                    pool_tx_outs
+                        .get(&out_point)
+                        .or_else(|| self.tx_outs.get(&out_point))
+                        .cloned()
+                })
+                .collect();
+            add_tx_outs(&mut pool_tx_outs, tx);
+
+            if let Some(entry) = history_entry(tx, &spent, None, &self.my_scripts) {
+                pending.push(entry);
+            }
+        }
+
+        let total = pending.len() + self.confirmed.len();
+        let tip = self.tip.as_ref().map_or(0, |(index, _)| *index);
+        let entries = pending
+            .into_iter()
+            .rev()
+            .chain(self.confirmed.iter().rev().cloned().map(|mut entry| {
+                entry.confirmations = entry.block_index.map_or(0, |index| tip + 1 - index);
+                entry
+            }))
+            .skip(offset)
+            .take(limit)
+            .map(|mut entry| {
+                entry.label = labels.transactions.get(&entry.tx_id).cloned();
+                entry
+            })
+            .collect();
+
+        (total, entries)
+    }
+}
+
+fn add_tx_outs(tx_outs: &mut HashMap<(String, u64), TxOut>, tx: &Transaction) {
+    for (i, tx_out) in tx.tx_outs.iter().enumerate() {
+        tx_outs.insert((tx.id.clone(), i as u64), tx_out.clone());
+    }
+}
+
+/// None if tx doesn't touch my_scripts. spent are the outputs spent by the inputs of tx, None
+/// where they aren't known
+fn history_entry(
+    tx: &Transaction,
+    spent: &[Option<TxOut>],
+    block: Option<(u64, u64)>,
+    my_scripts: &HashSet<Script>,
+) -> Option<HistoryEntry> {
+    let is_coinbase = block.is_some_and(|_| is_coinbase(tx));
+    let mut sent = 0;
+    let mut total_in = Some(0u64);
+    if !is_coinbase {
+        for spent in spent.iter() {
+            if let Some(tx_out) = spent
+                .as_ref()
+                .filter(|t| my_scripts.contains(&t.script_pubkey))
+            {
+                sent += tx_out.amount;
+            }
+            total_in = total_in
+                .zip(spent.as_ref())
+                .map(|(total, tx_out)| total + tx_out.amount);
+        }
+    }
+
+    let mut received = 0;
+    let mut all_mine = true;
+    for tx_out in tx.tx_outs.iter() {
+        if my_scripts.contains(&tx_out.script_pubkey) {
+            received += tx_out.amount;
+        } else {
+            all_mine = false;
+        }
+    }
+
+    if sent == 0 && received == 0 {
+        return None;
+    }
+
+    let direction = if sent == 0 {
+        Direction::Incoming
+    } else if all_mine {
+        Direction::SelfTransfer
+    } else {
+        Direction::Outgoing
+    };
+    let total_out: u64 = tx.tx_outs.iter().map(|tx_out| tx_out.amount).sum();
+
+    Some(HistoryEntry {
+        tx_id: tx.id.clone(),
+        direction,
+        received,
+        sent,
+        fee: total_in
+            .filter(|_| sent > 0)
+            .and_then(|total_in| total_in.checked_sub(total_out)),
+        is_coinbase,
+        block_index: block.map(|(index, _)| index),
+        timestamp: block.map(|(_, timestamp)| timestamp),
+        confirmations: 0,
+        label: None,
+    })
+}
+
+/// the coinbase is the first transaction of a block and doesn't spend anything
+fn is_coinbase(tx: &Transaction) -> bool {
+    tx.tx_ins.len() == 1 && tx.tx_ins[0].tx_out_id.is_empty()
+}
+
+/// notes for addresses and transactions, they aren't secret so they are kept next to the keystore
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Labels {
+    #[serde(default)]
+    pub addresses: BTreeMap<String, String>,
+    #[serde(default)]
+    pub transactions: BTreeMap<String, String>,
+}
+
+impl Labels {
+    /// no file means no labels yet
+    pub fn read_file(path: &Path) -> Result<Self, KeystoreError> {
+        if !path.exists() {
+            return Ok(Labels::default());
+        }
+
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn write_file(&self, path: &Path) -> Result<(), KeystoreError> {
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+        fs::rename(&tmp, path)?;
+
+        Ok(())
+    }
+
+    /// an empty label removes it
+    pub fn set(labels: &mut BTreeMap<String, String>, key: String, label: String) {
+        if label.is_empty() {
+            labels.remove(&key);
+        } else {
+            labels.insert(key, label);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::BlockChain;
+    use crate::transaction::TxIn;
+
+    fn block(index: u32, data: Vec<Transaction>) -> Block {
+        Block {
+            index,
+            timestamp: 1000 + index as u64,
+            data,
+            ..BlockChain::get_genesis()
+        }
+    }
+
+    fn tx(id: &str, spends: &[(&str, u64)], tx_outs: &[(&Script, u64)]) -> Transaction {
+        Transaction {
+            id: id.to_string(),
+            tx_ins: spends
+                .iter()
+                .map(|(tx_out_id, tx_out_index)| TxIn {
+                    tx_out_id: tx_out_id.to_string(),
+                    tx_out_index: *tx_out_index,
+                    script_sig: Script::new(),
+                    sequence: 0,
+                })
+                .collect(),
+            tx_outs: tx_outs
+                .iter()
+                .map(|(script_pubkey, amount)| TxOut {
+                    script_pubkey: (*script_pubkey).clone(),
+                    amount: *amount,
+                })
+                .collect(),
+            lock_time: 0,
+        }
+    }
+
+    #[test]
+    fn test_wallet_history() {
+        let mine = Script::pay_to_public_key_hash(&[1; 20]);
+        let change = Script::pay_to_public_key_hash(&[2; 20]);
+        let other = Script::pay_to_public_key_hash(&[3; 20]);
+        let my_scripts = HashSet::from([mine.clone(), change.clone()]);
+
+        let blocks = vec![
+            BlockChain::get_genesis(),
+            block(1, vec![tx("coinbase1", &[("", 1)], &[(&mine, 50)])]),
+            block(2, vec![tx("coinbase2", &[("", 2)], &[(&other, 50)])]),
+            block(
+                3,
+                vec![
+                    tx("coinbase3", &[("", 3)], &[(&other, 51)]),
+                    tx("pay", &[("coinbase1", 0)], &[(&other, 30), (&change, 19)]),
+                ],
+            ),
+        ];
+        let pool = TransactionPool(vec![
+            tx("incoming", &[("coinbase2", 0)], &[(&mine, 50)]),
+            tx("consolidate", &[("pay", 1)], &[(&mine, 19)]),
+        ]);
+        let mut labels = Labels::default();
+        Labels::set(&mut labels.transactions, "pay".into(), "rent".into());
+
+        let history = wallet_history(&blocks, &pool, &my_scripts, &labels);
+        let ids: Vec<&str> = history.iter().map(|entry| entry.tx_id.as_str()).collect();
+        assert_eq!(ids, ["consolidate", "incoming", "pay", "coinbase1"]);
+
+        assert_eq!(history[0].direction, Direction::SelfTransfer);
+        assert_eq!(history[0].fee, Some(0));
+        assert!(history[0].is_pending() && history[0].confirmations == 0);
+
+        assert_eq!(history[1].direction, Direction::Incoming);
+        assert_eq!(history[1].fee, None);
+
+        assert_eq!(history[2].direction, Direction::Outgoing);
+        assert_eq!((history[2].sent, history[2].received), (50, 19));
+        assert_eq!(history[2].fee, Some(1));
+        assert_eq!(history[2].confirmations, 1);
+        assert_eq!(history[2].label.as_deref(), Some("rent"));
+
+        assert!(history[3].is_coinbase);
+        assert_eq!(history[3].confirmations, 3);
+        assert_eq!(history[3].block_index, Some(1));
+
+        // the index only scans the new blocks and pages the same history
+        let mut index = HistoryIndex::default();
+        index.update(&blocks[..2], &my_scripts);
+        index.update(&blocks, &my_scripts);
+        assert_eq!(
+            index.page(&pool, &labels, 0, usize::MAX),
+            (4, history.clone())
+        );
+        assert_eq!(
+            index.page(&pool, &labels, 1, 2),
+            (4, history[1..3].to_vec())
+        );
+
+        // a chain that replaces the indexed blocks is indexed again
+        let mut fork = blocks[..3].to_vec();
+        fork[2].hash = String::from("fork");
+        index.update(&fork, &my_scripts);
+        let (total, entries) = index.page(&TransactionPool::default(), &labels, 0, usize::MAX);
+        assert_eq!(total, 1);
+        assert_eq!(entries[0].tx_id, "coinbase1");
+        assert_eq!(entries[0].confirmations, 2);
+    }
+}
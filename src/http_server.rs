@@ -1,16 +1,33 @@
+use log::error;
 use serde::{Deserialize, Serialize};
 use std::io::Read;
 use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
+use naivecoin_rs::address::Address;
 use naivecoin_rs::block::Block;
-use naivecoin_rs::crypto::KeyPair;
+use naivecoin_rs::chain::BlockChain;
+use naivecoin_rs::coin_selection::{CoinControl, CoinSelection, OutPoint};
+use naivecoin_rs::crypto::{KeyPair, PrivateKey};
+use naivecoin_rs::history::HistoryEntry;
+use naivecoin_rs::keystore::KeystoreError;
 use naivecoin_rs::message::{Message, MessageType};
 use naivecoin_rs::p2p;
+use naivecoin_rs::psbt::{PartiallySignedTransaction, PsbtError};
+use naivecoin_rs::script::Script;
+use naivecoin_rs::signed_message;
+use naivecoin_rs::transaction::{parse_sighash, Transaction, TxOut, UnspentTxOut, SIGHASH_ALL};
+use naivecoin_rs::validator::poa::Vote;
 use naivecoin_rs::validator::Validator;
 use naivecoin_rs::wallet::Wallet;
 
 use crate::App;
 
+/// index of the block that will be mined next
+fn next_index(chain: &BlockChain) -> u64 {
+    chain.get_latest().unwrap().index as u64 + 1
+}
+
 fn blocks<V: Validator>(app: &App<V>) -> rouille::Response {
     rouille::Response::json(&app.block_chain.read().unwrap().blocks)
 }
@@ -34,18 +51,30 @@ fn connect_to_peer<V: Validator + Send + Sync>(peer: String, _app: &App<V>) -> r
 fn mine_raw_block<V: Validator + Send + Sync>(body: String, app: &App<V>) -> rouille::Response {
     let mut chain = app.block_chain.write().unwrap();
 
-    let next_block = Block::generate_next_raw(
+    let evidence = app.evidence_pool.read().unwrap().pending(&chain);
+    let Some(next_block) = Block::generate_next_raw(
         serde_json::from_str(&body).unwrap(),
+        evidence,
         &chain,
         &*app.validator.read().unwrap(),
-    );
+    ) else {
+        return no_block();
+    };
 
+    let mut pool = app.transaction_pool.write().unwrap();
+    let mut unspent_tx_outs = app.unspent_tx_outs.write().unwrap();
     chain.add(
         next_block,
-        &mut app.transaction_pool.write().unwrap(),
-        &mut app.unspent_tx_outs.write().unwrap(),
+        &mut pool,
+        &mut unspent_tx_outs,
         &*app.validator.read().unwrap(),
     );
+    app.orphan_pool
+        .write()
+        .unwrap()
+        .process(&mut pool, &unspent_tx_outs, next_index(&chain));
+    drop(pool);
+    drop(unspent_tx_outs);
 
     let msg = Message {
         m_type: MessageType::ResponseBlockchain,
@@ -58,26 +87,170 @@ fn mine_raw_block<V: Validator + Send + Sync>(body: String, app: &App<V>) -> rou
 }
 
 #[derive(Deserialize, Serialize)]
-struct TxData {
-    address: String,
+struct Recipient {
+    #[serde(default)]
+    address: Option<String>,
+    /// locking script to pay to instead of the address
+    #[serde(default)]
+    script: Option<Script>,
     amount: u64,
 }
 
+impl Recipient {
+    fn receiver_script(&self) -> Result<Script, String> {
+        if let Some(script) = &self.script {
+            return Ok(script.clone());
+        }
+
+        match &self.address {
+            Some(address) => address
+                .parse::<Address>()
+                .map(|address| address.script_pubkey())
+                .map_err(|e| format!("invalid address {}: {}", address, e)),
+            None => Err(String::from("missing address or script")),
+        }
+    }
+
+    fn tx_out(&self) -> Result<TxOut, String> {
+        Ok(TxOut {
+            script_pubkey: self.receiver_script()?,
+            amount: self.amount,
+        })
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct TxOptions {
+    #[serde(default)]
+    lock_time: u64,
+    /// largestFirst, branchAndBound, randomImprove or privacy
+    #[serde(default)]
+    coin_selection: CoinSelection,
+    /// in coins per 1000 bytes
+    #[serde(default)]
+    fee_rate: u64,
+    /// spend exactly these outputs
+    #[serde(default)]
+    inputs: Option<Vec<OutPoint>>,
+}
+
+impl TxOptions {
+    fn coin_control(&self) -> CoinControl {
+        CoinControl {
+            strategy: self.coin_selection,
+            fee_rate: self.fee_rate,
+            inputs: self.inputs.clone(),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct TxData {
+    #[serde(flatten)]
+    recipient: Recipient,
+    #[serde(flatten)]
+    options: TxOptions,
+}
+
+/// pays every recipient in one transaction
+#[derive(Deserialize, Serialize)]
+struct PaymentData {
+    recipients: Vec<Recipient>,
+    #[serde(flatten)]
+    options: TxOptions,
+}
+
+impl PaymentData {
+    fn tx_outs(&self) -> Result<Vec<TxOut>, String> {
+        if self.recipients.is_empty() {
+            return Err(String::from("no recipients"));
+        }
+
+        self.recipients.iter().map(Recipient::tx_out).collect()
+    }
+}
+
+fn invalid_receiver(e: String) -> rouille::Response {
+    rouille::Response::text(e).with_status_code(400)
+}
+
+fn wallet_locked() -> rouille::Response {
+    rouille::Response::text("wallet is locked").with_status_code(403)
+}
+
+/// a staker has to unlock the wallet to sign and have an output that is old enough to stake
+fn no_block() -> rouille::Response {
+    rouille::Response::text("could not produce a block, unlock the wallet and check the stake")
+        .with_status_code(403)
+}
+
+fn keystore_error(e: KeystoreError) -> rouille::Response {
+    match e {
+        KeystoreError::KeyPoolEmpty | KeystoreError::Locked | KeystoreError::WatchOnly => {
+            rouille::Response::text(e.to_string()).with_status_code(403)
+        }
+        KeystoreError::MissingMnemonic
+        | KeystoreError::UnknownAddress
+        | KeystoreError::NoAddress => rouille::Response::text(e.to_string()).with_status_code(404),
+        e => rouille::Response::text(e.to_string()).with_status_code(500),
+    }
+}
+
+/// a watch-only wallet can't sign blocks, so it isn't even tried
+fn mining_wallet(wallet: &Wallet) -> Result<(), rouille::Response> {
+    match wallet.is_watch_only() {
+        Ok(true) => Err(keystore_error(KeystoreError::WatchOnly)),
+        Ok(false) => Ok(()),
+        Err(e) => Err(keystore_error(e)),
+    }
+}
+
+/// the change keys are only marked used once a transaction paying to them is sent, the
+/// transaction is already accepted when this fails so it is only logged
+fn use_change_keys(wallet: &Wallet, txs: &[Transaction]) {
+    if let Err(e) = wallet.use_change_keys(txs) {
+        error!("could not mark the change keys as used: {}", e);
+    }
+}
+
+/// the keys of the wallet and a fresh script for the change
+fn spending_keys(wallet: &Wallet) -> Result<(Vec<PrivateKey>, Script), rouille::Response> {
+    if wallet.is_watch_only().map_err(keystore_error)? {
+        return Err(keystore_error(KeystoreError::WatchOnly));
+    }
+    let private_keys = wallet.get_private_keys().ok_or_else(wallet_locked)?;
+    let change_address = wallet.get_change_address().map_err(keystore_error)?;
+
+    Ok((private_keys, change_address.script_pubkey()))
+}
+
 fn mine_transaction<V: Validator + Send + Sync>(body: String, app: &App<V>) -> rouille::Response {
     let data: TxData = serde_json::from_str(&body).unwrap();
+    let tx_out = match data.recipient.tx_out() {
+        Ok(tx_out) => tx_out,
+        Err(e) => return invalid_receiver(e),
+    };
 
     let mut chain = app.block_chain.write().unwrap();
     let wallet = app.wallet.read().unwrap();
+    if let Err(response) = mining_wallet(&wallet) {
+        return response;
+    }
+    if wallet.is_locked() {
+        return wallet_locked();
+    }
     let mut pool = app.transaction_pool.write().unwrap();
+    let evidence = app.evidence_pool.read().unwrap().pending(&chain);
     let u_tx_outs = app.unspent_tx_outs.write().unwrap();
 
     Block::generate_next_with_transaction(
-        data.address,
-        data.amount,
+        vec![tx_out],
         &chain,
         &wallet,
         &pool,
+        evidence,
         u_tx_outs,
+        &data.options.coin_control(),
         &*app.validator.read().unwrap(),
     )
     .map_or_else(
@@ -90,6 +263,13 @@ fn mine_transaction<V: Validator + Send + Sync>(body: String, app: &App<V>) -> r
                 &mut u_tx_outs,
                 &*app.validator.read().unwrap(),
             );
+            if chain.get_latest().unwrap().hash == next_block.hash {
+                use_change_keys(&wallet, &next_block.data);
+            }
+            app.orphan_pool
+                .write()
+                .unwrap()
+                .process(&mut pool, &u_tx_outs, next_index(&chain));
 
             let msg = Message {
                 m_type: MessageType::ResponseBlockchain,
@@ -106,21 +286,55 @@ fn mine_transaction<V: Validator + Send + Sync>(body: String, app: &App<V>) -> r
 }
 
 fn send_transaction<V: Validator + Send + Sync>(body: String, app: &App<V>) -> rouille::Response {
+    let data: TxData = serde_json::from_str(&body).expect("error parsing body");
+    match data.recipient.tx_out() {
+        Ok(tx_out) => send(vec![tx_out], &data.options, app),
+        Err(e) => invalid_receiver(e),
+    }
+}
+
+fn send_many<V: Validator + Send + Sync>(body: String, app: &App<V>) -> rouille::Response {
+    let data: PaymentData = serde_json::from_str(&body).expect("error parsing body");
+    match data.tx_outs() {
+        Ok(tx_outs) => send(tx_outs, &data.options, app),
+        Err(e) => invalid_receiver(e),
+    }
+}
+
+/// pay the tx_outs from the wallet and add the transaction to the pool
+fn send<V: Validator + Send + Sync>(
+    tx_outs: Vec<TxOut>,
+    options: &TxOptions,
+    app: &App<V>,
+) -> rouille::Response {
     let (tx, msg) = {
-        let private_key = app.wallet.read().unwrap().get_private_key();
+        let chain = app.block_chain.read().unwrap();
+        let (private_keys, change_script) = match spending_keys(&app.wallet.read().unwrap()) {
+            Ok(keys) => keys,
+            Err(response) => return response,
+        };
         let mut pool = app.transaction_pool.write().unwrap();
         let u_tx_outs = app.unspent_tx_outs.read().unwrap();
 
-        let data: TxData = serde_json::from_str(&body).expect("error parsing body");
-        let tx =
-            Wallet::create_transaction(data.address, data.amount, &private_key, &u_tx_outs, &pool)
-                .unwrap();
+        let Some(tx) = Wallet::create_transaction(
+            tx_outs,
+            &private_keys,
+            change_script,
+            &u_tx_outs,
+            &pool,
+            options.lock_time,
+            next_index(&chain),
+            &options.coin_control(),
+        ) else {
+            return rouille::Response::text("could not create transaction").with_status_code(500);
+        };
 
-        let ok = pool.add(tx.clone(), &u_tx_outs);
+        let ok = pool.add(tx.clone(), &u_tx_outs, next_index(&chain));
 
         if !ok {
             return rouille::Response::text("could not send transaction").with_status_code(500);
         }
+        use_change_keys(&app.wallet.read().unwrap(), std::slice::from_ref(&tx));
 
         (
             tx,
@@ -135,42 +349,676 @@ fn send_transaction<V: Validator + Send + Sync>(body: String, app: &App<V>) -> r
     rouille::Response::json(&tx)
 }
 
+/// create and sign a transaction without sending it, useful for transactions with a lock_time
+fn create_transaction<V: Validator>(body: String, app: &App<V>) -> rouille::Response {
+    let chain = app.block_chain.read().unwrap();
+    let (private_keys, change_script) = match spending_keys(&app.wallet.read().unwrap()) {
+        Ok(keys) => keys,
+        Err(response) => return response,
+    };
+    let pool = app.transaction_pool.read().unwrap();
+    let u_tx_outs = app.unspent_tx_outs.read().unwrap();
+
+    let data: TxData = serde_json::from_str(&body).expect("error parsing body");
+    let tx_out = match data.recipient.tx_out() {
+        Ok(tx_out) => tx_out,
+        Err(e) => return invalid_receiver(e),
+    };
+    Wallet::create_transaction(
+        vec![tx_out],
+        &private_keys,
+        change_script,
+        &u_tx_outs,
+        &pool,
+        data.options.lock_time,
+        next_index(&chain),
+        &data.options.coin_control(),
+    )
+    .map_or_else(
+        || rouille::Response::text("could not create transaction").with_status_code(500),
+        |tx| rouille::Response::json(&tx),
+    )
+}
+
+/// add an already signed transaction to the pool
+fn broadcast_transaction<V: Validator + Send + Sync>(
+    body: String,
+    app: &App<V>,
+) -> rouille::Response {
+    let tx: Transaction = match serde_json::from_str(&body) {
+        Ok(tx) => tx,
+        Err(_) => return rouille::Response::text("invalid transaction").with_status_code(400),
+    };
+
+    let msg = {
+        let chain = app.block_chain.read().unwrap();
+        let mut pool = app.transaction_pool.write().unwrap();
+        let u_tx_outs = app.unspent_tx_outs.read().unwrap();
+
+        if !pool.add(tx.clone(), &u_tx_outs, next_index(&chain)) {
+            return rouille::Response::text("could not send transaction").with_status_code(500);
+        }
+        use_change_keys(&app.wallet.read().unwrap(), std::slice::from_ref(&tx));
+
+        Message {
+            m_type: MessageType::ResponseTransactionPool,
+            content: serde_json::to_string(&*pool).unwrap(),
+        }
+    };
+    msg.broadcast::<V>();
+
+    rouille::Response::json(&tx)
+}
+
+/// vote to add or remove an authority with the wallet's address, only counts under proof of
+/// authority
+fn vote<V: Validator + Send + Sync>(body: String, app: &App<V>) -> rouille::Response {
+    let vote: Vote = match serde_json::from_str(&body) {
+        Ok(vote) => vote,
+        Err(_) => return rouille::Response::text("invalid vote").with_status_code(400),
+    };
+
+    let (tx, msg) = {
+        let chain = app.block_chain.read().unwrap();
+        let wallet = app.wallet.read().unwrap();
+        let voter = match wallet.get_address() {
+            Ok(voter) => voter,
+            Err(e) => return keystore_error(e),
+        };
+        let private_key = match wallet.get_private_key(&voter) {
+            Ok(private_key) => private_key,
+            Err(e) => return keystore_error(e),
+        };
+        let mut pool = app.transaction_pool.write().unwrap();
+        let u_tx_outs = app.unspent_tx_outs.read().unwrap();
+
+        let Some(tx) =
+            vote.create_transaction(&voter, &private_key, &u_tx_outs, &pool, next_index(&chain))
+        else {
+            return rouille::Response::text("could not create the vote").with_status_code(500);
+        };
+        if !pool.add(tx.clone(), &u_tx_outs, next_index(&chain)) {
+            return rouille::Response::text("could not send the vote").with_status_code(500);
+        }
+
+        let msg = Message {
+            m_type: MessageType::ResponseTransactionPool,
+            content: serde_json::to_string(&*pool).unwrap(),
+        };
+        (tx, msg)
+    };
+    msg.broadcast::<V>();
+
+    rouille::Response::json(&tx)
+}
+
+#[derive(Deserialize, Serialize)]
+struct MultisigData {
+    required: usize,
+    public_keys: Vec<String>,
+}
+
+fn create_multisig(body: String) -> rouille::Response {
+    let data: MultisigData = serde_json::from_str(&body).expect("error parsing body");
+
+    Wallet::create_multisig_script(data.required, &data.public_keys).map_or_else(
+        || rouille::Response::text("invalid multisig").with_status_code(400),
+        |script| rouille::Response::text(script.to_hex()),
+    )
+}
+
+#[derive(Deserialize, Serialize)]
+struct UnsignedTxData {
+    /// where the change goes, a fresh change address of the wallet if it isn't given
+    #[serde(default)]
+    change_address: Option<String>,
+    #[serde(flatten)]
+    tx: TxData,
+}
+
+/// create a transaction that spends from any address of the wallet, including the watched ones,
+/// without signing it. a watch-only node uses this to prepare transactions for offline signing,
+/// they are returned in a container with the spent outputs
+fn create_unsigned_transaction<V: Validator>(body: String, app: &App<V>) -> rouille::Response {
+    let data: UnsignedTxData = serde_json::from_str(&body).expect("error parsing body");
+    let tx_out = match data.tx.recipient.tx_out() {
+        Ok(tx_out) => tx_out,
+        Err(e) => return invalid_receiver(e),
+    };
+
+    let wallet = app.wallet.read().unwrap();
+    let change_address = match &data.change_address {
+        Some(address) => match address.parse::<Address>() {
+            Ok(address) => address,
+            Err(e) => return invalid_receiver(format!("invalid address {}: {}", address, e)),
+        },
+        None => match wallet
+            .is_watch_only()
+            .and_then(|watch_only| match watch_only {
+                true => wallet.get_address(),
+                false => wallet.get_change_address(),
+            }) {
+            Ok(address) => address,
+            Err(e) => return keystore_error(e),
+        },
+    };
+    let from_scripts: Vec<Script> = match wallet.get_scripts() {
+        Ok(scripts) => scripts.into_iter().collect(),
+        Err(e) => return keystore_error(e),
+    };
+
+    let chain = app.block_chain.read().unwrap();
+    let pool = app.transaction_pool.read().unwrap();
+    let u_tx_outs = app.unspent_tx_outs.read().unwrap();
+    Wallet::create_unsigned_transaction(
+        vec![tx_out],
+        &from_scripts,
+        change_address.script_pubkey(),
+        &u_tx_outs,
+        &pool,
+        data.tx.options.lock_time,
+        next_index(&chain),
+        &data.tx.options.coin_control(),
+    )
+    .and_then(|tx| PartiallySignedTransaction::new(tx, &u_tx_outs).ok())
+    .map_or_else(
+        || rouille::Response::text("could not create transaction").with_status_code(500),
+        |psbt| rouille::Response::json(&psbt),
+    )
+}
+
+fn psbt_error(e: PsbtError) -> rouille::Response {
+    rouille::Response::text(e.to_string()).with_status_code(400)
+}
+
+/// add the signatures of this wallet to a partially signed transaction
+fn sign_psbt<V: Validator>(
+    body: String,
+    sighash: Option<String>,
+    app: &App<V>,
+) -> rouille::Response {
+    let mut psbt: PartiallySignedTransaction = match serde_json::from_str(&body) {
+        Ok(psbt) => psbt,
+        Err(_) => return rouille::Response::text("invalid psbt").with_status_code(400),
+    };
+    if let Err(e) = psbt.check() {
+        return psbt_error(e);
+    }
+    let hash_type = match sighash.as_deref().map_or(Some(SIGHASH_ALL), parse_sighash) {
+        Some(hash_type) => hash_type,
+        None => return rouille::Response::text("invalid sighash").with_status_code(400),
+    };
+    let private_keys = match app.wallet.read().unwrap().get_private_keys() {
+        Some(private_keys) => private_keys,
+        None => return wallet_locked(),
+    };
+
+    if psbt.sign(&private_keys, hash_type) == 0 {
+        return rouille::Response::text("no input can be signed by this wallet")
+            .with_status_code(400);
+    }
+
+    rouille::Response::json(&psbt)
+}
+
+/// merge the signatures of copies of a partially signed transaction
+fn combine_psbts(body: String) -> rouille::Response {
+    let psbts: Vec<PartiallySignedTransaction> = match serde_json::from_str(&body) {
+        Ok(psbts) => psbts,
+        Err(_) => return rouille::Response::text("invalid psbts").with_status_code(400),
+    };
+
+    PartiallySignedTransaction::combine(&psbts)
+        .map_or_else(psbt_error, |psbt| rouille::Response::json(&psbt))
+}
+
+/// send a fully signed transaction, from a psbt that has been signed offline, to the pool
+fn broadcast_psbt<V: Validator + Send + Sync>(body: String, app: &App<V>) -> rouille::Response {
+    let psbt: PartiallySignedTransaction = match serde_json::from_str(&body) {
+        Ok(psbt) => psbt,
+        Err(_) => return rouille::Response::text("invalid psbt").with_status_code(400),
+    };
+
+    match psbt.finalize() {
+        Ok(tx) => broadcast_transaction(serde_json::to_string(&tx).unwrap(), app),
+        Err(e) => psbt_error(e),
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct MultisigTxData {
+    /// the multisig script whose outputs will be spent
+    multisig: Script,
+    #[serde(flatten)]
+    tx: TxData,
+}
+
+/// create an unsigned transaction that spends multisig outputs, the change goes back to the multisig
+fn create_multisig_transaction<V: Validator>(body: String, app: &App<V>) -> rouille::Response {
+    let chain = app.block_chain.read().unwrap();
+    let pool = app.transaction_pool.read().unwrap();
+    let u_tx_outs = app.unspent_tx_outs.read().unwrap();
+
+    let data: MultisigTxData = serde_json::from_str(&body).expect("error parsing body");
+    let tx_out = match data.tx.recipient.tx_out() {
+        Ok(tx_out) => tx_out,
+        Err(e) => return invalid_receiver(e),
+    };
+    Wallet::create_unsigned_transaction(
+        vec![tx_out],
+        std::slice::from_ref(&data.multisig),
+        data.multisig.clone(),
+        &u_tx_outs,
+        &pool,
+        data.tx.options.lock_time,
+        next_index(&chain),
+        &data.tx.options.coin_control(),
+    )
+    .map_or_else(
+        || rouille::Response::text("could not create transaction").with_status_code(500),
+        |tx| rouille::Response::json(&tx),
+    )
+}
+
+/// add the signatures of this wallet to a transaction
+fn sign_transaction<V: Validator>(
+    body: String,
+    sighash: Option<String>,
+    app: &App<V>,
+) -> rouille::Response {
+    let tx: Transaction = match serde_json::from_str(&body) {
+        Ok(tx) => tx,
+        Err(_) => return rouille::Response::text("invalid transaction").with_status_code(400),
+    };
+    let hash_type = match sighash.as_deref().map_or(Some(SIGHASH_ALL), parse_sighash) {
+        Some(hash_type) => hash_type,
+        None => return rouille::Response::text("invalid sighash").with_status_code(400),
+    };
+    let private_keys = match app.wallet.read().unwrap().get_private_keys() {
+        Some(private_keys) => private_keys,
+        None => return wallet_locked(),
+    };
+    let u_tx_outs = app.unspent_tx_outs.read().unwrap();
+
+    let (tx, signed) = Wallet::sign_transaction(tx, &private_keys, hash_type, &u_tx_outs);
+    if signed == 0 {
+        return rouille::Response::text("no input can be signed by this wallet")
+            .with_status_code(400);
+    }
+
+    rouille::Response::json(&tx)
+}
+
+/// merge the signatures of copies of a transaction signed by different wallets
+fn combine_transactions<V: Validator>(body: String, app: &App<V>) -> rouille::Response {
+    let txs: Vec<Transaction> = match serde_json::from_str(&body) {
+        Ok(txs) => txs,
+        Err(_) => return rouille::Response::text("invalid transactions").with_status_code(400),
+    };
+    let u_tx_outs = app.unspent_tx_outs.read().unwrap();
+
+    Wallet::combine_transactions(&txs, &u_tx_outs).map_or_else(
+        || rouille::Response::text("could not combine transactions").with_status_code(400),
+        |tx| rouille::Response::json(&tx),
+    )
+}
+
 fn get_pool<V: Validator>(app: &App<V>) -> rouille::Response {
     let pool = app.transaction_pool.read().unwrap();
     rouille::Response::json(&*pool)
 }
 
+fn get_evidence<V: Validator>(app: &App<V>) -> rouille::Response {
+    rouille::Response::json(&app.evidence_pool.read().unwrap().get_all())
+}
+
+/// the spendable balance, immature coinbase outputs are reported by /immatureBalance
 fn get_balance<V: Validator>(app: &App<V>) -> rouille::Response {
+    let chain = app.block_chain.read().unwrap();
+    let wallet = app.wallet.read().unwrap();
+    let u_tx_outs = app.unspent_tx_outs.read().unwrap();
+
+    let addresses = match wallet.get_addresses() {
+        Ok(addresses) => addresses,
+        Err(e) => return keystore_error(e),
+    };
+    let balance: u64 = addresses
+        .iter()
+        .map(|address| Wallet::get_balance(address, &u_tx_outs, next_index(&chain)))
+        .sum();
+
+    rouille::Response::text(balance.to_string())
+}
+
+fn get_immature_balance<V: Validator>(app: &App<V>) -> rouille::Response {
+    let chain = app.block_chain.read().unwrap();
     let wallet = app.wallet.read().unwrap();
     let u_tx_outs = app.unspent_tx_outs.read().unwrap();
 
-    let pub_key = wallet.get_public_key();
-    let balance = Wallet::get_balance(&KeyPair::public_key_to_hex(&pub_key), &u_tx_outs);
+    let addresses = match wallet.get_addresses() {
+        Ok(addresses) => addresses,
+        Err(e) => return keystore_error(e),
+    };
+    let balance: u64 = addresses
+        .iter()
+        .map(|address| Wallet::get_immature_balance(address, &u_tx_outs, next_index(&chain)))
+        .sum();
 
     rouille::Response::text(balance.to_string())
 }
 
-fn get_public_key<V: Validator>(app: &App<V>) -> rouille::Response {
-    let public_key = app.wallet.read().unwrap().get_public_key();
-    rouille::Response::text(KeyPair::public_key_to_hex(&public_key))
+/// key_type is "ecdsa" (the default) or "schnorr"
+fn get_address<V: Validator>(key_type: Option<String>, app: &App<V>) -> rouille::Response {
+    let wallet = app.wallet.read().unwrap();
+
+    let address = match key_type.as_deref() {
+        None | Some("ecdsa") => wallet.get_address(),
+        Some("schnorr") => wallet.get_schnorr_address(),
+        Some(_) => return rouille::Response::text("invalid key type").with_status_code(400),
+    };
+
+    address.map_or_else(keystore_error, |address| {
+        rouille::Response::text(address.to_string())
+    })
+}
+
+/// an address of a key that hasn't been handed out before
+fn get_new_address<V: Validator>(key_type: Option<String>, app: &App<V>) -> rouille::Response {
+    let wallet = app.wallet.read().unwrap();
+
+    let address = match key_type.as_deref() {
+        None | Some("ecdsa") => wallet.get_new_address(),
+        Some("schnorr") => wallet.get_new_schnorr_address(),
+        Some(_) => return rouille::Response::text("invalid key type").with_status_code(400),
+    };
+
+    address.map_or_else(keystore_error, |address| {
+        rouille::Response::text(address.to_string())
+    })
+}
+
+fn get_public_key<V: Validator>(key_type: Option<String>, app: &App<V>) -> rouille::Response {
+    let wallet = app.wallet.read().unwrap();
+
+    let public_key = match key_type.as_deref() {
+        None | Some("ecdsa") => wallet
+            .get_public_key()
+            .and_then(|public_key| Ok(KeyPair::public_key_to_hex(&public_key)?)),
+        Some("schnorr") => wallet.get_schnorr_public_key().map(hex::encode),
+        Some(_) => return rouille::Response::text("invalid key type").with_status_code(400),
+    };
+
+    public_key.map_or_else(keystore_error, rouille::Response::text)
+}
+
+#[derive(Deserialize)]
+struct UnlockData {
+    passphrase: String,
+    /// seconds after which the wallet locks itself again, stays unlocked until /lock when missing
+    #[serde(default)]
+    timeout: Option<u64>,
+}
+
+fn unlock_wallet<V: Validator>(body: String, app: &App<V>) -> rouille::Response {
+    let data: UnlockData = match serde_json::from_str(&body) {
+        Ok(data) => data,
+        Err(_) => return rouille::Response::text("invalid unlock request").with_status_code(400),
+    };
+
+    let mut wallet = app.wallet.write().unwrap();
+    match wallet.unlock(&data.passphrase, data.timeout.map(Duration::from_secs)) {
+        Ok(()) => rouille::Response::text(""),
+        Err(KeystoreError::WrongPassphrase) => {
+            rouille::Response::text("wrong passphrase").with_status_code(403)
+        }
+        Err(e) => keystore_error(e),
+    }
+}
+
+fn lock_wallet<V: Validator>(app: &App<V>) -> rouille::Response {
+    app.wallet.write().unwrap().lock();
+    rouille::Response::text("")
+}
+
+fn get_mnemonic<V: Validator>(app: &App<V>) -> rouille::Response {
+    app.wallet
+        .read()
+        .unwrap()
+        .get_mnemonic()
+        .map_or_else(keystore_error, rouille::Response::text)
+}
+
+/// find the keys of a restored wallet that have been used in the chain
+fn rescan<V: Validator>(app: &App<V>) -> rouille::Response {
+    let chain = app.block_chain.read().unwrap();
+
+    app.wallet
+        .read()
+        .unwrap()
+        .rescan(&chain.blocks)
+        .map_or_else(keystore_error, |found| {
+            rouille::Response::text(found.to_string())
+        })
+}
+
+#[derive(Deserialize, Serialize)]
+struct SignMessageData {
+    /// the wallet's first address when missing
+    #[serde(default)]
+    address: Option<String>,
+    message: String,
+}
+
+/// prove control of an address without moving funds, returns the hex encoded signature
+fn sign_message<V: Validator>(body: String, app: &App<V>) -> rouille::Response {
+    let data: SignMessageData = match serde_json::from_str(&body) {
+        Ok(data) => data,
+        Err(_) => return rouille::Response::text("invalid sign request").with_status_code(400),
+    };
+    let wallet = app.wallet.read().unwrap();
+    let address = match data.address.as_deref().map(str::parse::<Address>) {
+        None => match wallet.get_address() {
+            Ok(address) => address,
+            Err(e) => return keystore_error(e),
+        },
+        Some(Ok(address)) => address,
+        Some(Err(e)) => return invalid_receiver(format!("invalid address: {}", e)),
+    };
+
+    wallet
+        .sign_message(&address, data.message.as_bytes())
+        .map_or_else(keystore_error, rouille::Response::text)
+}
+
+#[derive(Deserialize, Serialize)]
+struct VerifyMessageData {
+    address: String,
+    message: String,
+    signature: String,
+}
+
+/// anyone can verify, no wallet needed
+fn verify_message(body: String) -> rouille::Response {
+    let data: VerifyMessageData = match serde_json::from_str(&body) {
+        Ok(data) => data,
+        Err(_) => return rouille::Response::text("invalid verify request").with_status_code(400),
+    };
+    let address = match data.address.parse::<Address>() {
+        Ok(address) => address,
+        Err(e) => return invalid_receiver(format!("invalid address: {}", e)),
+    };
+
+    let valid = signed_message::verify_message(&address, data.message.as_bytes(), &data.signature);
+    rouille::Response::text(valid.to_string())
+}
+
+/// number of history entries on a page if the request doesn't say
+const DEFAULT_PAGE_SIZE: usize = 20;
+
+#[derive(Serialize)]
+struct HistoryPage {
+    /// number of entries on all pages
+    total: usize,
+    entries: Vec<HistoryEntry>,
+}
+
+/// transactions of the wallet, newest first, offset and limit select the page
+fn get_history<V: Validator>(
+    offset: Option<String>,
+    limit: Option<String>,
+    app: &App<V>,
+) -> rouille::Response {
+    let (Ok(offset), Ok(limit)) = (
+        offset.map_or(Ok(0), |offset| offset.parse::<usize>()),
+        limit.map_or(Ok(DEFAULT_PAGE_SIZE), |limit| limit.parse::<usize>()),
+    ) else {
+        return rouille::Response::text("invalid offset or limit").with_status_code(400);
+    };
+
+    let chain = app.block_chain.read().unwrap();
+    let pool = app.transaction_pool.read().unwrap();
+    let (total, entries) =
+        match app
+            .wallet
+            .read()
+            .unwrap()
+            .get_history(&chain.blocks, &pool, offset, limit)
+        {
+            Ok(page) => page,
+            Err(e) => return keystore_error(e),
+        };
+
+    rouille::Response::json(&HistoryPage { total, entries })
+}
+
+#[derive(Deserialize, Serialize)]
+struct LabelData {
+    /// label either an address or a transaction
+    #[serde(default)]
+    address: Option<String>,
+    #[serde(default)]
+    tx_id: Option<String>,
+    /// an empty label removes it
+    label: String,
+}
+
+fn set_label<V: Validator>(body: String, app: &App<V>) -> rouille::Response {
+    let data: LabelData = serde_json::from_str(&body).expect("error parsing body");
+    let wallet = app.wallet.read().unwrap();
+
+    let result = match (&data.address, &data.tx_id) {
+        (Some(address), None) => match address.parse::<Address>() {
+            Ok(address) => wallet.set_address_label(&address, data.label),
+            Err(e) => return invalid_receiver(format!("invalid address {}: {}", address, e)),
+        },
+        (None, Some(tx_id)) => wallet.set_transaction_label(tx_id, data.label),
+        _ => {
+            return rouille::Response::text("label either an address or a transaction")
+                .with_status_code(400)
+        }
+    };
+
+    result.map_or_else(keystore_error, |()| rouille::Response::text(""))
+}
+
+#[derive(Deserialize, Serialize)]
+struct ImportData {
+    /// import either an address or a hex encoded public key
+    #[serde(default)]
+    address: Option<String>,
+    #[serde(default)]
+    public_key: Option<String>,
+}
+
+/// watch an address without its private key, returns the address
+fn import_address<V: Validator>(body: String, app: &App<V>) -> rouille::Response {
+    let data: ImportData = serde_json::from_str(&body).expect("error parsing body");
+    let wallet = app.wallet.read().unwrap();
+
+    let imported = match (&data.address, &data.public_key) {
+        (Some(address), None) => match address.parse::<Address>() {
+            Ok(address) => wallet.import_address(address.clone()).map(|()| address),
+            Err(e) => return invalid_receiver(format!("invalid address {}: {}", address, e)),
+        },
+        (None, Some(public_key)) => match hex::decode(public_key) {
+            Ok(public_key) => wallet.import_public_key(&public_key),
+            Err(_) => return rouille::Response::text("invalid public key").with_status_code(400),
+        },
+        _ => {
+            return rouille::Response::text("import either an address or a public key")
+                .with_status_code(400)
+        }
+    };
+
+    match imported {
+        Ok(address) => rouille::Response::text(address.to_string()),
+        Err(KeystoreError::Crypto(_)) => {
+            rouille::Response::text("invalid public key").with_status_code(400)
+        }
+        Err(e) => keystore_error(e),
+    }
+}
+
+/// the unspent outputs of the wallet, including the watched ones
+fn get_unspent_tx_outs<V: Validator>(app: &App<V>) -> rouille::Response {
+    let my_scripts = match app.wallet.read().unwrap().get_scripts() {
+        Ok(scripts) => scripts,
+        Err(e) => return keystore_error(e),
+    };
+    let u_tx_outs: Vec<UnspentTxOut> = app
+        .unspent_tx_outs
+        .read()
+        .unwrap()
+        .iter()
+        .filter(|u_tx_out| my_scripts.contains(&u_tx_out.script_pubkey))
+        .cloned()
+        .collect();
+
+    rouille::Response::json(&u_tx_outs)
+}
+
+fn get_labels<V: Validator>(app: &App<V>) -> rouille::Response {
+    app.wallet
+        .read()
+        .unwrap()
+        .get_labels()
+        .map_or_else(keystore_error, |labels| rouille::Response::json(&labels))
 }
 
 fn mine_block<V: Validator + Send + Sync>(app: &App<V>) -> rouille::Response {
     let msg = {
         let mut chain = app.block_chain.write().unwrap();
         let wallet = app.wallet.read().unwrap();
+        if let Err(response) = mining_wallet(&wallet) {
+            return response;
+        }
         let mut pool = app.transaction_pool.write().unwrap();
 
-        let next_block =
-            Block::generate_next(&chain, &wallet, &pool, &*app.validator.read().unwrap());
+        let fees = pool.get_fees(&app.unspent_tx_outs.read().unwrap());
+        let evidence = app.evidence_pool.read().unwrap().pending(&chain);
+        let Some(next_block) = Block::generate_next(
+            &chain,
+            &wallet,
+            &pool,
+            evidence,
+            fees,
+            &*app.validator.read().unwrap(),
+        ) else {
+            return no_block();
+        };
 
         let mut unspent_tx_outs = app.unspent_tx_outs.write().unwrap();
+
         chain.add(
             next_block,
             &mut pool,
             &mut unspent_tx_outs,
             &*app.validator.read().unwrap(),
         );
+        app.orphan_pool
+            .write()
+            .unwrap()
+            .process(&mut pool, &unspent_tx_outs, next_index(&chain));
 
         Message {
             m_type: MessageType::ResponseBlockchain,
@@ -196,17 +1044,120 @@ pub fn init_http_server<V: Validator + Send + Sync + 'static>(
          },
 
          (GET) (/addr) => {
-            get_public_key(&app.read().unwrap())
+            get_address(request.get_param("type"), &app.read().unwrap())
+         },
+
+         (POST) (/newAddress) => {
+            get_new_address(request.get_param("type"), &app.read().unwrap())
+         },
+
+         (GET) (/publicKey) => {
+            get_public_key(request.get_param("type"), &app.read().unwrap())
+         },
+
+         (POST) (/unlock) => {
+            let mut body = String::new();
+            request.data().unwrap().read_to_string(&mut body).unwrap();
+
+            unlock_wallet(body, &app.read().unwrap())
+         },
+
+         (POST) (/lock) => {
+            lock_wallet(&app.read().unwrap())
+         },
+
+         (GET) (/mnemonic) => {
+            get_mnemonic(&app.read().unwrap())
+         },
+
+         (POST) (/rescan) => {
+            rescan(&app.read().unwrap())
+         },
+
+         (GET) (/history) => {
+             get_history(request.get_param("offset"), request.get_param("limit"), &app.read().unwrap())
+         },
+
+         (POST) (/importAddress) => {
+            let mut body = String::new();
+            request.data().unwrap().read_to_string(&mut body).unwrap();
+
+            import_address(body, &app.read().unwrap())
+         },
+
+         (GET) (/utxos) => {
+             get_unspent_tx_outs(&app.read().unwrap())
+         },
+
+         (POST) (/createUnsignedTransaction) => {
+            let mut body = String::new();
+            request.data().unwrap().read_to_string(&mut body).unwrap();
+
+            create_unsigned_transaction(body, &app.read().unwrap())
+         },
+
+         (POST) (/signPsbt) => {
+            let mut body = String::new();
+            request.data().unwrap().read_to_string(&mut body).unwrap();
+
+            sign_psbt(body, request.get_param("sighash"), &app.read().unwrap())
+         },
+
+         (POST) (/combinePsbt) => {
+            let mut body = String::new();
+            request.data().unwrap().read_to_string(&mut body).unwrap();
+
+            combine_psbts(body)
+         },
+
+         (POST) (/broadcastPsbt) => {
+            let mut body = String::new();
+            request.data().unwrap().read_to_string(&mut body).unwrap();
+
+            broadcast_psbt(body, &app.read().unwrap())
+         },
+
+         (GET) (/labels) => {
+             get_labels(&app.read().unwrap())
+         },
+
+         (POST) (/signMessage) => {
+            let mut body = String::new();
+            request.data().unwrap().read_to_string(&mut body).unwrap();
+
+            sign_message(body, &app.read().unwrap())
+         },
+
+         (POST) (/verifyMessage) => {
+            let mut body = String::new();
+            request.data().unwrap().read_to_string(&mut body).unwrap();
+
+            verify_message(body)
+         },
+
+         (POST) (/label) => {
+            let mut body = String::new();
+            request.data().unwrap().read_to_string(&mut body).unwrap();
+
+            set_label(body, &app.read().unwrap())
          },
 
          (GET) (/balance) => {
              get_balance(&app.read().unwrap())
          },
 
+         (GET) (/immatureBalance) => {
+             get_immature_balance(&app.read().unwrap())
+         },
+
          (GET) (/pool) => {
              get_pool(&app.read().unwrap())
          },
 
+         (GET) (/evidence) => {
+             get_evidence(&app.read().unwrap())
+         },
+
          (POST) (/mineBlock) => {
              mine_block(&app.read().unwrap())
          },
@@ -239,6 +1190,62 @@ pub fn init_http_server<V: Validator + Send + Sync + 'static>(
             send_transaction(body, &app.read().unwrap())
          },
 
+         (POST) (/sendMany) => {
+            let mut body = String::new();
+            request.data().unwrap().read_to_string(&mut body).unwrap();
+
+            send_many(body, &app.read().unwrap())
+         },
+
+         (POST) (/createTransaction) => {
+            let mut body = String::new();
+            request.data().unwrap().read_to_string(&mut body).unwrap();
+
+            create_transaction(body, &app.read().unwrap())
+         },
+
+         (POST) (/broadcastTransaction) => {
+            let mut body = String::new();
+            request.data().unwrap().read_to_string(&mut body).unwrap();
+
+            broadcast_transaction(body, &app.read().unwrap())
+         },
+
+         (POST) (/vote) => {
+            let mut body = String::new();
+            request.data().unwrap().read_to_string(&mut body).unwrap();
+
+            vote(body, &app.read().unwrap())
+         },
+
+         (POST) (/createMultisig) => {
+            let mut body = String::new();
+            request.data().unwrap().read_to_string(&mut body).unwrap();
+
+            create_multisig(body)
+         },
+
+         (POST) (/createMultisigTransaction) => {
+            let mut body = String::new();
+            request.data().unwrap().read_to_string(&mut body).unwrap();
+
+            create_multisig_transaction(body, &app.read().unwrap())
+         },
+
+         (POST) (/signTransaction) => {
+            let mut body = String::new();
+            request.data().unwrap().read_to_string(&mut body).unwrap();
+
+            sign_transaction(body, request.get_param("sighash"), &app.read().unwrap())
+         },
+
+         (POST) (/combineTransactions) => {
+            let mut body = String::new();
+            request.data().unwrap().read_to_string(&mut body).unwrap();
+
+            combine_transactions(body, &app.read().unwrap())
+         },
+
          _ => rouille::Response::empty_404()
 
         )
@@ -0,0 +1,678 @@
+use bip39::Mnemonic;
+use log::info;
+use openssl::{
+    pkcs5::scrypt,
+    rand::rand_bytes,
+    symm::{decrypt_aead, encrypt_aead, Cipher},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    os::unix::fs::{OpenOptionsExt, PermissionsExt},
+    path::Path,
+};
+use zeroize::Zeroizing;
+
+use crate::address::Address;
+use crate::crypto::{CryptoError, KeyPair, PrivateKey};
+use crate::hd::{ExtendedKey, HARDENED};
+
+pub const KEYSTORE_VERSION: u32 = 2;
+/// the keystore holds private keys, nobody but the owner should be able to read it
+pub const KEYSTORE_FILE_MODE: u32 = 0o600;
+
+const KEY_SIZE: usize = 32;
+const SALT_SIZE: usize = 16;
+const NONCE_SIZE: usize = 12;
+const TAG_SIZE: usize = 16;
+/// scrypt needs 128 * r * n bytes, leave some room above the default parameters
+const SCRYPT_MAX_MEMORY: u64 = 64 * 1024 * 1024;
+
+/// the coin type in the BIP-44 derivation path of the wallet keys
+pub const COIN_TYPE: u32 = 1;
+const SEED_TAG: &[u8] = b"seed";
+
+/// the AES key derived from the passphrase, the keystore keys are encrypted with it. it is
+/// wiped from memory when dropped
+pub type EncryptionKey = Zeroizing<[u8; KEY_SIZE]>;
+
+#[derive(Debug)]
+pub enum KeystoreError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    Crypto(CryptoError),
+    /// the passphrase could not decrypt the keys, or the file was tampered with
+    WrongPassphrase,
+    MissingPassphrase,
+    UnsupportedVersion(u32),
+    /// all pregenerated keys are used and new ones can't be added while the wallet is locked
+    KeyPoolEmpty,
+    MissingMnemonic,
+    InvalidMnemonic,
+    /// the private keys are needed but the wallet is locked
+    Locked,
+    /// the wallet only watches addresses and has no private keys
+    WatchOnly,
+    /// none of the wallet's keys belongs to the address
+    UnknownAddress,
+    /// a watch-only wallet that hasn't imported an address yet
+    NoAddress,
+}
+
+impl fmt::Display for KeystoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeystoreError::Io(e) => write!(f, "keystore io error: {}", e),
+            KeystoreError::Json(e) => write!(f, "invalid keystore: {}", e),
+            KeystoreError::Crypto(e) => write!(f, "keystore crypto error: {}", e),
+            KeystoreError::WrongPassphrase => write!(f, "wrong passphrase"),
+            KeystoreError::MissingPassphrase => write!(f, "a passphrase is required"),
+            KeystoreError::UnsupportedVersion(v) => write!(f, "unsupported keystore version {}", v),
+            KeystoreError::KeyPoolEmpty => {
+                write!(f, "the key pool is empty, unlock the wallet to refill it")
+            }
+            KeystoreError::MissingMnemonic => write!(f, "the wallet has no mnemonic"),
+            KeystoreError::InvalidMnemonic => write!(f, "invalid mnemonic"),
+            KeystoreError::Locked => write!(f, "wallet is locked"),
+            KeystoreError::WatchOnly => write!(f, "the wallet is watch-only"),
+            KeystoreError::UnknownAddress => write!(f, "the address doesn't belong to the wallet"),
+            KeystoreError::NoAddress => write!(f, "the wallet has no addresses, import one first"),
+        }
+    }
+}
+
+impl std::error::Error for KeystoreError {}
+
+impl From<io::Error> for KeystoreError {
+    fn from(e: io::Error) -> Self {
+        KeystoreError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for KeystoreError {
+    fn from(e: serde_json::Error) -> Self {
+        KeystoreError::Json(e)
+    }
+}
+
+impl From<CryptoError> for KeystoreError {
+    fn from(e: CryptoError) -> Self {
+        KeystoreError::Crypto(e)
+    }
+}
+
+impl From<openssl::error::ErrorStack> for KeystoreError {
+    fn from(e: openssl::error::ErrorStack) -> Self {
+        KeystoreError::Crypto(CryptoError::Openssl(e))
+    }
+}
+
+/// scrypt cost parameters, stored in the keystore so they can be raised later
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KdfParams {
+    pub log_n: u8,
+    pub r: u32,
+    pub p: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        KdfParams {
+            log_n: 15,
+            r: 8,
+            p: 1,
+        }
+    }
+}
+
+/// a private key encrypted with AES-256-GCM. the public keys are kept in the clear so the node knows
+/// its addresses while the wallet is locked, they are authenticated as associated data so they
+/// can't be swapped without breaking decryption
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeystoreKey {
+    pub public_key: String,
+    pub schnorr_public_key: String,
+    /// only used for the change of our own transactions
+    #[serde(default)]
+    pub change: bool,
+    /// the address has been handed out, unused keys make up the key pool
+    #[serde(default)]
+    pub used: bool,
+    /// position in the receive or change chain of the seed, None for keys that weren't derived
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<u32>,
+    #[serde(flatten)]
+    pub encrypted: Encrypted,
+}
+
+impl KeystoreKey {
+    fn encrypt(
+        private_key: &PrivateKey,
+        encryption_key: &EncryptionKey,
+        change: bool,
+    ) -> Result<Self, KeystoreError> {
+        let public_key = KeyPair::public_key_to_hex(&private_key.to_public_key()?)?;
+        let schnorr_public_key = hex::encode(private_key.to_schnorr_key()?.public_key());
+
+        let aad = associated_data(KEYSTORE_VERSION, &public_key, &schnorr_public_key);
+        let pem = Zeroizing::new(private_key.to_pem()?);
+        let encrypted = Encrypted::seal(encryption_key, &aad, &pem)?;
+
+        Ok(KeystoreKey {
+            public_key,
+            schnorr_public_key,
+            change,
+            used: false,
+            index: None,
+            encrypted,
+        })
+    }
+
+    fn decrypt(
+        &self,
+        encryption_key: &EncryptionKey,
+        version: u32,
+    ) -> Result<PrivateKey, KeystoreError> {
+        let aad = associated_data(version, &self.public_key, &self.schnorr_public_key);
+        let pem = self.encrypted.open(encryption_key, &aad)?;
+
+        let private_key = PrivateKey::from_pem(&pem)?;
+        if KeyPair::public_key_to_hex(&private_key.to_public_key()?)? != self.public_key {
+            return Err(KeystoreError::WrongPassphrase);
+        }
+
+        Ok(private_key)
+    }
+}
+
+/// AES-256-GCM ciphertext with its nonce and authentication tag
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Encrypted {
+    pub nonce: String,
+    pub ciphertext: String,
+    pub tag: String,
+}
+
+impl Encrypted {
+    fn seal(
+        encryption_key: &EncryptionKey,
+        aad: &[u8],
+        plaintext: &[u8],
+    ) -> Result<Self, KeystoreError> {
+        let mut nonce = [0; NONCE_SIZE];
+        rand_bytes(&mut nonce)?;
+
+        let mut tag = [0; TAG_SIZE];
+        let ciphertext = encrypt_aead(
+            Cipher::aes_256_gcm(),
+            encryption_key.as_slice(),
+            Some(&nonce),
+            aad,
+            plaintext,
+            &mut tag,
+        )?;
+
+        Ok(Encrypted {
+            nonce: hex::encode(nonce),
+            ciphertext: hex::encode(ciphertext),
+            tag: hex::encode(tag),
+        })
+    }
+
+    /// the plaintext is wiped from memory when dropped
+    fn open(
+        &self,
+        encryption_key: &EncryptionKey,
+        aad: &[u8],
+    ) -> Result<Zeroizing<Vec<u8>>, KeystoreError> {
+        let nonce = hex::decode(&self.nonce).map_err(CryptoError::from)?;
+        let ciphertext = hex::decode(&self.ciphertext).map_err(CryptoError::from)?;
+        let tag = hex::decode(&self.tag).map_err(CryptoError::from)?;
+
+        decrypt_aead(
+            Cipher::aes_256_gcm(),
+            encryption_key.as_slice(),
+            Some(&nonce),
+            aad,
+            &ciphertext,
+            &tag,
+        )
+        .map(Zeroizing::new)
+        .map_err(|_| KeystoreError::WrongPassphrase)
+    }
+}
+
+/// all keys of a wallet, encrypted under one key derived from the passphrase with scrypt
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Keystore {
+    pub version: u32,
+    pub kdf: KdfParams,
+    pub salt: String,
+    /// the mnemonic the keys are derived from, keystores of older wallets may not have one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<Encrypted>,
+    pub keys: Vec<KeystoreKey>,
+    /// a watch-only keystore has no keys and can't be unlocked
+    #[serde(default)]
+    pub watch_only: bool,
+    /// addresses that are tracked without holding their private keys
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub watched: Vec<WatchedKey>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WatchedKey {
+    pub address: Address,
+    /// hex encoded, if it is known outputs that pay to the public key directly are tracked too
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_key: Option<String>,
+}
+
+/// the single key keystore that was used before wallets held more than one key
+#[derive(Deserialize)]
+struct KeystoreV1 {
+    kdf: KdfParams,
+    salt: String,
+    #[serde(flatten)]
+    key: KeystoreKey,
+}
+
+impl Keystore {
+    /// an empty keystore and the encryption key for its keys
+    pub fn new(passphrase: &str, kdf: KdfParams) -> Result<(Self, EncryptionKey), KeystoreError> {
+        let mut salt = [0; SALT_SIZE];
+        rand_bytes(&mut salt)?;
+
+        let keystore = Keystore {
+            version: KEYSTORE_VERSION,
+            kdf,
+            salt: hex::encode(salt),
+            seed: None,
+            keys: vec![],
+            watch_only: false,
+            watched: vec![],
+        };
+        let encryption_key = keystore.encryption_key(passphrase)?;
+
+        Ok((keystore, encryption_key))
+    }
+
+    /// a keystore without private keys, it only holds the watched addresses
+    pub fn new_watch_only() -> Self {
+        Keystore {
+            version: KEYSTORE_VERSION,
+            kdf: KdfParams::default(),
+            salt: String::new(),
+            seed: None,
+            keys: vec![],
+            watch_only: true,
+            watched: vec![],
+        }
+    }
+
+    /// track the address, importing it again adds the public key if it wasn't known before
+    pub fn watch(&mut self, address: Address, public_key: Option<String>) {
+        match self.watched.iter_mut().find(|key| key.address == address) {
+            Some(key) => key.public_key = key.public_key.take().or(public_key),
+            None => self.watched.push(WatchedKey {
+                address,
+                public_key,
+            }),
+        }
+    }
+
+    /// derive the encryption key and check it against the seed or the first key
+    pub fn encryption_key(&self, passphrase: &str) -> Result<EncryptionKey, KeystoreError> {
+        if self.watch_only {
+            return Err(KeystoreError::WatchOnly);
+        }
+        let salt = hex::decode(&self.salt).map_err(CryptoError::from)?;
+        let encryption_key = derive_key(passphrase, &salt, &self.kdf)?;
+
+        if self.seed.is_some() {
+            self.mnemonic(&encryption_key)?;
+        } else if let Some(key) = self.keys.first() {
+            key.decrypt(&encryption_key, self.version)?;
+        }
+
+        Ok(encryption_key)
+    }
+
+    pub fn set_mnemonic(
+        &mut self,
+        mnemonic: &Mnemonic,
+        encryption_key: &EncryptionKey,
+    ) -> Result<(), KeystoreError> {
+        let aad = [&self.version.to_be_bytes(), SEED_TAG].concat();
+        let phrase = Zeroizing::new(mnemonic.to_string());
+        self.seed = Some(Encrypted::seal(encryption_key, &aad, phrase.as_bytes())?);
+
+        Ok(())
+    }
+
+    pub fn mnemonic(&self, encryption_key: &EncryptionKey) -> Result<Mnemonic, KeystoreError> {
+        let seed = self.seed.as_ref().ok_or(KeystoreError::MissingMnemonic)?;
+        let aad = [&self.version.to_be_bytes(), SEED_TAG].concat();
+        let phrase = seed.open(encryption_key, &aad)?;
+
+        std::str::from_utf8(&phrase)
+            .ok()
+            .and_then(|phrase| Mnemonic::parse(phrase).ok())
+            .ok_or(KeystoreError::InvalidMnemonic)
+    }
+
+    /// the private key at m/44'/COIN_TYPE'/0'/change/index
+    pub fn derive_private_key(
+        &self,
+        encryption_key: &EncryptionKey,
+        change: bool,
+        index: u32,
+    ) -> Result<PrivateKey, KeystoreError> {
+        Ok(self
+            .chain_key(encryption_key, change)?
+            .derive(index)?
+            .to_private_key()?)
+    }
+
+    fn chain_key(
+        &self,
+        encryption_key: &EncryptionKey,
+        change: bool,
+    ) -> Result<ExtendedKey, KeystoreError> {
+        let seed = Zeroizing::new(self.mnemonic(encryption_key)?.to_seed(""));
+        let path = [44 | HARDENED, COIN_TYPE | HARDENED, HARDENED, change as u32];
+
+        Ok(ExtendedKey::from_seed(seed.as_slice())?.derive_path(&path)?)
+    }
+
+    pub fn add_key(
+        &mut self,
+        private_key: &PrivateKey,
+        encryption_key: &EncryptionKey,
+        change: bool,
+    ) -> Result<&mut KeystoreKey, KeystoreError> {
+        self.keys
+            .push(KeystoreKey::encrypt(private_key, encryption_key, change)?);
+
+        Ok(self.keys.last_mut().unwrap())
+    }
+
+    /// add keys until there are size unused receive and change keys after the last used one, so
+    /// a rescan doesn't miss keys that were handed out after a gap. the keys are derived from the
+    /// seed, keystores without a seed get random keys
+    pub fn fill_key_pool(
+        &mut self,
+        encryption_key: &EncryptionKey,
+        size: usize,
+    ) -> Result<(), KeystoreError> {
+        for change in [false, true] {
+            let unused = self
+                .keys
+                .iter()
+                .rev()
+                .filter(|key| key.change == change)
+                .take_while(|key| !key.used)
+                .count();
+            if unused >= size {
+                continue;
+            }
+
+            let chain_key = match self.seed {
+                Some(_) => Some(self.chain_key(encryption_key, change)?),
+                None => None,
+            };
+            let mut next_index = self
+                .keys
+                .iter()
+                .filter(|key| key.change == change)
+                .filter_map(|key| key.index)
+                .max()
+                .map_or(0, |index| index + 1);
+
+            for _ in unused..size {
+                match &chain_key {
+                    Some(chain_key) => {
+                        let private_key = chain_key.derive(next_index)?.to_private_key()?;
+                        self.add_key(&private_key, encryption_key, change)?.index =
+                            Some(next_index);
+                        next_index += 1;
+                    }
+                    None => {
+                        self.add_key(&KeyPair::generate()?.private_key, encryption_key, change)?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// the oldest unused key of the pool, without marking it
+    pub fn next_key(&self, change: bool) -> Result<&KeystoreKey, KeystoreError> {
+        if self.watch_only {
+            return Err(KeystoreError::WatchOnly);
+        }
+        self.keys
+            .iter()
+            .find(|key| key.change == change && !key.used)
+            .ok_or(KeystoreError::KeyPoolEmpty)
+    }
+
+    /// mark the oldest unused key of the pool as used
+    pub fn take_key(&mut self, change: bool) -> Result<&KeystoreKey, KeystoreError> {
+        if self.watch_only {
+            return Err(KeystoreError::WatchOnly);
+        }
+        let key = self
+            .keys
+            .iter_mut()
+            .find(|key| key.change == change && !key.used)
+            .ok_or(KeystoreError::KeyPoolEmpty)?;
+        key.used = true;
+
+        Ok(key)
+    }
+
+    pub fn decrypt(
+        &self,
+        encryption_key: &EncryptionKey,
+    ) -> Result<Vec<PrivateKey>, KeystoreError> {
+        self.keys
+            .iter()
+            .map(|key| key.decrypt(encryption_key, self.version))
+            .collect()
+    }
+
+    pub fn read_file(path: &Path) -> Result<Self, KeystoreError> {
+        let keystore: Self = serde_json::from_slice(&fs::read(path)?)?;
+        if keystore.version != KEYSTORE_VERSION {
+            return Err(KeystoreError::UnsupportedVersion(keystore.version));
+        }
+
+        Ok(keystore)
+    }
+
+    /// the private key of a keystore written by an older version, None if the file isn't one
+    pub fn read_legacy_file(
+        path: &Path,
+        passphrase: Option<&str>,
+    ) -> Result<Option<PrivateKey>, KeystoreError> {
+        let contents = fs::read(path)?;
+        if let Ok(private_key) = PrivateKey::from_pem(&contents) {
+            return Ok(Some(private_key));
+        }
+
+        let legacy: KeystoreV1 = match serde_json::from_slice(&contents) {
+            Ok(legacy) => legacy,
+            Err(_) => return Ok(None),
+        };
+        let passphrase = passphrase.ok_or(KeystoreError::MissingPassphrase)?;
+        let salt = hex::decode(&legacy.salt).map_err(CryptoError::from)?;
+        let encryption_key = derive_key(passphrase, &salt, &legacy.kdf)?;
+
+        Ok(Some(legacy.key.decrypt(&encryption_key, 1)?))
+    }
+
+    /// the file is written next to the old one and renamed over it, so a crash can't leave a
+    /// half written keystore behind
+    pub fn write_file(&self, path: &Path) -> Result<(), KeystoreError> {
+        if let Some(prefix) = path.parent() {
+            fs::create_dir_all(prefix)?;
+        }
+
+        // a leftover temp file could have other permissions, the mode only applies to a new file
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        match fs::remove_file(&tmp_path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        let mut f = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(KEYSTORE_FILE_MODE)
+            .open(&tmp_path)?;
+        info!("writing keystore to path: {}", path.display());
+
+        f.write_all(&serde_json::to_vec_pretty(self)?)?;
+        f.sync_all()?;
+        fs::rename(&tmp_path, path)?;
+
+        Ok(())
+    }
+
+    /// make sure an existing keystore is only readable by its owner
+    pub fn restrict_permissions(path: &Path) -> Result<(), KeystoreError> {
+        let mut permissions = fs::metadata(path)?.permissions();
+        if permissions.mode() & 0o777 != KEYSTORE_FILE_MODE {
+            permissions.set_mode(KEYSTORE_FILE_MODE);
+            fs::set_permissions(path, permissions)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn derive_key(
+    passphrase: &str,
+    salt: &[u8],
+    kdf: &KdfParams,
+) -> Result<EncryptionKey, KeystoreError> {
+    let mut key = Zeroizing::new([0; KEY_SIZE]);
+    scrypt(
+        passphrase.as_bytes(),
+        salt,
+        1 << kdf.log_n,
+        kdf.r as u64,
+        kdf.p as u64,
+        SCRYPT_MAX_MEMORY,
+        key.as_mut(),
+    )?;
+
+    Ok(key)
+}
+
+fn associated_data(version: u32, public_key: &str, schnorr_public_key: &str) -> Vec<u8> {
+    [
+        &version.to_be_bytes(),
+        public_key.as_bytes(),
+        schnorr_public_key.as_bytes(),
+    ]
+    .concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_KDF: KdfParams = KdfParams {
+        log_n: 10,
+        r: 8,
+        p: 1,
+    };
+
+    #[test]
+    fn test_encrypt_decrypt() {
+        let key = KeyPair::generate().unwrap().private_key;
+        let (mut keystore, encryption_key) = Keystore::new("correct horse", TEST_KDF).unwrap();
+        keystore.add_key(&key, &encryption_key, false).unwrap();
+
+        let encryption_key = keystore.encryption_key("correct horse").unwrap();
+        let decrypted = keystore.decrypt(&encryption_key).unwrap();
+        assert_eq!(decrypted[0].to_pem().unwrap(), key.to_pem().unwrap());
+
+        assert!(matches!(
+            keystore.encryption_key("wrong horse"),
+            Err(KeystoreError::WrongPassphrase)
+        ));
+
+        // the public key is bound to the ciphertext
+        let mut swapped = keystore.clone();
+        swapped.keys[0].public_key = KeyPair::public_key_to_hex(
+            &KeyPair::generate()
+                .unwrap()
+                .private_key
+                .to_public_key()
+                .unwrap(),
+        )
+        .unwrap();
+        assert!(matches!(
+            swapped.decrypt(&encryption_key),
+            Err(KeystoreError::WrongPassphrase)
+        ));
+    }
+
+    #[test]
+    fn test_key_pool() {
+        let (mut keystore, encryption_key) = Keystore::new("passphrase", TEST_KDF).unwrap();
+        keystore.fill_key_pool(&encryption_key, 2).unwrap();
+        assert_eq!(keystore.keys.len(), 4);
+
+        let first = keystore.take_key(false).unwrap().clone();
+        assert!(!first.change);
+        assert_ne!(
+            keystore.take_key(false).unwrap().public_key,
+            first.public_key
+        );
+        assert!(matches!(
+            keystore.take_key(false),
+            Err(KeystoreError::KeyPoolEmpty)
+        ));
+        assert!(keystore.take_key(true).unwrap().change);
+
+        // only the used keys are replaced
+        keystore.fill_key_pool(&encryption_key, 2).unwrap();
+        assert_eq!(keystore.keys.len(), 7);
+        assert_eq!(keystore.decrypt(&encryption_key).unwrap().len(), 7);
+    }
+
+    #[test]
+    fn test_write_file_permissions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wallet").join("keystore.json");
+        let key = KeyPair::generate().unwrap().private_key;
+        let (mut keystore, encryption_key) = Keystore::new("passphrase", TEST_KDF).unwrap();
+        keystore.add_key(&key, &encryption_key, false).unwrap();
+
+        keystore.write_file(&path).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, KEYSTORE_FILE_MODE);
+        assert_eq!(Keystore::read_file(&path).unwrap(), keystore);
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        Keystore::restrict_permissions(&path).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, KEYSTORE_FILE_MODE);
+
+        // a temp file left behind by a crash doesn't pass its permissions on
+        let tmp_path = dir.path().join("wallet").join("keystore.json.tmp");
+        fs::write(&tmp_path, b"leftover").unwrap();
+        fs::set_permissions(&tmp_path, fs::Permissions::from_mode(0o644)).unwrap();
+        keystore.write_file(&path).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, KEYSTORE_FILE_MODE);
+        assert_eq!(Keystore::read_file(&path).unwrap(), keystore);
+    }
+}
@@ -1,10 +1,27 @@
+use once_cell::sync::Lazy;
+use std::env;
+
+pub mod address;
 pub mod block;
 pub mod chain;
+pub mod coin_selection;
 pub mod crypto;
 pub mod difficulter;
+pub mod evidence_pool;
+pub mod hd;
+pub mod history;
+pub mod keystore;
 pub mod message;
+pub mod orphan_pool;
 pub mod p2p;
 pub mod p2p_handler;
+pub mod psbt;
+pub mod script;
+pub mod signature_cache;
+pub mod signed_message;
+pub mod slashing;
+#[cfg(test)]
+mod test_util;
 pub mod transaction;
 pub mod transaction_pool;
 pub mod validator;
@@ -15,3 +32,12 @@ pub const BLOCK_GENERATION_INTERVAL: u32 = 10;
 pub const DIFFICULTY_ADJUSTMENT_INTERVAL: u32 = 10;
 
 pub const COINBASE_AMOUNT: u64 = 50;
+pub const DEFAULT_COINBASE_MATURITY: u64 = 10;
+/// number of blocks that has to be added after a coinbase transaction before its output can be spent
+/// set with the COINBASE_MATURITY environment variable, every node of a network has to use the same value
+pub static COINBASE_MATURITY: Lazy<u64> = Lazy::new(|| {
+    env::var("COINBASE_MATURITY")
+        .ok()
+        .map(|maturity| maturity.parse().expect("could not parse COINBASE_MATURITY"))
+        .unwrap_or(DEFAULT_COINBASE_MATURITY)
+});
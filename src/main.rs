@@ -2,11 +2,13 @@ mod http_server;
 
 use log::{error, info};
 use naivecoin_rs::chain::BlockChain;
+use naivecoin_rs::evidence_pool::EvidencePool;
+use naivecoin_rs::orphan_pool::OrphanPool;
 use naivecoin_rs::p2p_handler::P2PHandler;
 use naivecoin_rs::transaction::UnspentTxOut;
 use naivecoin_rs::transaction_pool::TransactionPool;
+use naivecoin_rs::validator::poa::PoaValidator;
 use naivecoin_rs::validator::pos::PosValidator;
-use naivecoin_rs::validator::pow::PowValidator;
 use naivecoin_rs::validator::Validator;
 use serde::{Deserialize, Serialize};
 use std::env;
@@ -24,12 +26,18 @@ struct Config {
     p2p_port: String,
     initial_peers: String,
     key_location: String,
+    /// comma separated addresses, proof of authority is used instead of proof of stake if set
+    authorities: String,
+    /// comma separated addresses that produce the first blocks under proof of stake
+    bootstrap_stakers: String,
 }
 
 #[derive(Debug)]
 pub struct App<V: Validator> {
     pub block_chain: Arc<RwLock<BlockChain>>,
     pub transaction_pool: Arc<RwLock<TransactionPool>>,
+    pub orphan_pool: Arc<RwLock<OrphanPool>>,
+    pub evidence_pool: Arc<RwLock<EvidencePool>>,
     pub wallet: Arc<RwLock<Wallet>>,
     pub unspent_tx_outs: Arc<RwLock<Vec<UnspentTxOut>>>,
     pub validator: Arc<RwLock<V>>,
@@ -41,11 +49,31 @@ impl<V: Validator + Send + Sync> App<V> {
         wallet: Arc<RwLock<Wallet>>,
         unspent_tx_outs: Arc<RwLock<Vec<UnspentTxOut>>>,
     ) -> App<V> {
-        wallet.read().unwrap().generate_private_key();
+        // only needed to create the keystore, the wallet starts locked either way
+        let passphrase = env::var("WALLET_PASSPHRASE").ok();
+        // restores the wallet when there is no keystore yet
+        let mnemonic = env::var("WALLET_MNEMONIC").ok();
+        // a watch-only wallet holds no private keys, addresses are imported through the api
+        let watch_only = env::var("WATCH_ONLY").is_ok_and(|watch_only| watch_only == "true");
+        if watch_only {
+            wallet
+                .read()
+                .unwrap()
+                .init_watch_only()
+                .expect("could not load watch-only wallet");
+        } else {
+            wallet
+                .read()
+                .unwrap()
+                .init(passphrase.as_deref(), mnemonic.as_deref())
+                .expect("could not load wallet, set WALLET_PASSPHRASE to create a new one");
+        }
 
         Self {
             block_chain: Arc::default(),
             transaction_pool: Arc::default(),
+            orphan_pool: Arc::default(),
+            evidence_pool: Arc::default(),
             wallet,
             unspent_tx_outs,
             validator,
@@ -61,6 +89,8 @@ impl Config {
             initial_peers: env::var("INITIAL").unwrap_or_default(),
             key_location: env::var("KEY_LOC")
                 .unwrap_or_else(|_| String::from("./node/wallet/private_key.pem")),
+            authorities: env::var("AUTHORITIES").unwrap_or_default(),
+            bootstrap_stakers: env::var("BOOTSTRAP_STAKERS").unwrap_or_default(),
         }
     }
 }
@@ -69,14 +99,44 @@ fn main() {
     env_logger::init();
 
     let config = Config::from_env();
-    let wallet = Arc::new(RwLock::new(Wallet {
-        signing_key_location: config.key_location,
-    }));
+    let wallet = Arc::new(RwLock::new(Wallet::new(config.key_location.clone())));
     let unspent_tx_outs: Arc<RwLock<Vec<UnspentTxOut>>> = Arc::default();
-    let validator = Arc::new(RwLock::new(PosValidator {
-        wallet: wallet.clone(),
-        unspent_tx_outs: unspent_tx_outs.clone(),
-    }));
+
+    if config.authorities.is_empty() {
+        let bootstrap_stakers = config
+            .bootstrap_stakers
+            .split(',')
+            .filter(|staker| !staker.is_empty())
+            .map(|staker| staker.parse().expect("could not parse bootstrap staker"))
+            .collect();
+        let validator = PosValidator {
+            wallet: wallet.clone(),
+            unspent_tx_outs: unspent_tx_outs.clone(),
+            bootstrap_stakers,
+        };
+        run(config, validator, wallet, unspent_tx_outs);
+    } else {
+        let authorities = config
+            .authorities
+            .split(',')
+            .map(|authority| authority.parse().expect("could not parse authority"))
+            .collect();
+        let validator = PoaValidator {
+            wallet: wallet.clone(),
+            unspent_tx_outs: unspent_tx_outs.clone(),
+            authorities,
+        };
+        run(config, validator, wallet, unspent_tx_outs);
+    }
+}
+
+fn run<V: Validator + Send + Sync + 'static>(
+    config: Config,
+    validator: V,
+    wallet: Arc<RwLock<Wallet>>,
+    unspent_tx_outs: Arc<RwLock<Vec<UnspentTxOut>>>,
+) {
+    let validator = Arc::new(RwLock::new(validator));
     let app = Arc::new(RwLock::new(App::new(validator, wallet, unspent_tx_outs)));
 
     for peer in config.initial_peers.split(',') {
@@ -85,7 +145,7 @@ fn main() {
         }
 
         if let Ok(peer) = peer.parse() {
-            Server::<PosValidator>::connect_to_peer(peer);
+            Server::<V>::connect_to_peer(peer);
         } else {
             error!("could not parse peer: {}", &peer);
         }
@@ -110,6 +170,8 @@ fn main() {
             handler: P2PHandler {
                 chain: rapp.block_chain.clone(),
                 transaction_pool: rapp.transaction_pool.clone(),
+                orphan_pool: rapp.orphan_pool.clone(),
+                evidence_pool: rapp.evidence_pool.clone(),
                 unspent_tx_outs: rapp.unspent_tx_outs.clone(),
                 validator: rapp.validator.clone(),
             },
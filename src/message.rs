@@ -2,12 +2,16 @@ use log::{info, warn};
 use mio::{net::TcpStream, Token};
 use serde::{Deserialize, Serialize};
 use std::io::Write;
+use std::net::SocketAddr;
 use std::thread;
 
 use crate::{
     block::Block,
     chain::BlockChain,
+    evidence_pool::EvidencePool,
+    orphan_pool::OrphanPool,
     p2p::Server,
+    slashing::DoubleSignEvidence,
     transaction::{Transaction, UnspentTxOut},
     transaction_pool::TransactionPool,
     validator::Validator,
@@ -20,6 +24,8 @@ pub enum MessageType {
     ResponseBlockchain,
     QueryTransactionPool,
     ResponseTransactionPool,
+    /// blocks signed twice at the same height by one miner
+    ResponseEvidence,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
@@ -50,12 +56,37 @@ impl Message {
         &self,
         chain: &mut BlockChain,
         pool: &mut TransactionPool,
+        orphans: &mut OrphanPool,
+        evidence: &mut EvidencePool,
         unspent_tx_outs: &mut Vec<UnspentTxOut>,
         validator: &impl Validator,
     ) {
-        let mut received_blocks: Vec<Block> = serde_json::from_str(&self.content).unwrap();
-        received_blocks.sort_by(|a, b| a.index.cmp(&b.index));
-        let latest_block_received = received_blocks.last().unwrap();
+        let mut received_blocks: Vec<Block> = match serde_json::from_str(&self.content) {
+            Ok(blocks) => blocks,
+            Err(e) => {
+                warn!("invalid blocks received: {}", e);
+                return;
+            }
+        };
+        received_blocks.sort_by_key(|a| a.index);
+        // a miner that signed a block we hold and a different one at the same height
+        let found = evidence.detect(chain, &received_blocks);
+        if !found.is_empty() {
+            let msg = Message {
+                m_type: MessageType::ResponseEvidence,
+                content: serde_json::to_string(&found).unwrap(),
+            };
+            thread::spawn(move || {
+                msg.broadcast::<V>();
+            });
+        }
+        let latest_block_received = match received_blocks.last() {
+            Some(block) => block,
+            None => {
+                warn!("received_blocks.len() == 0");
+                return;
+            }
+        };
         let latest_block_held = chain.get_latest().unwrap();
 
         if latest_block_received.index > latest_block_held.index {
@@ -87,6 +118,21 @@ impl Message {
             } else {
                 chain.replace(received_blocks, unspent_tx_outs, pool, validator);
             }
+
+            // the new blocks may contain the parents of some orphans
+            let next_index = chain.get_latest().unwrap().index as u64 + 1;
+            if !orphans
+                .process(pool, unspent_tx_outs, next_index)
+                .is_empty()
+            {
+                let msg = Message {
+                    m_type: MessageType::ResponseTransactionPool,
+                    content: serde_json::to_string(pool).unwrap(),
+                };
+                thread::spawn(move || {
+                    msg.broadcast::<V>();
+                });
+            }
         }
         // else received blockchain is not longer than current blockchain. Do nothing
     }
@@ -94,19 +140,33 @@ impl Message {
     pub fn handle_transaction_pool_response<V: Validator + Send + Sync>(
         &self,
         pool: &mut TransactionPool,
+        orphans: &mut OrphanPool,
         unspent_tx_outs: &[UnspentTxOut],
+        block_index: u64,
+        peer: Option<SocketAddr>,
     ) {
-        let received_transactions: Vec<Transaction> =
-            serde_json::from_str(&self.content).expect("error parsing json");
+        let received_transactions: Vec<Transaction> = match serde_json::from_str(&self.content) {
+            Ok(transactions) => transactions,
+            Err(e) => {
+                warn!("invalid transactions received: {}", e);
+                return;
+            }
+        };
         if received_transactions.is_empty() {
             warn!("received_transactions.len() == 0");
             return;
         }
         for received_tx in received_transactions {
-            let ok = pool.add(received_tx, unspent_tx_outs);
+            let ok = pool.add(received_tx.clone(), unspent_tx_outs, block_index);
 
             if !ok {
-                warn!("error adding transaction");
+                // the parent of this transaction may not have reached us yet
+                let missing = received_tx.missing_tx_outs(unspent_tx_outs);
+                if orphans.add(received_tx, missing, peer) {
+                    info!("stored transaction as orphan");
+                } else {
+                    warn!("error adding transaction");
+                }
             } else {
                 let msg = Message {
                     m_type: MessageType::ResponseTransactionPool,
@@ -119,6 +179,34 @@ impl Message {
         }
     }
 
+    pub fn handle_evidence_response<V: Validator + Send + Sync>(
+        &self,
+        chain: &BlockChain,
+        evidence: &mut EvidencePool,
+    ) {
+        let received_evidence: Vec<DoubleSignEvidence> = match serde_json::from_str(&self.content) {
+            Ok(evidence) => evidence,
+            Err(e) => {
+                warn!("invalid evidence received: {}", e);
+                return;
+            }
+        };
+
+        let new: Vec<DoubleSignEvidence> = received_evidence
+            .into_iter()
+            .filter(|e| evidence.add(e.clone(), chain))
+            .collect();
+        if !new.is_empty() {
+            let msg = Message {
+                m_type: MessageType::ResponseEvidence,
+                content: serde_json::to_string(&new).unwrap(),
+            };
+            thread::spawn(move || {
+                msg.broadcast::<V>();
+            });
+        }
+    }
+
     pub fn broadcast<V: Validator + Send + Sync>(self) {
         info!("broadcast");
         Server::<V>::broadcast(self.serialize().as_bytes());
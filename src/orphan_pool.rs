@@ -0,0 +1,361 @@
+use log::{info, warn};
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::transaction::{Transaction, UnspentTxOut};
+use crate::transaction_pool::TransactionPool;
+
+pub const MAX_ORPHAN_TRANSACTIONS: usize = 100;
+/// per IP address, a peer that reconnects from another port shares the limit
+pub const MAX_ORPHANS_PER_PEER: usize = 20;
+/// in bytes, larger transactions aren't kept while their parents are missing
+pub const MAX_ORPHAN_TX_SIZE: usize = 100_000;
+// in seconds
+pub const ORPHAN_EXPIRE_TIME: u64 = 20 * 60;
+
+/// (tx_out_id, tx_out_index) of an output that a transaction spends
+pub type OutPoint = (String, u64);
+
+#[derive(Debug, Clone)]
+struct Orphan {
+    tx: Transaction,
+    peer: Option<IpAddr>,
+    expires_at: u64,
+}
+
+/// transactions whose inputs reference outputs we don't know about yet
+/// they are kept here until their parents arrive, they expire or they get evicted
+#[derive(Debug, Default)]
+pub struct OrphanPool {
+    orphans: HashMap<String, Orphan>,
+    by_missing: HashMap<OutPoint, HashSet<String>>,
+}
+
+impl OrphanPool {
+    pub fn new() -> Self {
+        OrphanPool::default()
+    }
+
+    /// add tx that is missing the given outpoints, returns false if it is not kept
+    pub fn add(
+        &mut self,
+        tx: Transaction,
+        missing: Vec<OutPoint>,
+        peer: Option<SocketAddr>,
+    ) -> bool {
+        if !is_well_formed(&tx) {
+            warn!("dropping malformed orphan tx {}", tx.id);
+            return false;
+        }
+
+        self.insert(
+            tx,
+            missing,
+            peer.map(|peer| peer.ip()),
+            now() + ORPHAN_EXPIRE_TIME,
+        )
+    }
+
+    fn insert(
+        &mut self,
+        tx: Transaction,
+        missing: Vec<OutPoint>,
+        peer: Option<IpAddr>,
+        expires_at: u64,
+    ) -> bool {
+        self.expire(now());
+
+        if missing.is_empty() || self.orphans.contains_key(&tx.id) {
+            return false;
+        }
+
+        if let Some(peer) = peer {
+            if self.count_from_peer(&peer) >= MAX_ORPHANS_PER_PEER {
+                warn!("peer {} has too many orphans, dropping {}", peer, tx.id);
+                return false;
+            }
+        }
+
+        if self.orphans.len() >= MAX_ORPHAN_TRANSACTIONS {
+            self.evict_oldest();
+        }
+
+        info!("adding orphan tx: {} missing: {:?}", tx.id, missing);
+
+        for out_point in missing {
+            self.by_missing
+                .entry(out_point)
+                .or_default()
+                .insert(tx.id.clone());
+        }
+        self.orphans.insert(
+            tx.id.clone(),
+            Orphan {
+                tx,
+                peer,
+                expires_at,
+            },
+        );
+
+        true
+    }
+
+    /// retry the orphans whose missing outputs are now unspent
+    /// returns the transactions that made it into the pool
+    pub fn process(
+        &mut self,
+        pool: &mut TransactionPool,
+        unspent_tx_outs: &[UnspentTxOut],
+        block_index: u64,
+    ) -> Vec<Transaction> {
+        self.expire(now());
+
+        let ready: HashSet<String> = unspent_tx_outs
+            .iter()
+            .filter_map(|u_tx_out| {
+                self.by_missing
+                    .get(&(u_tx_out.tx_out_id.clone(), u_tx_out.tx_out_index))
+            })
+            .flatten()
+            .cloned()
+            .collect();
+
+        let mut accepted = vec![];
+        for id in ready {
+            let orphan = match self.remove(&id) {
+                Some(orphan) => orphan,
+                None => continue,
+            };
+
+            if pool.add(orphan.tx.clone(), unspent_tx_outs, block_index) {
+                info!("orphan tx {} accepted to the pool", id);
+                accepted.push(orphan.tx);
+                continue;
+            }
+
+            // some other parent is still missing, wait for it until the original deadline
+            let missing = orphan.tx.missing_tx_outs(unspent_tx_outs);
+            if !missing.is_empty() {
+                self.insert(orphan.tx, missing, orphan.peer, orphan.expires_at);
+            }
+        }
+
+        accepted
+    }
+
+    pub fn len(&self) -> usize {
+        self.orphans.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.orphans.is_empty()
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.orphans.contains_key(id)
+    }
+
+    fn count_from_peer(&self, peer: &IpAddr) -> usize {
+        self.orphans
+            .values()
+            .filter(|orphan| orphan.peer.as_ref() == Some(peer))
+            .count()
+    }
+
+    fn expire(&mut self, now: u64) {
+        let expired: Vec<String> = self
+            .orphans
+            .iter()
+            .filter(|(_, orphan)| orphan.expires_at <= now)
+            .map(|(id, _)| id.clone())
+            .collect();
+
+        for id in expired {
+            info!("orphan tx {} expired", id);
+            self.remove(&id);
+        }
+    }
+
+    fn evict_oldest(&mut self) {
+        let oldest = self
+            .orphans
+            .iter()
+            .min_by_key(|(_, orphan)| orphan.expires_at)
+            .map(|(id, _)| id.clone());
+
+        if let Some(id) = oldest {
+            warn!("orphan pool is full, evicting {}", id);
+            self.remove(&id);
+        }
+    }
+
+    fn remove(&mut self, id: &str) -> Option<Orphan> {
+        let orphan = self.orphans.remove(id)?;
+
+        self.by_missing.retain(|_, ids| {
+            ids.remove(id);
+            !ids.is_empty()
+        });
+
+        Some(orphan)
+    }
+}
+
+/// checks that don't need the parents, so the pool can't be filled with garbage
+fn is_well_formed(tx: &Transaction) -> bool {
+    !tx.tx_ins.is_empty()
+        && tx.size() <= MAX_ORPHAN_TX_SIZE
+        && tx.id == tx.get_transaction_id()
+        && tx.tx_ins.iter().all(|tx_in| is_tx_id(&tx_in.tx_out_id))
+}
+
+/// hex encoded sha256
+fn is_tx_id(id: &str) -> bool {
+    id.len() == 64 && id.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::sha256;
+    use crate::script::Script;
+    use crate::transaction::{TxIn, SEQUENCE_FINAL};
+
+    fn tx_id(name: &str) -> String {
+        hex::encode(sha256(name.as_bytes()))
+    }
+
+    /// lock_time tells apart orphans with the same parents
+    fn orphan_tx(parents: &[&str], lock_time: u64) -> Transaction {
+        let mut tx = Transaction {
+            id: String::new(),
+            tx_ins: parents
+                .iter()
+                .map(|parent| TxIn {
+                    tx_out_id: tx_id(parent),
+                    tx_out_index: 0,
+                    script_sig: Script::new(),
+                    sequence: SEQUENCE_FINAL,
+                })
+                .collect(),
+            tx_outs: vec![],
+            lock_time,
+        };
+        tx.id = tx.get_transaction_id();
+
+        tx
+    }
+
+    fn parent_tx_out(parent: &str) -> UnspentTxOut {
+        UnspentTxOut {
+            tx_out_id: tx_id(parent),
+            tx_out_index: 0,
+            address: None,
+            script_pubkey: Script::new(),
+            amount: 10,
+            block_index: 1,
+            timestamp: 0,
+            is_coinbase: false,
+        }
+    }
+
+    #[test]
+    fn test_per_peer_limit() {
+        let mut orphans = OrphanPool::new();
+        let peer: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+
+        for i in 0..MAX_ORPHANS_PER_PEER {
+            let tx = orphan_tx(&["parent"], i as u64);
+            let missing = tx.missing_tx_outs(&[]);
+            assert!(orphans.add(tx, missing, Some(peer)));
+        }
+
+        // reconnecting from another port doesn't give the peer a new quota
+        let reconnected: SocketAddr = "127.0.0.1:5001".parse().unwrap();
+        let tx = orphan_tx(&["parent"], MAX_ORPHANS_PER_PEER as u64);
+        let missing = tx.missing_tx_outs(&[]);
+        assert!(!orphans.add(tx.clone(), missing.clone(), Some(reconnected)));
+        assert!(orphans.add(tx, missing, None));
+        assert_eq!(orphans.len(), MAX_ORPHANS_PER_PEER + 1);
+    }
+
+    #[test]
+    fn test_malformed() {
+        let mut orphans = OrphanPool::new();
+
+        let mut wrong_id = orphan_tx(&["parent"], 0);
+        wrong_id.id = tx_id("other");
+        let no_inputs = orphan_tx(&[], 0);
+        let mut bad_parent = orphan_tx(&["parent"], 0);
+        bad_parent.tx_ins[0].tx_out_id = String::from("parent");
+        bad_parent.id = bad_parent.get_transaction_id();
+        let mut too_big = orphan_tx(&["parent"], 0);
+        too_big.tx_ins[0].script_sig = Script(vec![0; MAX_ORPHAN_TX_SIZE]);
+
+        for tx in [wrong_id, no_inputs, bad_parent, too_big] {
+            let missing = vec![(tx_id("parent"), 0)];
+            assert!(!orphans.add(tx, missing, None));
+        }
+        assert!(orphans.is_empty());
+    }
+
+    #[test]
+    fn test_bounded_and_expiry() {
+        let mut orphans = OrphanPool::new();
+
+        for i in 0..MAX_ORPHAN_TRANSACTIONS + 5 {
+            let tx = orphan_tx(&[&format!("parent{}", i)], 0);
+            let missing = tx.missing_tx_outs(&[]);
+            orphans.add(tx, missing, None);
+        }
+        assert_eq!(orphans.len(), MAX_ORPHAN_TRANSACTIONS);
+        assert_eq!(orphans.by_missing.len(), MAX_ORPHAN_TRANSACTIONS);
+
+        orphans.expire(now() + ORPHAN_EXPIRE_TIME);
+        assert!(orphans.is_empty());
+        assert!(orphans.by_missing.is_empty());
+    }
+
+    #[test]
+    fn test_process_keeps_expiry_of_incomplete_orphan() {
+        let mut orphans = OrphanPool::new();
+        let mut pool = TransactionPool::new();
+
+        let tx = orphan_tx(&["parent a", "parent b"], 0);
+        let id = tx.id.clone();
+        let missing = tx.missing_tx_outs(&[]);
+        orphans.add(tx, missing, None);
+        let expires_at = now() + 5;
+        orphans.orphans.get_mut(&id).unwrap().expires_at = expires_at;
+
+        // only one of the parents arrived
+        let unspent_tx_outs = vec![parent_tx_out("parent a")];
+        assert!(orphans.process(&mut pool, &unspent_tx_outs, 2).is_empty());
+        assert_eq!(orphans.orphans[&id].expires_at, expires_at);
+    }
+
+    #[test]
+    fn test_process_drops_invalid_orphan() {
+        let mut orphans = OrphanPool::new();
+        let mut pool = TransactionPool::new();
+
+        let tx = orphan_tx(&["parent"], 0);
+        let id = tx.id.clone();
+        let missing = tx.missing_tx_outs(&[]);
+        orphans.add(tx, missing, None);
+
+        // the parent arrived but the child isn't signed so it can't go to the pool
+        let unspent_tx_outs = vec![parent_tx_out("parent")];
+        assert!(orphans.process(&mut pool, &unspent_tx_outs, 2).is_empty());
+        assert!(!orphans.contains(&id));
+        assert!(pool.0.is_empty());
+    }
+}
@@ -5,7 +5,9 @@ use mio::net::TcpStream;
 
 use crate::{
     chain::BlockChain,
+    evidence_pool::EvidencePool,
     message::{Message, MessageType},
+    orphan_pool::OrphanPool,
     transaction::UnspentTxOut,
     transaction_pool::TransactionPool,
     validator::Validator,
@@ -17,6 +19,8 @@ where
 {
     pub chain: Arc<RwLock<BlockChain>>,
     pub transaction_pool: Arc<RwLock<TransactionPool>>,
+    pub orphan_pool: Arc<RwLock<OrphanPool>>,
+    pub evidence_pool: Arc<RwLock<EvidencePool>>,
     pub unspent_tx_outs: Arc<RwLock<Vec<UnspentTxOut>>>,
     pub validator: Arc<RwLock<V>>,
 }
@@ -54,16 +58,27 @@ impl<V: Validator + Send + Sync> P2PHandler<V> {
                 msg.handle_blockchain_response::<V>(
                     &mut self.chain.write().unwrap(),
                     &mut self.transaction_pool.write().unwrap(),
+                    &mut self.orphan_pool.write().unwrap(),
+                    &mut self.evidence_pool.write().unwrap(),
                     &mut self.unspent_tx_outs.write().unwrap(),
                     &*self.validator.read().unwrap(),
                 );
             }
             MessageType::ResponseTransactionPool => {
+                let next_index = self.chain.read().unwrap().get_latest().unwrap().index as u64 + 1;
                 msg.handle_transaction_pool_response::<V>(
                     &mut self.transaction_pool.write().unwrap(),
+                    &mut self.orphan_pool.write().unwrap(),
                     &self.unspent_tx_outs.write().unwrap(),
+                    next_index,
+                    connection.peer_addr().ok(),
                 );
             }
+            MessageType::ResponseEvidence => {
+                // the chain is locked before the evidence pool like everywhere else
+                let chain = self.chain.read().unwrap();
+                msg.handle_evidence_response::<V>(&chain, &mut self.evidence_pool.write().unwrap());
+            }
         }
     }
 }
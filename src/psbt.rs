@@ -0,0 +1,231 @@
+use serde::{Deserialize, Serialize};
+
+use std::fmt;
+
+use crate::crypto::PrivateKey;
+use crate::transaction::{Transaction, UnspentTxOut};
+use crate::wallet::Wallet;
+
+pub const PSBT_VERSION: u32 = 1;
+
+#[derive(Debug)]
+pub enum PsbtError {
+    UnsupportedVersion(u32),
+    /// the output spent by the input with this index is unknown
+    MissingInput(usize),
+    /// the output included for the input with this index isn't the one it spends
+    InputMismatch(usize),
+    InvalidId,
+    /// only copies of the same transaction can be combined
+    DifferentTransactions,
+    /// some inputs are still missing signatures
+    Incomplete,
+}
+
+impl fmt::Display for PsbtError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PsbtError::UnsupportedVersion(v) => write!(f, "unsupported psbt version {}", v),
+            PsbtError::MissingInput(i) => write!(f, "the output spent by input {} is unknown", i),
+            PsbtError::InputMismatch(i) => {
+                write!(f, "the output of input {} doesn't match the transaction", i)
+            }
+            PsbtError::InvalidId => write!(f, "invalid transaction id"),
+            PsbtError::DifferentTransactions => write!(f, "the transactions are different"),
+            PsbtError::Incomplete => write!(f, "the transaction isn't fully signed"),
+        }
+    }
+}
+
+impl std::error::Error for PsbtError {}
+
+/// a transaction that still needs signatures, with the outputs its inputs spend so it can be
+/// signed on a machine without the chain. the signatures commit to the amounts and scripts of all
+/// spent outputs, so a signer that is lied to about any of them produces invalid signatures instead
+/// of overpaying fees. inputs signed with SIGHASH_ANYONECANPAY only commit to their own output
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PartiallySignedTransaction {
+    pub version: u32,
+    pub tx: Transaction,
+    /// the outputs spent by the inputs, in the same order
+    pub inputs: Vec<UnspentTxOut>,
+}
+
+impl PartiallySignedTransaction {
+    pub fn new(tx: Transaction, unspent_tx_outs: &[UnspentTxOut]) -> Result<Self, PsbtError> {
+        let inputs = tx
+            .tx_ins
+            .iter()
+            .enumerate()
+            .map(|(i, tx_in)| {
+                unspent_tx_outs
+                    .iter()
+                    .find(|u_tx_out| {
+                        u_tx_out.tx_out_id == tx_in.tx_out_id
+                            && u_tx_out.tx_out_index == tx_in.tx_out_index
+                    })
+                    .cloned()
+                    .ok_or(PsbtError::MissingInput(i))
+            })
+            .collect::<Result<Vec<UnspentTxOut>, PsbtError>>()?;
+
+        Ok(PartiallySignedTransaction {
+            version: PSBT_VERSION,
+            tx,
+            inputs,
+        })
+    }
+
+    /// a container read from somewhere else has to be checked before it is used
+    pub fn check(&self) -> Result<(), PsbtError> {
+        if self.version != PSBT_VERSION {
+            return Err(PsbtError::UnsupportedVersion(self.version));
+        }
+        if self.tx.get_transaction_id() != self.tx.id {
+            return Err(PsbtError::InvalidId);
+        }
+
+        for (i, tx_in) in self.tx.tx_ins.iter().enumerate() {
+            let input = self.inputs.get(i).ok_or(PsbtError::MissingInput(i))?;
+            if input.tx_out_id != tx_in.tx_out_id || input.tx_out_index != tx_in.tx_out_index {
+                return Err(PsbtError::InputMismatch(i));
+            }
+        }
+        if self.inputs.len() != self.tx.tx_ins.len() {
+            return Err(PsbtError::InputMismatch(self.tx.tx_ins.len()));
+        }
+
+        Ok(())
+    }
+
+    /// add the signatures of the keys, returns the number of inputs they signed
+    pub fn sign(&mut self, private_keys: &[PrivateKey], hash_type: u8) -> usize {
+        let (tx, signed) =
+            Wallet::sign_transaction(self.tx.clone(), private_keys, hash_type, &self.inputs);
+        self.tx = tx;
+
+        signed
+    }
+
+    /// merge the signatures of copies that have been signed by different wallets
+    pub fn combine(psbts: &[Self]) -> Result<Self, PsbtError> {
+        let first = psbts.first().ok_or(PsbtError::DifferentTransactions)?;
+        for psbt in psbts {
+            psbt.check()?;
+            if psbt.tx.id != first.tx.id || psbt.inputs != first.inputs {
+                return Err(PsbtError::DifferentTransactions);
+            }
+        }
+
+        let txs: Vec<Transaction> = psbts.iter().map(|psbt| psbt.tx.clone()).collect();
+        let tx = Wallet::combine_transactions(&txs, &first.inputs)
+            .ok_or(PsbtError::DifferentTransactions)?;
+
+        Ok(PartiallySignedTransaction {
+            tx,
+            ..first.clone()
+        })
+    }
+
+    /// every input is signed
+    pub fn is_complete(&self) -> bool {
+        self.tx
+            .tx_ins
+            .iter()
+            .enumerate()
+            .all(|(i, tx_in)| tx_in.validate(&self.tx, i, &self.inputs))
+    }
+
+    /// the transaction ready to be broadcast
+    pub fn finalize(self) -> Result<Transaction, PsbtError> {
+        self.check()?;
+        if !self.is_complete() {
+            return Err(PsbtError::Incomplete);
+        }
+
+        Ok(self.tx)
+    }
+
+    /// what the transaction pays in fees, None if the outputs are worth more than the inputs
+    pub fn fee(&self) -> Option<u64> {
+        self.tx.get_fee(&self.inputs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::KeyPair;
+    use crate::script::Script;
+    use crate::transaction::{TxOut, SIGHASH_ALL};
+
+    #[test]
+    fn test_sign_offline() {
+        let keys: Vec<PrivateKey> = (0..2)
+            .map(|_| KeyPair::generate().unwrap().private_key)
+            .collect();
+        let pub_keys: Vec<Vec<u8>> = keys
+            .iter()
+            .map(|key| KeyPair::public_key_to_bytes(&key.to_public_key().unwrap()).unwrap())
+            .collect();
+        let unspent_tx_outs: Vec<UnspentTxOut> = pub_keys
+            .iter()
+            .enumerate()
+            .map(|(i, pub_key)| UnspentTxOut {
+                tx_out_id: format!("funding{}", i),
+                tx_out_index: 0,
+                address: None,
+                script_pubkey: Script::pay_to_public_key(pub_key),
+                amount: 50,
+                block_index: 1,
+                timestamp: 0,
+                is_coinbase: false,
+            })
+            .collect();
+
+        let mut tx = Transaction {
+            id: String::new(),
+            tx_ins: unspent_tx_outs
+                .iter()
+                .map(UnspentTxOut::to_unsigned_tx_in)
+                .collect(),
+            tx_outs: vec![TxOut {
+                script_pubkey: Script::new(),
+                amount: 99,
+            }],
+            lock_time: 0,
+        };
+        tx.id = tx.get_transaction_id();
+        let psbt = PartiallySignedTransaction::new(tx, &unspent_tx_outs).unwrap();
+        assert_eq!(psbt.fee(), Some(1));
+
+        // the container goes through a file to each signer
+        let exported = serde_json::to_string(&psbt).unwrap();
+        let mut first: PartiallySignedTransaction = serde_json::from_str(&exported).unwrap();
+        first.check().unwrap();
+        assert_eq!(first.sign(&keys[..1], SIGHASH_ALL), 1);
+        assert!(!first.is_complete());
+        assert!(matches!(
+            first.clone().finalize(),
+            Err(PsbtError::Incomplete)
+        ));
+
+        let mut second = psbt.clone();
+        assert_eq!(second.sign(&keys[1..], SIGHASH_ALL), 1);
+
+        // a signer that is lied to about the amount of the other input signs a different digest
+        let mut lied = psbt.clone();
+        lied.inputs[1].amount = 500;
+        assert_eq!(lied.sign(&keys[..1], SIGHASH_ALL), 1);
+        assert!(!lied.tx.tx_ins[0].validate(&lied.tx, 0, &unspent_tx_outs));
+
+        let combined = PartiallySignedTransaction::combine(&[first, second]).unwrap();
+        let tx = combined.finalize().unwrap();
+        assert!(tx.validate(&unspent_tx_outs));
+
+        // the spent outputs have to match the inputs
+        let mut tampered = psbt;
+        tampered.inputs.swap(0, 1);
+        assert!(matches!(tampered.check(), Err(PsbtError::InputMismatch(0))));
+    }
+}
@@ -0,0 +1,1025 @@
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::fmt;
+
+use crate::crypto::{
+    hash160, schnorr_verify, sha256, KeyPair, SchnorrCheck, Signature, SCHNORR_PUBLIC_KEY_SIZE,
+    SCHNORR_SIGNATURE_SIZE,
+};
+use crate::signature_cache::SIGNATURE_CACHE;
+use crate::transaction::{
+    Transaction, UnspentTxOut, LOCKTIME_THRESHOLD, SEQUENCE_LOCKTIME_DISABLE_FLAG,
+    SEQUENCE_LOCKTIME_MASK, SEQUENCE_LOCKTIME_TYPE_FLAG,
+};
+
+// a small subset of the bitcoin script opcodes, with the same values
+pub const OP_0: u8 = 0x00;
+pub const OP_PUSHDATA1: u8 = 0x4c;
+pub const OP_PUSHDATA2: u8 = 0x4d;
+pub const OP_1: u8 = 0x51;
+pub const OP_16: u8 = 0x60;
+pub const OP_IF: u8 = 0x63;
+pub const OP_NOTIF: u8 = 0x64;
+pub const OP_ELSE: u8 = 0x67;
+pub const OP_ENDIF: u8 = 0x68;
+pub const OP_VERIFY: u8 = 0x69;
+pub const OP_RETURN: u8 = 0x6a;
+pub const OP_DROP: u8 = 0x75;
+pub const OP_DUP: u8 = 0x76;
+pub const OP_EQUAL: u8 = 0x87;
+pub const OP_EQUALVERIFY: u8 = 0x88;
+pub const OP_SHA256: u8 = 0xa8;
+pub const OP_HASH160: u8 = 0xa9;
+pub const OP_CHECKSIG: u8 = 0xac;
+pub const OP_CHECKSIGVERIFY: u8 = 0xad;
+pub const OP_CHECKMULTISIG: u8 = 0xae;
+pub const OP_CHECKMULTISIGVERIFY: u8 = 0xaf;
+pub const OP_CHECKLOCKTIMEVERIFY: u8 = 0xb1;
+pub const OP_CHECKSEQUENCEVERIFY: u8 = 0xb2;
+
+pub const MAX_SCRIPT_SIZE: usize = 10_000;
+pub const MAX_ELEMENT_SIZE: usize = 520;
+pub const MAX_STACK_SIZE: usize = 1000;
+pub const MAX_OPS_PER_SCRIPT: usize = 201;
+pub const MAX_PUBKEYS_PER_MULTISIG: usize = 20;
+// numbers on the stack can be at most 5 bytes so lock times fit
+const MAX_NUM_SIZE: usize = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScriptError {
+    ScriptSize,
+    ElementSize,
+    StackSize,
+    OpCount,
+    PubKeyCount,
+    SigCount,
+    BadOpcode(u8),
+    BadPush,
+    InvalidStackOperation,
+    UnbalancedConditional,
+    NumberOverflow,
+    NonMinimalNumber,
+    NegativeLockTime,
+    UnsatisfiedLockTime,
+    OpReturn,
+    Verify,
+    EqualVerify,
+    CheckSigVerify,
+    CheckMultiSigVerify,
+    SigPushOnly,
+    CleanStack,
+    EvalFalse,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "script error: {:?}", self)
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Instruction {
+    PushBytes(Vec<u8>),
+    Op(u8),
+}
+
+/// locking (script_pubkey) or unlocking (script_sig) script
+/// it is serialized as a hex string
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub struct Script(pub Vec<u8>);
+
+impl From<Script> for String {
+    fn from(script: Script) -> Self {
+        script.to_hex()
+    }
+}
+
+impl TryFrom<String> for Script {
+    type Error = hex::FromHexError;
+
+    fn try_from(str: String) -> Result<Self, Self::Error> {
+        Ok(Self(hex::decode(str)?))
+    }
+}
+
+impl Script {
+    pub fn new() -> Self {
+        Script::default()
+    }
+
+    pub fn to_hex(&self) -> String {
+        hex::encode(&self.0)
+    }
+
+    pub fn push_opcode(mut self, op: u8) -> Self {
+        self.0.push(op);
+        self
+    }
+
+    pub fn push_data(mut self, data: &[u8]) -> Self {
+        if data.len() < OP_PUSHDATA1 as usize {
+            self.0.push(data.len() as u8);
+        } else if data.len() <= u8::MAX as usize {
+            self.0.push(OP_PUSHDATA1);
+            self.0.push(data.len() as u8);
+        } else {
+            self.0.push(OP_PUSHDATA2);
+            self.0.extend((data.len() as u16).to_le_bytes());
+        }
+        self.0.extend(data);
+        self
+    }
+
+    pub fn push_int(self, n: i64) -> Self {
+        match n {
+            0 => self.push_opcode(OP_0),
+            1..=16 => self.push_opcode(OP_1 + n as u8 - 1),
+            _ => self.push_data(&encode_num(n)),
+        }
+    }
+
+    /// <pub_key> OP_CHECKSIG
+    pub fn pay_to_public_key(pub_key: &[u8]) -> Self {
+        Script::new().push_data(pub_key).push_opcode(OP_CHECKSIG)
+    }
+
+    /// OP_DUP OP_HASH160 <hash160(pub_key)> OP_EQUALVERIFY OP_CHECKSIG
+    pub fn pay_to_public_key_hash(pub_key_hash: &[u8]) -> Self {
+        Script::new()
+            .push_opcode(OP_DUP)
+            .push_opcode(OP_HASH160)
+            .push_data(pub_key_hash)
+            .push_opcode(OP_EQUALVERIFY)
+            .push_opcode(OP_CHECKSIG)
+    }
+
+    /// <m> <pub_key>... <n> OP_CHECKMULTISIG
+    pub fn multisig(required: usize, pub_keys: &[Vec<u8>]) -> Self {
+        let mut script = Script::new().push_int(required as i64);
+        for pub_key in pub_keys {
+            script = script.push_data(pub_key);
+        }
+        script
+            .push_int(pub_keys.len() as i64)
+            .push_opcode(OP_CHECKMULTISIG)
+    }
+
+    /// OP_SHA256 <hash> OP_EQUALVERIFY <pub_key> OP_CHECKSIG
+    pub fn hash_lock(hash: &[u8], pub_key: &[u8]) -> Self {
+        Script::new()
+            .push_opcode(OP_SHA256)
+            .push_data(hash)
+            .push_opcode(OP_EQUALVERIFY)
+            .push_data(pub_key)
+            .push_opcode(OP_CHECKSIG)
+    }
+
+    /// <lock_time> OP_CHECKLOCKTIMEVERIFY OP_DROP <pub_key> OP_CHECKSIG
+    pub fn time_lock(lock_time: u64, pub_key: &[u8]) -> Self {
+        Script::new()
+            .push_int(lock_time as i64)
+            .push_opcode(OP_CHECKLOCKTIMEVERIFY)
+            .push_opcode(OP_DROP)
+            .push_data(pub_key)
+            .push_opcode(OP_CHECKSIG)
+    }
+
+    /// merge the signatures in the script_sigs of a multisig output into one script_sig
+    /// the signatures are ordered like the public keys and at most the required count is kept
+    pub fn combine_multisig(
+        &self,
+        script_sigs: &[Script],
+        checker: &impl SignatureChecker,
+    ) -> Option<Script> {
+        let (required, pub_keys) = self.as_multisig()?;
+
+        let sigs: Vec<Vec<u8>> = script_sigs
+            .iter()
+            .flat_map(|script_sig| script_sig.instructions().unwrap_or_default())
+            .filter_map(|instruction| match instruction {
+                Instruction::PushBytes(sig) if !sig.is_empty() => Some(sig),
+                _ => None,
+            })
+            .collect();
+
+        let mut script_sig = Script::new();
+        let mut count = 0;
+        for pub_key in pub_keys.iter() {
+            if count == required {
+                break;
+            }
+            if let Some(sig) = sigs.iter().find(|sig| checker.check_sig(sig, pub_key)) {
+                script_sig = script_sig.push_data(sig);
+                count += 1;
+            }
+        }
+
+        Some(script_sig)
+    }
+
+    pub fn instructions(&self) -> Result<Vec<Instruction>, ScriptError> {
+        let bytes = &self.0;
+        let mut instructions = vec![];
+        let mut i = 0;
+
+        while i < bytes.len() {
+            let op = bytes[i];
+            i += 1;
+
+            let len = match op {
+                0x01..=0x4b => op as usize,
+                OP_PUSHDATA1 => {
+                    let len = *bytes.get(i).ok_or(ScriptError::BadPush)? as usize;
+                    i += 1;
+                    len
+                }
+                OP_PUSHDATA2 => {
+                    let len = bytes.get(i..i + 2).ok_or(ScriptError::BadPush)?;
+                    i += 2;
+                    u16::from_le_bytes([len[0], len[1]]) as usize
+                }
+                OP_0 => {
+                    instructions.push(Instruction::PushBytes(vec![]));
+                    continue;
+                }
+                _ => {
+                    instructions.push(Instruction::Op(op));
+                    continue;
+                }
+            };
+
+            let data = bytes.get(i..i + len).ok_or(ScriptError::BadPush)?;
+            instructions.push(Instruction::PushBytes(data.to_vec()));
+            i += len;
+        }
+
+        Ok(instructions)
+    }
+
+    /// OP_1 to OP_16 count as pushes too
+    pub fn is_push_only(&self) -> bool {
+        self.instructions().is_ok_and(|instructions| {
+            instructions.iter().all(|instruction| match instruction {
+                Instruction::PushBytes(_) => true,
+                Instruction::Op(op) => (OP_1..=OP_16).contains(op),
+            })
+        })
+    }
+
+    /// the public key of a pay to public key script
+    pub fn as_public_key(&self) -> Option<Vec<u8>> {
+        match self.instructions().ok()?.as_slice() {
+            [Instruction::PushBytes(pub_key), Instruction::Op(OP_CHECKSIG)] => {
+                Some(pub_key.clone())
+            }
+            _ => None,
+        }
+    }
+
+    /// the public key hash of a pay to public key hash script
+    pub fn as_public_key_hash(&self) -> Option<Vec<u8>> {
+        match self.instructions().ok()?.as_slice() {
+            [Instruction::Op(OP_DUP), Instruction::Op(OP_HASH160), Instruction::PushBytes(hash), Instruction::Op(OP_EQUALVERIFY), Instruction::Op(OP_CHECKSIG)] => {
+                Some(hash.clone())
+            }
+            _ => None,
+        }
+    }
+
+    /// the required signature count and public keys of a multisig script
+    pub fn as_multisig(&self) -> Option<(usize, Vec<Vec<u8>>)> {
+        let instructions = self.instructions().ok()?;
+        let (first, rest) = instructions.split_first()?;
+        let (last, rest) = rest.split_last()?;
+        let (n, keys) = rest.split_last()?;
+
+        let required = small_int(first)?;
+        let n = small_int(n)?;
+        if *last != Instruction::Op(OP_CHECKMULTISIG) || keys.len() != n || required > n {
+            return None;
+        }
+
+        let pub_keys = keys
+            .iter()
+            .map(|key| match key {
+                Instruction::PushBytes(key) => Some(key.clone()),
+                Instruction::Op(_) => None,
+            })
+            .collect::<Option<Vec<Vec<u8>>>>()?;
+
+        Some((required, pub_keys))
+    }
+}
+
+fn small_int(instruction: &Instruction) -> Option<usize> {
+    match instruction {
+        Instruction::Op(op) if (OP_1..=OP_16).contains(op) => Some((op - OP_1 + 1) as usize),
+        _ => None,
+    }
+}
+
+/// checks the interpreter can't do on its own because they need the spending transaction
+pub trait SignatureChecker {
+    fn check_sig(&self, sig: &[u8], pub_key: &[u8]) -> bool;
+    /// a check that fails the script if it is false, so the result doesn't change what the
+    /// script does. a checker may assume it passes and verify it later
+    fn check_sig_verify(&self, sig: &[u8], pub_key: &[u8]) -> bool {
+        self.check_sig(sig, pub_key)
+    }
+    fn check_lock_time(&self, lock_time: u64) -> bool;
+    fn check_sequence(&self, sequence: u32) -> bool;
+}
+
+/// checks signatures and locks against an input of a transaction
+/// unspent_tx_outs contains the outputs spent by the inputs of the transaction
+pub struct TransactionSignatureChecker<'a> {
+    pub transaction: &'a Transaction,
+    pub tx_in_index: usize,
+    pub unspent_tx_outs: &'a [UnspentTxOut],
+}
+
+impl TransactionSignatureChecker<'_> {
+    /// the digest that sig commits to and sig without the trailing sighash type byte
+    fn split_sig<'s>(&self, sig: &'s [u8]) -> Option<(Vec<u8>, &'s [u8])> {
+        let (hash_type, raw_sig) = sig.split_last()?;
+        let sighash =
+            self.transaction
+                .signature_hash(self.tx_in_index, self.unspent_tx_outs, *hash_type)?;
+
+        Some((sighash, raw_sig))
+    }
+}
+
+/// 32 byte x-only keys are verified with Schnorr and the SEC1 ones with ECDSA
+pub fn verify_signature(digest: &[u8], sig: &[u8], pub_key: &[u8]) -> bool {
+    if pub_key.len() == SCHNORR_PUBLIC_KEY_SIZE {
+        return schnorr_verify(digest, sig, pub_key).unwrap_or(false);
+    }
+
+    if !KeyPair::is_valid_public_key_encoding(pub_key) {
+        return false;
+    }
+    let public_key = match KeyPair::public_key_from_bytes(pub_key) {
+        Ok(public_key) => public_key,
+        Err(_) => return false,
+    };
+    let signature = match Signature::from_der(sig) {
+        Ok(signature) => signature,
+        Err(_) => return false,
+    };
+
+    signature.verify(digest, &public_key).unwrap_or(false)
+}
+
+impl SignatureChecker for TransactionSignatureChecker<'_> {
+    /// sig is a DER or BIP-340 signature followed by the sighash type byte
+    /// valid signatures are remembered in the signature cache
+    fn check_sig(&self, sig: &[u8], pub_key: &[u8]) -> bool {
+        let (sighash, raw_sig) = match self.split_sig(sig) {
+            Some(split) => split,
+            None => return false,
+        };
+
+        if SIGNATURE_CACHE
+            .read()
+            .unwrap()
+            .contains(&sighash, raw_sig, pub_key)
+        {
+            return true;
+        }
+
+        let valid = verify_signature(&sighash, raw_sig, pub_key);
+        if valid {
+            SIGNATURE_CACHE
+                .write()
+                .unwrap()
+                .insert(&sighash, raw_sig, pub_key);
+        }
+
+        valid
+    }
+
+    fn check_lock_time(&self, lock_time: u64) -> bool {
+        let tx_lock_time = self.transaction.lock_time;
+
+        // both have to be block indexes or both have to be timestamps
+        if (tx_lock_time < LOCKTIME_THRESHOLD) != (lock_time < LOCKTIME_THRESHOLD) {
+            return false;
+        }
+
+        lock_time <= tx_lock_time
+    }
+
+    fn check_sequence(&self, sequence: u32) -> bool {
+        let tx_sequence = self.transaction.tx_ins[self.tx_in_index].sequence;
+
+        if tx_sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 {
+            return false;
+        }
+
+        if (tx_sequence & SEQUENCE_LOCKTIME_TYPE_FLAG) != (sequence & SEQUENCE_LOCKTIME_TYPE_FLAG) {
+            return false;
+        }
+
+        sequence & SEQUENCE_LOCKTIME_MASK <= tx_sequence & SEQUENCE_LOCKTIME_MASK
+    }
+}
+
+/// checks like TransactionSignatureChecker but Schnorr signatures of check_sig_verify that aren't
+/// cached are assumed to be valid and collected in deferred, the spend is only valid if they pass
+/// schnorr_batch_verify. check_sig is verified right away, its result can pick a branch
+pub struct BatchSignatureChecker<'a> {
+    pub checker: TransactionSignatureChecker<'a>,
+    pub deferred: RefCell<Vec<SchnorrCheck>>,
+}
+
+impl<'a> BatchSignatureChecker<'a> {
+    pub fn new(checker: TransactionSignatureChecker<'a>) -> Self {
+        BatchSignatureChecker {
+            checker,
+            deferred: RefCell::new(vec![]),
+        }
+    }
+}
+
+impl SignatureChecker for BatchSignatureChecker<'_> {
+    fn check_sig(&self, sig: &[u8], pub_key: &[u8]) -> bool {
+        self.checker.check_sig(sig, pub_key)
+    }
+
+    fn check_sig_verify(&self, sig: &[u8], pub_key: &[u8]) -> bool {
+        if pub_key.len() != SCHNORR_PUBLIC_KEY_SIZE {
+            return self.checker.check_sig(sig, pub_key);
+        }
+
+        let (sighash, raw_sig) = match self.checker.split_sig(sig) {
+            Some(split) => split,
+            None => return false,
+        };
+        if raw_sig.len() != SCHNORR_SIGNATURE_SIZE {
+            return false;
+        }
+
+        if !SIGNATURE_CACHE
+            .read()
+            .unwrap()
+            .contains(&sighash, raw_sig, pub_key)
+        {
+            self.deferred.borrow_mut().push(SchnorrCheck {
+                digest: sighash,
+                signature: raw_sig.to_vec(),
+                public_key: pub_key.to_vec(),
+            });
+        }
+
+        true
+    }
+
+    fn check_lock_time(&self, lock_time: u64) -> bool {
+        self.checker.check_lock_time(lock_time)
+    }
+
+    fn check_sequence(&self, sequence: u32) -> bool {
+        self.checker.check_sequence(sequence)
+    }
+}
+
+/// run script_sig then script_pubkey on the same stack, the spend is valid if it leaves only true
+pub fn verify_script(
+    script_sig: &Script,
+    script_pubkey: &Script,
+    checker: &impl SignatureChecker,
+) -> Result<(), ScriptError> {
+    if !script_sig.is_push_only() {
+        return Err(ScriptError::SigPushOnly);
+    }
+
+    let mut stack = vec![];
+    eval_script(script_sig, &mut stack, checker, false)?;
+    eval_script(script_pubkey, &mut stack, checker, true)?;
+
+    match stack.as_slice() {
+        [top] if cast_to_bool(top) => Ok(()),
+        [.., top] if cast_to_bool(top) => Err(ScriptError::CleanStack),
+        _ => Err(ScriptError::EvalFalse),
+    }
+}
+
+/// is_last is set for the script whose result decides the spend, an OP_CHECKSIG at its end
+/// fails the spend if it is false just like an OP_CHECKSIGVERIFY
+pub fn eval_script(
+    script: &Script,
+    stack: &mut Vec<Vec<u8>>,
+    checker: &impl SignatureChecker,
+    is_last: bool,
+) -> Result<(), ScriptError> {
+    if script.0.len() > MAX_SCRIPT_SIZE {
+        return Err(ScriptError::ScriptSize);
+    }
+
+    // the branches of the OP_IFs we are in, only executing if all of them are true
+    let mut exec_stack: Vec<bool> = vec![];
+    let mut op_count = 0;
+
+    let mut instructions = script.instructions()?.into_iter().peekable();
+    while let Some(instruction) = instructions.next() {
+        let executing = exec_stack.iter().all(|&b| b);
+
+        let op = match instruction {
+            Instruction::PushBytes(data) => {
+                if data.len() > MAX_ELEMENT_SIZE {
+                    return Err(ScriptError::ElementSize);
+                }
+                if executing {
+                    stack.push(data);
+                }
+                continue;
+            }
+            Instruction::Op(op) => op,
+        };
+
+        if op > OP_16 {
+            op_count += 1;
+            if op_count > MAX_OPS_PER_SCRIPT {
+                return Err(ScriptError::OpCount);
+            }
+        }
+
+        match op {
+            OP_IF | OP_NOTIF => {
+                let mut value = false;
+                if executing {
+                    value = cast_to_bool(&pop(stack)?);
+                    if op == OP_NOTIF {
+                        value = !value;
+                    }
+                }
+                exec_stack.push(value);
+            }
+            OP_ELSE => {
+                let last = exec_stack
+                    .last_mut()
+                    .ok_or(ScriptError::UnbalancedConditional)?;
+                *last = !*last;
+            }
+            OP_ENDIF => {
+                exec_stack.pop().ok_or(ScriptError::UnbalancedConditional)?;
+            }
+            _ if !executing => {}
+            OP_1..=OP_16 => stack.push(encode_num((op - OP_1 + 1) as i64)),
+            OP_VERIFY => {
+                if !cast_to_bool(&pop(stack)?) {
+                    return Err(ScriptError::Verify);
+                }
+            }
+            OP_RETURN => return Err(ScriptError::OpReturn),
+            OP_DROP => {
+                pop(stack)?;
+            }
+            OP_DUP => {
+                let top = stack
+                    .last()
+                    .ok_or(ScriptError::InvalidStackOperation)?
+                    .clone();
+                stack.push(top);
+            }
+            OP_EQUAL | OP_EQUALVERIFY => {
+                let a = pop(stack)?;
+                let b = pop(stack)?;
+                if op == OP_EQUALVERIFY {
+                    if a != b {
+                        return Err(ScriptError::EqualVerify);
+                    }
+                } else {
+                    stack.push(encode_bool(a == b));
+                }
+            }
+            OP_SHA256 => {
+                let top = pop(stack)?;
+                stack.push(sha256(&top));
+            }
+            OP_HASH160 => {
+                let top = pop(stack)?;
+                stack.push(hash160(&top));
+            }
+            OP_CHECKSIG | OP_CHECKSIGVERIFY => {
+                let pub_key = pop(stack)?;
+                let sig = pop(stack)?;
+                let verified = op == OP_CHECKSIGVERIFY
+                    || match instructions.peek() {
+                        Some(next) => *next == Instruction::Op(OP_VERIFY),
+                        None => is_last,
+                    };
+                let ok = if verified {
+                    checker.check_sig_verify(&sig, &pub_key)
+                } else {
+                    checker.check_sig(&sig, &pub_key)
+                };
+
+                if op == OP_CHECKSIGVERIFY {
+                    if !ok {
+                        return Err(ScriptError::CheckSigVerify);
+                    }
+                } else {
+                    stack.push(encode_bool(ok));
+                }
+            }
+            OP_CHECKMULTISIG | OP_CHECKMULTISIGVERIFY => {
+                let ok = check_multisig(stack, checker, &mut op_count)?;
+
+                if op == OP_CHECKMULTISIGVERIFY {
+                    if !ok {
+                        return Err(ScriptError::CheckMultiSigVerify);
+                    }
+                } else {
+                    stack.push(encode_bool(ok));
+                }
+            }
+            OP_CHECKLOCKTIMEVERIFY => {
+                let lock_time =
+                    decode_num(stack.last().ok_or(ScriptError::InvalidStackOperation)?)?;
+                if lock_time < 0 {
+                    return Err(ScriptError::NegativeLockTime);
+                }
+                if !checker.check_lock_time(lock_time as u64) {
+                    return Err(ScriptError::UnsatisfiedLockTime);
+                }
+            }
+            OP_CHECKSEQUENCEVERIFY => {
+                let sequence = decode_num(stack.last().ok_or(ScriptError::InvalidStackOperation)?)?;
+                if sequence < 0 {
+                    return Err(ScriptError::NegativeLockTime);
+                }
+                // if the disable flag is set it behaves as a no-op
+                let sequence = u32::try_from(sequence).map_err(|_| ScriptError::NumberOverflow)?;
+                if sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG == 0
+                    && !checker.check_sequence(sequence)
+                {
+                    return Err(ScriptError::UnsatisfiedLockTime);
+                }
+            }
+            op => return Err(ScriptError::BadOpcode(op)),
+        }
+
+        if stack.len() > MAX_STACK_SIZE {
+            return Err(ScriptError::StackSize);
+        }
+    }
+
+    if !exec_stack.is_empty() {
+        return Err(ScriptError::UnbalancedConditional);
+    }
+
+    Ok(())
+}
+
+/// pops <n> <pub_key>... <m> <sig>... and checks that the signatures are in the same order as the keys
+fn check_multisig(
+    stack: &mut Vec<Vec<u8>>,
+    checker: &impl SignatureChecker,
+    op_count: &mut usize,
+) -> Result<bool, ScriptError> {
+    let key_count = decode_num(&pop(stack)?)?;
+    if key_count < 0 || key_count as usize > MAX_PUBKEYS_PER_MULTISIG {
+        return Err(ScriptError::PubKeyCount);
+    }
+    *op_count += key_count as usize;
+    if *op_count > MAX_OPS_PER_SCRIPT {
+        return Err(ScriptError::OpCount);
+    }
+    let mut pub_keys = (0..key_count)
+        .map(|_| pop(stack))
+        .collect::<Result<Vec<Vec<u8>>, ScriptError>>()?;
+    pub_keys.reverse();
+
+    let sig_count = decode_num(&pop(stack)?)?;
+    if sig_count < 0 || sig_count > key_count {
+        return Err(ScriptError::SigCount);
+    }
+    let mut sigs = (0..sig_count)
+        .map(|_| pop(stack))
+        .collect::<Result<Vec<Vec<u8>>, ScriptError>>()?;
+    sigs.reverse();
+
+    let mut keys = pub_keys.iter();
+    for sig in sigs.iter() {
+        // the keys that are skipped can't be used by the following signatures
+        if !keys.any(|pub_key| checker.check_sig(sig, pub_key)) {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+fn pop(stack: &mut Vec<Vec<u8>>) -> Result<Vec<u8>, ScriptError> {
+    stack.pop().ok_or(ScriptError::InvalidStackOperation)
+}
+
+fn cast_to_bool(data: &[u8]) -> bool {
+    for (i, byte) in data.iter().enumerate() {
+        if *byte != 0 {
+            // negative zero is false
+            return !(i == data.len() - 1 && *byte == 0x80);
+        }
+    }
+    false
+}
+
+fn encode_bool(value: bool) -> Vec<u8> {
+    if value {
+        vec![1]
+    } else {
+        vec![]
+    }
+}
+
+/// numbers are little endian with the sign in the highest bit of the last byte
+pub fn encode_num(value: i64) -> Vec<u8> {
+    if value == 0 {
+        return vec![];
+    }
+
+    let negative = value < 0;
+    let mut abs = value.unsigned_abs();
+    let mut result = vec![];
+    while abs > 0 {
+        result.push((abs & 0xff) as u8);
+        abs >>= 8;
+    }
+
+    if result.last().unwrap() & 0x80 != 0 {
+        result.push(if negative { 0x80 } else { 0 });
+    } else if negative {
+        *result.last_mut().unwrap() |= 0x80;
+    }
+
+    result
+}
+
+/// only the shortest encoding of a number is accepted, so a script can't be changed without
+/// changing what it does
+pub fn decode_num(data: &[u8]) -> Result<i64, ScriptError> {
+    if data.len() > MAX_NUM_SIZE {
+        return Err(ScriptError::NumberOverflow);
+    }
+
+    // the last byte can only be empty apart from the sign if the byte before needs the sign bit
+    if let Some(last) = data.last() {
+        if last & 0x7f == 0 && (data.len() == 1 || data[data.len() - 2] & 0x80 == 0) {
+            return Err(ScriptError::NonMinimalNumber);
+        }
+    }
+
+    let mut result: i64 = 0;
+    for (i, byte) in data.iter().enumerate() {
+        result |= (*byte as i64) << (8 * i);
+    }
+
+    match data.last() {
+        Some(last) if last & 0x80 != 0 => Ok(-(result & !(0x80 << (8 * (data.len() - 1))))),
+        _ => Ok(result),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// accepts signatures that are equal to the key so scripts can be tested without real signatures
+    struct MockChecker {
+        lock_time: u64,
+        sequence: u32,
+    }
+
+    impl SignatureChecker for MockChecker {
+        fn check_sig(&self, sig: &[u8], pub_key: &[u8]) -> bool {
+            sig == pub_key
+        }
+
+        fn check_lock_time(&self, lock_time: u64) -> bool {
+            lock_time <= self.lock_time
+        }
+
+        fn check_sequence(&self, sequence: u32) -> bool {
+            sequence <= self.sequence
+        }
+    }
+
+    const CHECKER: MockChecker = MockChecker {
+        lock_time: 100,
+        sequence: 10,
+    };
+
+    #[test]
+    fn test_num_encode_decode() {
+        for n in [
+            0,
+            1,
+            -1,
+            127,
+            128,
+            -128,
+            255,
+            256,
+            500_000_000,
+            -500_000_000,
+        ] {
+            assert_eq!(decode_num(&encode_num(n)).unwrap(), n);
+        }
+        assert_eq!(encode_num(128), vec![0x80, 0x00]);
+        assert_eq!(encode_num(-1), vec![0x81]);
+        assert!(decode_num(&[1, 2, 3, 4, 5, 6]).is_err());
+        // zero is the empty array and padding isn't allowed
+        for non_minimal in [&[0][..], &[0x80], &[1, 0], &[1, 0x80], &[0xff, 0, 0]] {
+            assert_eq!(decode_num(non_minimal), Err(ScriptError::NonMinimalNumber));
+        }
+        assert_eq!(decode_num(&[0xff, 0]), Ok(255));
+        assert_eq!(decode_num(&[0xff, 0x80]), Ok(-255));
+    }
+
+    #[test]
+    fn test_pay_to_public_key_hash() {
+        let pub_key = vec![2; 33];
+        let script_pubkey = Script::pay_to_public_key_hash(&hash160(&pub_key));
+        assert_eq!(script_pubkey.as_public_key_hash(), Some(hash160(&pub_key)));
+
+        let script_sig = Script::new().push_data(&pub_key).push_data(&pub_key);
+        assert_eq!(verify_script(&script_sig, &script_pubkey, &CHECKER), Ok(()));
+
+        let wrong_key = vec![3; 33];
+        let script_sig = Script::new().push_data(&wrong_key).push_data(&wrong_key);
+        assert_eq!(
+            verify_script(&script_sig, &script_pubkey, &CHECKER),
+            Err(ScriptError::EqualVerify)
+        );
+    }
+
+    #[test]
+    fn test_multisig() {
+        let keys = vec![vec![1; 33], vec![2; 33], vec![3; 33]];
+        let script_pubkey = Script::multisig(2, &keys);
+        assert_eq!(script_pubkey.as_multisig(), Some((2, keys.clone())));
+
+        let script_sig = Script::new().push_data(&keys[0]).push_data(&keys[2]);
+        assert_eq!(verify_script(&script_sig, &script_pubkey, &CHECKER), Ok(()));
+
+        // signatures have to be in the order of the keys
+        let script_sig = Script::new().push_data(&keys[2]).push_data(&keys[0]);
+        assert_eq!(
+            verify_script(&script_sig, &script_pubkey, &CHECKER),
+            Err(ScriptError::EvalFalse)
+        );
+
+        let script_sig = Script::new().push_data(&keys[0]);
+        assert!(verify_script(&script_sig, &script_pubkey, &CHECKER).is_err());
+    }
+
+    #[test]
+    fn test_combine_multisig() {
+        let keys = vec![vec![1; 33], vec![2; 33], vec![3; 33]];
+        let script_pubkey = Script::multisig(2, &keys);
+
+        let first = Script::new().push_data(&keys[2]);
+        let second = Script::new().push_data(&keys[0]);
+        let unrelated = Script::new().push_data(&[4; 33]);
+
+        let combined = script_pubkey
+            .combine_multisig(&[first.clone(), unrelated, second.clone()], &CHECKER)
+            .unwrap();
+        assert_eq!(
+            combined,
+            Script::new().push_data(&keys[0]).push_data(&keys[2])
+        );
+        assert_eq!(verify_script(&combined, &script_pubkey, &CHECKER), Ok(()));
+
+        // a single signature is not enough
+        let partial = script_pubkey.combine_multisig(&[first], &CHECKER).unwrap();
+        assert!(verify_script(&partial, &script_pubkey, &CHECKER).is_err());
+
+        assert!(Script::pay_to_public_key(&keys[0])
+            .combine_multisig(&[second], &CHECKER)
+            .is_none());
+    }
+
+    #[test]
+    fn test_hash_lock() {
+        let pub_key = vec![2; 33];
+        let script_pubkey = Script::hash_lock(&sha256(b"secret"), &pub_key);
+
+        let script_sig = Script::new().push_data(&pub_key).push_data(b"secret");
+        assert_eq!(verify_script(&script_sig, &script_pubkey, &CHECKER), Ok(()));
+
+        let script_sig = Script::new().push_data(&pub_key).push_data(b"guess");
+        assert_eq!(
+            verify_script(&script_sig, &script_pubkey, &CHECKER),
+            Err(ScriptError::EqualVerify)
+        );
+    }
+
+    #[test]
+    fn test_time_locks() {
+        let pub_key = vec![2; 33];
+        let script_sig = Script::new().push_data(&pub_key);
+
+        let script_pubkey = Script::time_lock(100, &pub_key);
+        assert_eq!(verify_script(&script_sig, &script_pubkey, &CHECKER), Ok(()));
+
+        let script_pubkey = Script::time_lock(101, &pub_key);
+        assert_eq!(
+            verify_script(&script_sig, &script_pubkey, &CHECKER),
+            Err(ScriptError::UnsatisfiedLockTime)
+        );
+
+        let script_pubkey = Script::new()
+            .push_int(11)
+            .push_opcode(OP_CHECKSEQUENCEVERIFY)
+            .push_opcode(OP_DROP)
+            .push_data(&pub_key)
+            .push_opcode(OP_CHECKSIG);
+        assert_eq!(
+            verify_script(&script_sig, &script_pubkey, &CHECKER),
+            Err(ScriptError::UnsatisfiedLockTime)
+        );
+
+        // a sequence doesn't get truncated to its lower 32 bits
+        let csv = |sequence: Script| {
+            let script_pubkey = Script(
+                [
+                    sequence.0,
+                    Script::new()
+                        .push_opcode(OP_CHECKSEQUENCEVERIFY)
+                        .push_opcode(OP_DROP)
+                        .push_data(&pub_key)
+                        .push_opcode(OP_CHECKSIG)
+                        .0,
+                ]
+                .concat(),
+            );
+            verify_script(&script_sig, &script_pubkey, &CHECKER)
+        };
+        assert_eq!(
+            csv(Script::new().push_int(1 << 32)),
+            Err(ScriptError::NumberOverflow)
+        );
+        assert_eq!(
+            csv(Script::new().push_data(&[10, 0])),
+            Err(ScriptError::NonMinimalNumber)
+        );
+        assert_eq!(csv(Script::new().push_int(10)), Ok(()));
+    }
+
+    #[test]
+    fn test_if_else() {
+        // OP_IF <a> OP_ELSE <b> OP_ENDIF OP_CHECKSIG
+        let (a, b) = (vec![1; 33], vec![2; 33]);
+        let script_pubkey = Script::new()
+            .push_opcode(OP_IF)
+            .push_data(&a)
+            .push_opcode(OP_ELSE)
+            .push_data(&b)
+            .push_opcode(OP_ENDIF)
+            .push_opcode(OP_CHECKSIG);
+
+        let script_sig = Script::new().push_data(&a).push_int(1);
+        assert_eq!(verify_script(&script_sig, &script_pubkey, &CHECKER), Ok(()));
+
+        let script_sig = Script::new().push_data(&b).push_int(0);
+        assert_eq!(verify_script(&script_sig, &script_pubkey, &CHECKER), Ok(()));
+
+        let script_sig = Script::new().push_data(&a).push_int(0);
+        assert_eq!(
+            verify_script(&script_sig, &script_pubkey, &CHECKER),
+            Err(ScriptError::EvalFalse)
+        );
+
+        let unbalanced = Script::new().push_int(1).push_opcode(OP_IF);
+        assert_eq!(
+            verify_script(&Script::new(), &unbalanced, &CHECKER),
+            Err(ScriptError::UnbalancedConditional)
+        );
+    }
+
+    #[test]
+    fn test_script_sig_must_be_push_only() {
+        let script_sig = Script::new().push_int(1).push_opcode(OP_DUP);
+        let script_pubkey = Script::new().push_opcode(OP_DROP);
+        assert_eq!(
+            verify_script(&script_sig, &script_pubkey, &CHECKER),
+            Err(ScriptError::SigPushOnly)
+        );
+    }
+
+    #[test]
+    fn test_serialize() {
+        let script = Script::pay_to_public_key(&[2; 33]);
+        let json = serde_json::to_string(&script).unwrap();
+        assert_eq!(json, format!("\"21{}ac\"", "02".repeat(33)));
+        assert_eq!(serde_json::from_str::<Script>(&json).unwrap(), script);
+
+        assert!(Script(vec![OP_PUSHDATA1, 10, 1]).instructions().is_err());
+    }
+}
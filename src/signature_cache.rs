@@ -0,0 +1,93 @@
+use once_cell::sync::Lazy;
+use std::collections::{HashSet, VecDeque};
+use std::sync::RwLock;
+
+use crate::crypto::sha256;
+
+pub const MAX_SIGNATURE_CACHE_SIZE: usize = 50_000;
+
+/// signatures that have already been verified, so transactions that were checked when they
+/// entered the pool don't have to be checked again when they arrive in a block
+pub static SIGNATURE_CACHE: Lazy<RwLock<SignatureCache>> =
+    Lazy::new(|| RwLock::new(SignatureCache::new(MAX_SIGNATURE_CACHE_SIZE)));
+
+/// bounded set of valid (digest, signature, public key) triples, the oldest entries are evicted first
+#[derive(Debug)]
+pub struct SignatureCache {
+    entries: HashSet<Vec<u8>>,
+    order: VecDeque<Vec<u8>>,
+    max_size: usize,
+}
+
+impl SignatureCache {
+    pub fn new(max_size: usize) -> Self {
+        SignatureCache {
+            entries: HashSet::new(),
+            order: VecDeque::new(),
+            max_size,
+        }
+    }
+
+    pub fn contains(&self, digest: &[u8], signature: &[u8], public_key: &[u8]) -> bool {
+        self.entries
+            .contains(&Self::key(digest, signature, public_key))
+    }
+
+    /// only call this for signatures that have been verified
+    pub fn insert(&mut self, digest: &[u8], signature: &[u8], public_key: &[u8]) {
+        let key = Self::key(digest, signature, public_key);
+        if self.entries.contains(&key) {
+            return;
+        }
+
+        if self.entries.len() >= self.max_size {
+            if let Some(oldest) = self.order.pop_front() {
+                self.entries.remove(&oldest);
+            }
+        }
+
+        self.entries.insert(key.clone());
+        self.order.push_back(key);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn key(digest: &[u8], signature: &[u8], public_key: &[u8]) -> Vec<u8> {
+        let mut data = vec![];
+        for part in [digest, signature, public_key] {
+            data.extend((part.len() as u64).to_be_bytes());
+            data.extend(part);
+        }
+
+        sha256(&data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bounded_cache() {
+        let mut cache = SignatureCache::new(2);
+
+        cache.insert(b"digest", b"sig1", b"key");
+        cache.insert(b"digest", b"sig2", b"key");
+        cache.insert(b"digest", b"sig2", b"key");
+        assert_eq!(cache.len(), 2);
+        assert!(cache.contains(b"digest", b"sig1", b"key"));
+        assert!(!cache.contains(b"digest", b"sig1", b"other key"));
+
+        // the oldest entry makes room for the new one
+        cache.insert(b"digest", b"sig3", b"key");
+        assert_eq!(cache.len(), 2);
+        assert!(!cache.contains(b"digest", b"sig1", b"key"));
+        assert!(cache.contains(b"digest", b"sig3", b"key"));
+    }
+}
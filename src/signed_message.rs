@@ -0,0 +1,106 @@
+use crate::address::{Address, SCHNORR_ADDRESS_VERSION};
+use crate::crypto::{
+    tagged_hash, CryptoError, KeyPair, PrivateKey, Signature, COMPRESSED_PUBLIC_KEY_SIZE,
+};
+use crate::script::verify_signature;
+
+/// messages are hashed under their own tag, so a signed message can never be a valid signature
+/// of a transaction or anything else the keys sign
+const MESSAGE_TAG: &[u8] = b"naivecoin/message";
+
+/// tagged hash of the length prefixed message
+pub fn message_digest(message: &[u8]) -> Vec<u8> {
+    let mut data = (message.len() as u64).to_be_bytes().to_vec();
+    data.extend(message);
+
+    tagged_hash(MESSAGE_TAG, &data)
+}
+
+/// prove that the owner of the address wrote the message, hex encoded
+pub fn sign_message(
+    private_key: &PrivateKey,
+    address: &Address,
+    message: &[u8],
+) -> Result<String, CryptoError> {
+    sign_digest(private_key, address, &message_digest(message))
+}
+
+pub fn verify_message(address: &Address, message: &[u8], signature: &str) -> bool {
+    verify_digest(address, &message_digest(message), signature)
+}
+
+/// sign for an address. a P-256 address is a hash of the public key, so the key comes before the
+/// DER signature. a Schnorr address is the key itself and only the signature is needed
+pub fn sign_digest(
+    private_key: &PrivateKey,
+    address: &Address,
+    digest: &[u8],
+) -> Result<String, CryptoError> {
+    if address.version() == SCHNORR_ADDRESS_VERSION {
+        return Ok(hex::encode(private_key.to_schnorr_key()?.sign(digest)?));
+    }
+
+    let mut signature = KeyPair::public_key_to_bytes(&private_key.to_public_key()?)?;
+    signature.extend(Signature::from_sign(digest, private_key)?.to_der()?);
+
+    Ok(hex::encode(signature))
+}
+
+pub fn verify_digest(address: &Address, digest: &[u8], signature: &str) -> bool {
+    let signature = match hex::decode(signature) {
+        Ok(signature) => signature,
+        Err(_) => return false,
+    };
+
+    if address.version() == SCHNORR_ADDRESS_VERSION {
+        return verify_signature(digest, &signature, address.payload());
+    }
+
+    if signature.len() <= COMPRESSED_PUBLIC_KEY_SIZE {
+        return false;
+    }
+    let (public_key, signature) = signature.split_at(COMPRESSED_PUBLIC_KEY_SIZE);
+
+    Address::from_public_key_bytes(public_key) == *address
+        && verify_signature(digest, signature, public_key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::sha256;
+
+    #[test]
+    fn test_sign_and_verify_message() {
+        let key = KeyPair::generate().unwrap().private_key;
+        let address = Address::from_public_key(&key.to_public_key().unwrap()).unwrap();
+        let schnorr_address =
+            Address::from_schnorr_key(&key.to_schnorr_key().unwrap().public_key()).unwrap();
+        let other = KeyPair::generate().unwrap().private_key;
+        let other_address = Address::from_public_key(&other.to_public_key().unwrap()).unwrap();
+
+        for address in [&address, &schnorr_address] {
+            let signature = sign_message(&key, address, b"I own this address").unwrap();
+            assert!(verify_message(address, b"I own this address", &signature));
+            assert!(!verify_message(address, b"I own that address", &signature));
+            assert!(!verify_message(
+                &other_address,
+                b"I own this address",
+                &signature
+            ));
+        }
+
+        // someone else's key doesn't prove anything about the address
+        let forged = sign_message(&other, &address, b"I own this address").unwrap();
+        assert!(!verify_message(&address, b"I own this address", &forged));
+        assert!(!verify_message(&address, b"I own this address", "zz"));
+    }
+
+    #[test]
+    fn test_domain_separation() {
+        // a message that happens to be a transaction digest doesn't sign the transaction
+        let sighash = sha256(b"transaction");
+        assert_ne!(message_digest(&sighash), sighash);
+        assert_ne!(message_digest(b"ab"), message_digest(b"a"));
+    }
+}
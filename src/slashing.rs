@@ -0,0 +1,255 @@
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    address::Address,
+    block::{Block, SignedHeader},
+    chain::BlockChain,
+    crypto::sha256,
+    transaction::UnspentTxOut,
+};
+
+/// bounds the work a block can put on validators
+pub const MAX_EVIDENCE_PER_BLOCK: usize = 10;
+/// in blocks, older evidence can't be put in a block anymore
+pub const EVIDENCE_MAX_AGE: u32 = 100;
+
+/// two different blocks at the same height signed by the same miner. only one of them can end up
+/// in the chain, so signing both is trying to win on every branch at no cost
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct DoubleSignEvidence {
+    pub first: SignedHeader,
+    pub second: SignedHeader,
+}
+
+impl DoubleSignEvidence {
+    /// the headers are ordered by hash, so both ways of reporting a pair give the same evidence
+    pub fn new(a: SignedHeader, b: SignedHeader) -> Self {
+        if a.hash <= b.hash {
+            Self {
+                first: a,
+                second: b,
+            }
+        } else {
+            Self {
+                first: b,
+                second: a,
+            }
+        }
+    }
+
+    pub fn id(&self) -> String {
+        hex::encode(sha256(
+            [self.first.hash.as_bytes(), self.second.hash.as_bytes()]
+                .concat()
+                .as_slice(),
+        ))
+    }
+
+    pub fn offender(&self) -> &str {
+        &self.first.miner_address
+    }
+
+    pub fn index(&self) -> u32 {
+        self.first.index
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.first.index == self.second.index
+            && self.first.miner_address == self.second.miner_address
+            && self.first.hash != self.second.hash
+            && self.first.has_valid_signature()
+            && self.second.has_valid_signature()
+    }
+
+    /// too old to be put in the block at block_index
+    pub fn is_expired(&self, block_index: u32) -> bool {
+        block_index.saturating_sub(self.index()) > EVIDENCE_MAX_AGE
+    }
+}
+
+/// a miner is only slashed once for a height
+pub fn is_slashed(chain: &BlockChain, offender: &str, index: u32) -> bool {
+    chain
+        .blocks
+        .iter()
+        .flat_map(|block| block.evidence.iter())
+        .any(|e| e.offender() == offender && e.index() == index)
+}
+
+/// the evidence in the block has to be valid, about an earlier height that is at most
+/// EVIDENCE_MAX_AGE blocks old and not slashed yet
+pub fn has_valid_evidence(block: &Block, chain: &BlockChain) -> bool {
+    if block.evidence.len() > MAX_EVIDENCE_PER_BLOCK {
+        error!("block has more than {} evidence", MAX_EVIDENCE_PER_BLOCK);
+        return false;
+    }
+
+    let mut seen = HashSet::new();
+    for e in block.evidence.iter() {
+        if !e.is_valid() || e.index() >= block.index {
+            error!("invalid double signing evidence {}", e.id());
+            return false;
+        }
+        if e.is_expired(block.index) {
+            error!("double signing evidence {} is too old", e.id());
+            return false;
+        }
+        if !seen.insert((e.offender(), e.index())) || is_slashed(chain, e.offender(), e.index()) {
+            error!(
+                "{} is already slashed for block {}",
+                e.offender(),
+                e.index()
+            );
+            return false;
+        }
+    }
+
+    true
+}
+
+/// the outputs of the offender's address that existed at the offending height could have been
+/// staked there, so they are burned. outputs received or restaked later are kept
+pub fn burn(evidence: &[DoubleSignEvidence], unspent_tx_outs: &mut Vec<UnspentTxOut>) {
+    let mut offenders: HashMap<Address, u32> = HashMap::new();
+    for e in evidence {
+        if let Ok(address) = e.offender().parse() {
+            let index = offenders.entry(address).or_default();
+            *index = (*index).max(e.index());
+        }
+    }
+    if offenders.is_empty() {
+        return;
+    }
+
+    unspent_tx_outs.retain(|u_tx_out| match &u_tx_out.address {
+        Some(address)
+            if offenders
+                .get(address)
+                .is_some_and(|index| u_tx_out.block_index <= *index as u64) =>
+        {
+            info!(
+                "burning {} of {} for double signing",
+                u_tx_out.amount, address
+            );
+            false
+        }
+        _ => true,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{KeyPair, PrivateKey};
+    use crate::test_util::utxo;
+
+    fn signed_block(index: u32, hash: &str, key: &PrivateKey, address: &Address) -> Block {
+        let mut block = Block {
+            index,
+            previous_hash: String::new(),
+            timestamp: 0,
+            data: vec![],
+            hash: hash.to_owned(),
+            difficulty: 0,
+            nonce: 0,
+            miner_address: address.to_string(),
+            miner_balance: 0,
+            signature: String::new(),
+            evidence: vec![],
+        };
+        block.sign(key, address).unwrap();
+
+        block
+    }
+
+    #[test]
+    fn test_double_sign_evidence() {
+        let key = KeyPair::generate().unwrap().private_key;
+        let address = Address::from_public_key(&key.to_public_key().unwrap()).unwrap();
+        let other = KeyPair::generate().unwrap().private_key;
+        let other_address = Address::from_public_key(&other.to_public_key().unwrap()).unwrap();
+
+        let a = signed_block(5, "aa", &key, &address).header();
+        let b = signed_block(5, "bb", &key, &address).header();
+        let evidence = DoubleSignEvidence::new(b.clone(), a.clone());
+        assert!(evidence.is_valid());
+        assert_eq!(evidence, DoubleSignEvidence::new(a.clone(), b.clone()));
+        assert_eq!(evidence.offender(), address.to_string());
+
+        // the same block twice, or two heights, is not double signing
+        assert!(!DoubleSignEvidence::new(a.clone(), a.clone()).is_valid());
+        let c = signed_block(6, "cc", &key, &address).header();
+        assert!(!DoubleSignEvidence::new(a.clone(), c).is_valid());
+
+        // a header the miner didn't sign proves nothing
+        let mut forged = signed_block(5, "dd", &other, &other_address).header();
+        forged.miner_address = address.to_string();
+        assert!(!DoubleSignEvidence::new(a, forged).is_valid());
+    }
+
+    #[test]
+    fn test_slashing() {
+        let key = KeyPair::generate().unwrap().private_key;
+        let address = Address::from_public_key(&key.to_public_key().unwrap()).unwrap();
+        let other = KeyPair::generate().unwrap().private_key;
+        let other_address = Address::from_public_key(&other.to_public_key().unwrap()).unwrap();
+
+        let evidence = DoubleSignEvidence::new(
+            signed_block(5, "aa", &key, &address).header(),
+            signed_block(5, "bb", &key, &address).header(),
+        );
+
+        let mut chain = BlockChain::default();
+        let mut block = signed_block(7, "ee", &other, &other_address);
+        block.evidence = vec![evidence.clone()];
+        assert!(has_valid_evidence(&block, &chain));
+
+        // evidence can't be about a block that isn't before it
+        let mut early = block.clone();
+        early.index = 5;
+        assert!(!has_valid_evidence(&early, &chain));
+
+        // nor be used twice
+        let mut twice = block.clone();
+        twice.evidence.push(evidence.clone());
+        assert!(!has_valid_evidence(&twice, &chain));
+        chain.blocks.push(block.clone());
+        assert!(!has_valid_evidence(&block, &chain));
+
+        // only the outputs that existed at the offending height are burned
+        let mut unspent_tx_outs = vec![
+            utxo("1", &address, 50, 0),
+            utxo("2", &other_address, 50, 0),
+            utxo("3", &address, 50, 5),
+            utxo("4", &address, 50, 6),
+        ];
+        burn(&[evidence], &mut unspent_tx_outs);
+        assert_eq!(
+            unspent_tx_outs,
+            vec![utxo("2", &other_address, 50, 0), utxo("4", &address, 50, 6)]
+        );
+    }
+
+    #[test]
+    fn test_stale_evidence() {
+        let key = KeyPair::generate().unwrap().private_key;
+        let address = Address::from_public_key(&key.to_public_key().unwrap()).unwrap();
+        let other = KeyPair::generate().unwrap().private_key;
+        let other_address = Address::from_public_key(&other.to_public_key().unwrap()).unwrap();
+
+        let evidence = DoubleSignEvidence::new(
+            signed_block(5, "aa", &key, &address).header(),
+            signed_block(5, "bb", &key, &address).header(),
+        );
+
+        let chain = BlockChain::default();
+        let mut block = signed_block(5 + EVIDENCE_MAX_AGE, "ee", &other, &other_address);
+        block.evidence = vec![evidence];
+        assert!(has_valid_evidence(&block, &chain));
+
+        block.index += 1;
+        assert!(!has_valid_evidence(&block, &chain));
+    }
+}
@@ -0,0 +1,33 @@
+//! fixtures shared by the unit tests
+use std::fs;
+use tempfile::NamedTempFile;
+
+use crate::address::Address;
+use crate::transaction::UnspentTxOut;
+use crate::wallet::Wallet;
+
+pub const PASSPHRASE: &str = "passphrase";
+
+/// a new locked wallet whose keystore is at the location, the file is removed first so it
+/// doesn't look like an existing key
+pub fn new_wallet(location: &NamedTempFile) -> Wallet {
+    let wallet = Wallet::new(location.path().to_str().unwrap().to_owned());
+    fs::remove_file(location.path()).unwrap();
+    wallet.init(Some(PASSPHRASE), None).unwrap();
+
+    wallet
+}
+
+/// an output paying amount to the address, added at block_index
+pub fn utxo(id: &str, address: &Address, amount: u64, block_index: u64) -> UnspentTxOut {
+    UnspentTxOut {
+        tx_out_id: id.to_owned(),
+        tx_out_index: 0,
+        address: Some(address.clone()),
+        script_pubkey: address.script_pubkey(),
+        amount,
+        block_index,
+        timestamp: 0,
+        is_coinbase: false,
+    }
+}
@@ -1,28 +1,84 @@
-use log::{error, warn};
+use log::{debug, warn};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
+use std::thread;
 
-use crate::crypto::{KeyPair, PrivateKey, Signature};
-use crate::COINBASE_AMOUNT;
+use crate::address::Address;
+use crate::crypto::{
+    hash160, schnorr_batch_verify, KeyPair, PrivateKey, SchnorrCheck, Signature,
+    SCHNORR_PUBLIC_KEY_SIZE,
+};
+use crate::script::{verify_script, BatchSignatureChecker, Script, TransactionSignatureChecker};
+use crate::signature_cache::SIGNATURE_CACHE;
+use crate::{COINBASE_AMOUNT, COINBASE_MATURITY};
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+/// lock_time values below this are block indexes, the rest are unix timestamps
+pub const LOCKTIME_THRESHOLD: u64 = 500_000_000;
+
+/// sequence of an input that has no relative lock
+pub const SEQUENCE_FINAL: u32 = u32::MAX;
+/// if set the sequence is not interpreted as a relative lock
+pub const SEQUENCE_LOCKTIME_DISABLE_FLAG: u32 = 1 << 31;
+/// if set the relative lock is in units of 512 seconds instead of blocks
+pub const SEQUENCE_LOCKTIME_TYPE_FLAG: u32 = 1 << 22;
+pub const SEQUENCE_LOCKTIME_MASK: u32 = 0x0000ffff;
+pub const SEQUENCE_LOCKTIME_GRANULARITY: u32 = 9;
+
+/// the signature commits to all inputs and outputs
+pub const SIGHASH_ALL: u8 = 0x01;
+/// the signature commits to no outputs, anyone can change where the coins go
+pub const SIGHASH_NONE: u8 = 0x02;
+/// the signature commits only to the output with the same index as the input
+pub const SIGHASH_SINGLE: u8 = 0x03;
+/// combined with the flags above, the signature commits only to its own input
+pub const SIGHASH_ANYONECANPAY: u8 = 0x80;
+
+/// parse sighash names like ALL, NONE, SINGLE and SINGLE|ANYONECANPAY
+pub fn parse_sighash(sighash: &str) -> Option<u8> {
+    let (base, anyone_can_pay) = match sighash.split_once('|') {
+        Some((base, "ANYONECANPAY")) => (base, SIGHASH_ANYONECANPAY),
+        Some(_) => return None,
+        None => (sighash, 0),
+    };
+
+    let base = match base {
+        "ALL" => SIGHASH_ALL,
+        "NONE" => SIGHASH_NONE,
+        "SINGLE" => SIGHASH_SINGLE,
+        _ => return None,
+    };
+
+    Some(base | anyone_can_pay)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct UnspentTxOut {
     pub tx_out_id: String,
     pub tx_out_index: u64,
-    pub address: String,
+    /// address of the locking script, None if it isn't a pay to public key hash script
+    pub address: Option<Address>,
+    pub script_pubkey: Script,
     pub amount: u64,
+    /// index and timestamp of the block that created this output
+    pub block_index: u64,
+    pub timestamp: u64,
+    pub is_coinbase: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct TxIn {
     pub tx_out_id: String,
     pub tx_out_index: u64,
-    pub signature: String,
+    /// unlocking script that satisfies the script_pubkey of the referenced output
+    pub script_sig: Script,
+    #[serde(default = "default_sequence")]
+    pub sequence: u32,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct TxOut {
-    pub address: String,
+    /// locking script that has to be satisfied to spend this output
+    pub script_pubkey: Script,
     pub amount: u64,
 }
 
@@ -32,53 +88,171 @@ pub struct Transaction {
 
     pub tx_ins: Vec<TxIn>,
     pub tx_outs: Vec<TxOut>,
+    /// the transaction can't be in a block before this block index or timestamp
+    #[serde(default)]
+    pub lock_time: u64,
+}
+
+fn default_sequence() -> u32 {
+    SEQUENCE_FINAL
 }
 
 impl UnspentTxOut {
+    /// check if the output can be spent in the block with the given index
+    pub fn is_mature(&self, block_index: u64) -> bool {
+        !self.is_coinbase || self.block_index + *COINBASE_MATURITY <= block_index
+    }
+
     pub fn to_unsigned_tx_in(&self) -> TxIn {
         TxIn {
             tx_out_id: self.tx_out_id.clone(),
             tx_out_index: self.tx_out_index,
-            signature: String::new(),
+            script_sig: Script::new(),
+            sequence: SEQUENCE_FINAL,
         }
     }
 }
 
 impl Transaction {
+    /// hash of the transaction without the script_sigs
+    /// every field is fixed size or length prefixed so different transactions can't collide
     pub fn get_transaction_id(&self) -> String {
-        let tx_in_content = self
+        hex::encode(Sha256::digest(&self.serialize_without_script_sigs()))
+    }
+
+    /// size in bytes of the serialized transaction, fees are paid per byte
+    pub fn size(&self) -> usize {
+        let script_sigs: usize = self
             .tx_ins
             .iter()
-            .map(|tx_in| tx_in.tx_out_index.to_string() + &tx_in.tx_out_id)
-            .reduce(|a, b| a + &b)
-            .unwrap_or_default();
+            .map(|tx_in| U64_SIZE + tx_in.script_sig.0.len())
+            .sum();
 
-        let tx_out_content = self
+        self.serialize_without_script_sigs().len() + script_sigs
+    }
+
+    /// what is left of the inputs after paying the outputs, the miner of the block can claim it
+    /// None if an input isn't found or the outputs are worth more than the inputs
+    pub fn get_fee(&self, new_unspent_tx_outs: &[UnspentTxOut]) -> Option<u64> {
+        let mut total_tx_in_values: u64 = 0;
+        for tx_in in self.tx_ins.iter() {
+            let u_tx_out =
+                find_unspent_tx_out(&tx_in.tx_out_id, &tx_in.tx_out_index, new_unspent_tx_outs)?;
+            total_tx_in_values = total_tx_in_values.checked_add(u_tx_out.amount)?;
+        }
+
+        let total_tx_out_values = self
             .tx_outs
             .iter()
-            .map(|tx_out| tx_out.amount.to_string() + &tx_out.address)
-            .reduce(|a, b| a + &b)
-            .unwrap_or_default();
+            .try_fold(0u64, |total, tx_out| total.checked_add(tx_out.amount))?;
+
+        total_tx_in_values.checked_sub(total_tx_out_values)
+    }
+
+    fn serialize_without_script_sigs(&self) -> Vec<u8> {
+        let mut data = vec![];
+
+        write_len(&mut data, self.tx_ins.len());
+        for tx_in in self.tx_ins.iter() {
+            tx_in.write_out_point(&mut data);
+            data.extend(tx_in.sequence.to_be_bytes());
+        }
+
+        write_len(&mut data, self.tx_outs.len());
+        for tx_out in self.tx_outs.iter() {
+            tx_out.write(&mut data);
+        }
+
+        data.extend(self.lock_time.to_be_bytes());
+
+        data
+    }
+
+    /// digest that the signature of the input at tx_in_index commits to
+    /// unless SIGHASH_ANYONECANPAY is set it commits to the script_pubkeys and amounts of the
+    /// outputs spent by all inputs, like BIP-341, so a signer that is lied to about any of them
+    /// signs a different digest. the spent outputs are looked up in unspent_tx_outs
+    /// returns None if the hash_type is unknown, SIGHASH_SINGLE has no matching output or a spent
+    /// output is unknown
+    pub fn signature_hash(
+        &self,
+        tx_in_index: usize,
+        unspent_tx_outs: &[UnspentTxOut],
+        hash_type: u8,
+    ) -> Option<Vec<u8>> {
+        let base_type = hash_type & !SIGHASH_ANYONECANPAY;
+        let anyone_can_pay = hash_type & SIGHASH_ANYONECANPAY != 0;
+
+        if !(SIGHASH_ALL..=SIGHASH_SINGLE).contains(&base_type) {
+            return None;
+        }
+        let tx_in = self.tx_ins.get(tx_in_index)?;
+
+        let mut data = vec![hash_type];
+
+        if anyone_can_pay {
+            write_len(&mut data, 1);
+            tx_in.write_out_point(&mut data);
+            data.extend(tx_in.sequence.to_be_bytes());
+        } else {
+            write_len(&mut data, self.tx_ins.len());
+            for (index, other) in self.tx_ins.iter().enumerate() {
+                other.write_out_point(&mut data);
+                // with NONE and SINGLE the others can update their sequences
+                if index == tx_in_index || base_type == SIGHASH_ALL {
+                    data.extend(other.sequence.to_be_bytes());
+                } else {
+                    data.extend(0u32.to_be_bytes());
+                }
+            }
+        }
+
+        match base_type {
+            SIGHASH_ALL => {
+                write_len(&mut data, self.tx_outs.len());
+                for tx_out in self.tx_outs.iter() {
+                    tx_out.write(&mut data);
+                }
+            }
+            SIGHASH_SINGLE => {
+                let tx_out = self.tx_outs.get(tx_in_index)?;
+                write_len(&mut data, 1);
+                data.extend((tx_in_index as u64).to_be_bytes());
+                tx_out.write(&mut data);
+            }
+            _ => write_len(&mut data, 0),
+        }
 
-        let mut hasher = Sha256::new();
+        data.extend(self.lock_time.to_be_bytes());
 
-        hasher.update(tx_in_content);
-        hasher.update(tx_out_content);
+        if !anyone_can_pay {
+            for other in self.tx_ins.iter() {
+                let spent =
+                    find_unspent_tx_out(&other.tx_out_id, &other.tx_out_index, unspent_tx_outs)?;
+                write_bytes(&mut data, &spent.script_pubkey.0);
+                data.extend(spent.amount.to_be_bytes());
+            }
+        }
 
-        format!("{:x}", hasher.finalize())
+        // the input being signed
+        let spent = find_unspent_tx_out(&tx_in.tx_out_id, &tx_in.tx_out_index, unspent_tx_outs)?;
+        data.extend((tx_in_index as u64).to_be_bytes());
+        write_bytes(&mut data, &spent.script_pubkey.0);
+        data.extend(spent.amount.to_be_bytes());
+
+        Some(Sha256::digest(&data).to_vec())
     }
 
     pub fn validate(&self, new_unspent_tx_outs: &[UnspentTxOut]) -> bool {
-        if self.get_transaction_id() != self.id {
-            warn!("invalid tx id: {}", self.id);
+        if !self.validate_without_scripts(new_unspent_tx_outs) {
             return false;
         }
 
         let has_valid_tx_ins = self
             .tx_ins
             .iter()
-            .map(|tx_in| tx_in.validate(self, new_unspent_tx_outs))
-            .all(|x| x);
+            .enumerate()
+            .all(|(index, tx_in)| tx_in.validate(self, index, new_unspent_tx_outs));
 
         if !has_valid_tx_ins {
             warn!("some of the txIns are invalid in tx: {}", self.id);
@@ -86,42 +260,193 @@ impl Transaction {
             return false;
         }
 
-        let total_tx_in_values = self
-            .tx_ins
-            .iter()
-            .map(|tx_in| tx_in.get_amount(new_unspent_tx_outs))
-            .reduce(|a, b| a + b)
-            .unwrap_or(0);
+        true
+    }
 
-        let total_tx_out_values = self
-            .tx_outs
-            .iter()
-            .map(|tx_out| tx_out.amount)
-            .reduce(|a, b| a + b)
-            .unwrap_or(0);
+    /// everything validate checks except the scripts of the inputs
+    fn validate_without_scripts(&self, new_unspent_tx_outs: &[UnspentTxOut]) -> bool {
+        if self.get_transaction_id() != self.id {
+            warn!("invalid tx id: {}", self.id);
+            return false;
+        }
 
-        if total_tx_in_values != total_tx_out_values {
-            warn!("totalTxOutValues != totalTxInValues in tx: {}", self.id);
+        if let Some(tx_in) = self.tx_ins.iter().find(|tx_in| {
+            find_unspent_tx_out(&tx_in.tx_out_id, &tx_in.tx_out_index, new_unspent_tx_outs)
+                .is_none()
+        }) {
+            warn!("referenced txOut not found: {:?}", tx_in);
+            return false;
+        }
+
+        // the difference is the fee
+        if self.get_fee(new_unspent_tx_outs).is_none() {
+            warn!("totalTxOutValues > totalTxInValues in tx: {}", self.id);
             return false;
         }
 
         true
     }
 
+    /// verify the scripts of all inputs of the transactions on all cores
+    /// Schnorr signatures that aren't in the signature cache are verified together in one batch,
+    /// if a script or the batch fails all inputs are verified one by one to get the exact result
+    pub fn verify_scripts(transactions: &[Self], new_unspent_tx_outs: &[UnspentTxOut]) -> bool {
+        let inputs: Vec<(&Self, usize)> = transactions
+            .iter()
+            .flat_map(|tx| (0..tx.tx_ins.len()).map(move |index| (tx, index)))
+            .collect();
+        if inputs.is_empty() {
+            return true;
+        }
+
+        let threads = thread::available_parallelism()
+            .map_or(1, |threads| threads.get())
+            .min(inputs.len());
+        let chunk_size = inputs.len().div_ceil(threads);
+
+        let results: Vec<Option<Vec<SchnorrCheck>>> = thread::scope(|scope| {
+            inputs
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    scope.spawn(move || {
+                        chunk
+                            .iter()
+                            .map(|(tx, index)| {
+                                tx.tx_ins[*index].verify_deferring_schnorr(
+                                    tx,
+                                    *index,
+                                    new_unspent_tx_outs,
+                                )
+                            })
+                            .collect::<Option<Vec<Vec<SchnorrCheck>>>>()
+                    })
+                })
+                .collect::<Vec<_>>()
+                .into_iter()
+                .map(|handle| handle.join().unwrap_or(None).map(|c| c.concat()))
+                .collect()
+        });
+
+        match results.into_iter().collect::<Option<Vec<_>>>() {
+            Some(deferred) => {
+                let deferred = deferred.concat();
+                if schnorr_batch_verify(&deferred).unwrap_or(false) {
+                    let mut cache = SIGNATURE_CACHE.write().unwrap();
+                    for check in deferred.iter() {
+                        cache.insert(&check.digest, &check.signature, &check.public_key);
+                    }
+                    return true;
+                }
+                warn!("batch verification of schnorr signatures failed");
+            }
+            None => warn!("some of the txIns have invalid scripts"),
+        }
+
+        inputs
+            .iter()
+            .all(|(tx, index)| tx.tx_ins[*index].validate(tx, *index, new_unspent_tx_outs))
+    }
+
+    /// check if the lock_time allows this transaction in a block with the given index and timestamp
+    pub fn is_final(&self, block_index: u64, block_timestamp: u64) -> bool {
+        if self.lock_time == 0 {
+            return true;
+        }
+
+        if self.lock_time < LOCKTIME_THRESHOLD {
+            self.lock_time <= block_index
+        } else {
+            self.lock_time <= block_timestamp
+        }
+    }
+
+    /// check the relative locks of the inputs against the blocks that created the referenced outputs
+    pub fn has_mature_sequence_locks(
+        &self,
+        unspent_tx_outs: &[UnspentTxOut],
+        block_index: u64,
+        block_timestamp: u64,
+    ) -> bool {
+        self.tx_ins.iter().all(|tx_in| {
+            if tx_in.sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 {
+                return true;
+            }
+
+            let referenced =
+                match find_unspent_tx_out(&tx_in.tx_out_id, &tx_in.tx_out_index, unspent_tx_outs) {
+                    Some(u_tx_out) => u_tx_out,
+                    None => return false,
+                };
+
+            // a lock that overflows is never mature
+            let value = (tx_in.sequence & SEQUENCE_LOCKTIME_MASK) as u64;
+            let is_mature = if tx_in.sequence & SEQUENCE_LOCKTIME_TYPE_FLAG != 0 {
+                referenced
+                    .timestamp
+                    .checked_add(value << SEQUENCE_LOCKTIME_GRANULARITY)
+                    .map(|mature_at| mature_at <= block_timestamp)
+            } else {
+                referenced
+                    .block_index
+                    .checked_add(value)
+                    .map(|mature_at| mature_at <= block_index)
+            };
+
+            is_mature.unwrap_or(false)
+        })
+    }
+
+    /// check that no input spends a coinbase output that is not mature in the block with the given index
+    pub fn has_mature_coinbase_inputs(
+        &self,
+        unspent_tx_outs: &[UnspentTxOut],
+        block_index: u64,
+    ) -> bool {
+        self.tx_ins.iter().all(|tx_in| {
+            find_unspent_tx_out(&tx_in.tx_out_id, &tx_in.tx_out_index, unspent_tx_outs)
+                .is_some_and(|u_tx_out| u_tx_out.is_mature(block_index))
+        })
+    }
+
+    /// outputs referenced by the inputs of this transaction that are not in the given unspent outputs
+    pub fn missing_tx_outs(&self, unspent_tx_outs: &[UnspentTxOut]) -> Vec<(String, u64)> {
+        self.tx_ins
+            .iter()
+            .filter(|tx_in| {
+                find_unspent_tx_out(&tx_in.tx_out_id, &tx_in.tx_out_index, unspent_tx_outs)
+                    .is_none()
+            })
+            .map(|tx_in| (tx_in.tx_out_id.clone(), tx_in.tx_out_index))
+            .collect()
+    }
+
     pub fn validate_block_transactions(
         new_transactions: &[Self],
         new_unspent_tx_outs: &[UnspentTxOut],
         block_index: &u64,
+        block_timestamp: &u64,
     ) -> bool {
         if new_transactions.is_empty() {
             return true;
         }
         let coinbase_tx = &new_transactions[0];
-        if !Self::validate_coinbase_tx(coinbase_tx, block_index) {
+        let fees = new_transactions[1..]
+            .iter()
+            .filter_map(|tx| tx.get_fee(new_unspent_tx_outs))
+            .fold(0u64, u64::saturating_add);
+        if !Self::validate_coinbase_tx(coinbase_tx, block_index, fees) {
             warn!("invalid coinbase transaction: {:?}", coinbase_tx);
             return false;
         }
 
+        if let Some(tx) = new_transactions
+            .iter()
+            .find(|tx| !tx.is_final(*block_index, *block_timestamp))
+        {
+            warn!("transaction is locked until {}: {}", tx.lock_time, tx.id);
+            return false;
+        }
+
         let tx_ins: Vec<&TxIn> = new_transactions.iter().flat_map(|t| &t.tx_ins).collect();
 
         if TxIn::has_duplicates(tx_ins) {
@@ -131,13 +456,33 @@ impl Transaction {
 
         // transactions except coinbase transaction
         let normal_transactions = &new_transactions[1..];
+
+        if let Some(tx) = normal_transactions.iter().find(|tx| {
+            !tx.has_mature_sequence_locks(new_unspent_tx_outs, *block_index, *block_timestamp)
+        }) {
+            warn!(
+                "transaction has inputs with immature relative locks: {}",
+                tx.id
+            );
+            return false;
+        }
+
+        if let Some(tx) = normal_transactions
+            .iter()
+            .find(|tx| !tx.has_mature_coinbase_inputs(new_unspent_tx_outs, *block_index))
+        {
+            warn!("transaction spends an immature coinbase output: {}", tx.id);
+            return false;
+        }
+
         normal_transactions
             .iter()
-            .map(|tx_in| tx_in.validate(new_unspent_tx_outs))
-            .all(|x| x)
+            .all(|tx| tx.validate_without_scripts(new_unspent_tx_outs))
+            && Self::verify_scripts(normal_transactions, new_unspent_tx_outs)
     }
 
-    fn validate_coinbase_tx(transaction: &Self, block_index: &u64) -> bool {
+    /// the coinbase can claim the fees of the transactions in the block, unclaimed fees are burned
+    fn validate_coinbase_tx(transaction: &Self, block_index: &u64, fees: u64) -> bool {
         if transaction.get_transaction_id() != transaction.id {
             warn!("invalid coinbase tx id: {}", transaction.id,);
             false
@@ -145,12 +490,12 @@ impl Transaction {
             warn!("one txIn must be specified in the coinbase transaction");
             false
         } else if transaction.tx_ins[0].tx_out_index != *block_index {
-            warn!("the txIn signature in coinbase tx must be the block height");
+            warn!("the txIn index in coinbase tx must be the block height");
             false
         } else if transaction.tx_outs.len() != 1 {
             warn!("invalid number of txOuts in coinbase transaction");
             false
-        } else if transaction.tx_outs[0].amount != COINBASE_AMOUNT {
+        } else if transaction.tx_outs[0].amount > COINBASE_AMOUNT.saturating_add(fees) {
             warn!("invalid coinbase amount in coinbase transaction");
             false
         } else {
@@ -158,20 +503,22 @@ impl Transaction {
         }
     }
 
-    pub fn get_coinbase_tx(address: String, block_index: u64) -> Self {
+    pub fn get_coinbase_tx(script_pubkey: Script, block_index: u64, fees: u64) -> Self {
         let tx_in = TxIn {
             tx_out_id: String::new(),
             tx_out_index: block_index,
-            signature: String::new(),
+            script_sig: Script::new(),
+            sequence: SEQUENCE_FINAL,
         };
 
         let mut tx = Self {
             id: String::new(),
             tx_ins: vec![tx_in],
             tx_outs: vec![TxOut {
-                address,
-                amount: COINBASE_AMOUNT,
+                script_pubkey,
+                amount: COINBASE_AMOUNT + fees,
             }],
+            lock_time: 0,
         };
 
         tx.id = tx.get_transaction_id();
@@ -179,58 +526,111 @@ impl Transaction {
         tx
     }
 
+    /// spends a staked output and pays it back, so the output can only be staked once and its age
+    /// starts over. the first output is an empty marker
+    pub fn get_coinstake_tx(staked: &UnspentTxOut) -> Self {
+        let mut tx = Self {
+            id: String::new(),
+            tx_ins: vec![staked.to_unsigned_tx_in()],
+            tx_outs: vec![
+                TxOut {
+                    script_pubkey: Script::new(),
+                    amount: 0,
+                },
+                TxOut {
+                    script_pubkey: staked.script_pubkey.clone(),
+                    amount: staked.amount,
+                },
+            ],
+            lock_time: 0,
+        };
+
+        tx.id = tx.get_transaction_id();
+
+        tx
+    }
+
+    pub fn is_coinstake(&self) -> bool {
+        self.tx_ins
+            .first()
+            .is_some_and(|tx_in| !tx_in.tx_out_id.is_empty())
+            && self.tx_outs.len() >= 2
+            && self.tx_outs[0].amount == 0
+            && self.tx_outs[0].script_pubkey.0.is_empty()
+    }
+
     pub fn update_unspent_tx_out(
         new_transactions: &[Self],
-        a_unspent_tx_outs: &Vec<UnspentTxOut>,
+        a_unspent_tx_outs: &[UnspentTxOut],
+        block_index: &u64,
+        block_timestamp: &u64,
     ) -> Vec<UnspentTxOut> {
+        // the first transaction of a block is the coinbase transaction
         let new_unspent_tx_outs: Vec<UnspentTxOut> = new_transactions
             .iter()
-            .map(|t| {
+            .enumerate()
+            .map(|(tx_index, t)| {
                 t.tx_outs
                     .iter()
                     .enumerate()
+                    // the marker of a coinstake can't be spent
+                    .filter(|(index, _)| *index != 0 || !t.is_coinstake())
                     .map(|(index, tx_out)| UnspentTxOut {
                         tx_out_id: t.id.clone(),
                         tx_out_index: index as u64,
-                        address: tx_out.address.clone(),
+                        address: Address::from_script(&tx_out.script_pubkey),
+                        script_pubkey: tx_out.script_pubkey.clone(),
                         amount: tx_out.amount,
+                        block_index: *block_index,
+                        timestamp: *block_timestamp,
+                        is_coinbase: tx_index == 0,
                     })
                     .collect()
             })
-            .reduce(|a: Vec<UnspentTxOut>, b| vec![a, b].concat())
+            .reduce(|a: Vec<UnspentTxOut>, b| [a, b].concat())
             .unwrap_or_default();
 
         let consumed_tx_outs: Vec<UnspentTxOut> = new_transactions
             .iter()
             .map(|t| t.clone().tx_ins)
-            .reduce(|a, b| vec![a, b].concat())
+            .reduce(|a, b| [a, b].concat())
             .unwrap_or_default()
             .iter()
             .map(|tx_in| UnspentTxOut {
                 tx_out_id: tx_in.tx_out_id.clone(),
                 tx_out_index: tx_in.tx_out_index,
-                address: String::new(),
+                address: None,
+                script_pubkey: Script::new(),
                 amount: 0,
+                block_index: 0,
+                timestamp: 0,
+                is_coinbase: false,
             })
             .collect();
 
         a_unspent_tx_outs
-            .clone()
-            .into_iter()
+            .iter()
             .filter(|u_tx_o| {
                 find_unspent_tx_out(&u_tx_o.tx_out_id, &u_tx_o.tx_out_index, &consumed_tx_outs)
                     .is_none()
             })
-            .chain(new_unspent_tx_outs.into_iter())
+            .cloned()
+            .chain(new_unspent_tx_outs)
             .collect()
     }
 
     pub fn process_transaction(
         new_transactions: &[Self],
-        new_unspent_tx_outs: &Vec<UnspentTxOut>,
+        new_unspent_tx_outs: &[UnspentTxOut],
         block_index: &u64,
+        block_timestamp: &u64,
     ) -> Option<Vec<UnspentTxOut>> {
-        if !Self::validate_block_transactions(new_transactions, new_unspent_tx_outs, block_index) {
+        if !Self::validate_block_transactions(
+            new_transactions,
+            new_unspent_tx_outs,
+            block_index,
+            block_timestamp,
+        ) {
             warn!("invalid block transaction");
             return None;
         }
@@ -238,11 +638,18 @@ impl Transaction {
         Some(Self::update_unspent_tx_out(
             new_transactions,
             new_unspent_tx_outs,
+            block_index,
+            block_timestamp,
         ))
     }
 }
 
 impl TxIn {
+    fn write_out_point(&self, data: &mut Vec<u8>) {
+        write_bytes(data, self.tx_out_id.as_bytes());
+        data.extend(self.tx_out_index.to_be_bytes());
+    }
+
     fn has_duplicates(tx_ins: Vec<&Self>) -> bool {
         let v: Vec<String> = tx_ins
             .iter()
@@ -254,40 +661,27 @@ impl TxIn {
     pub fn validate(
         &self,
         transaction: &Transaction,
+        tx_in_index: usize,
         new_unspent_tx_outs: &[UnspentTxOut],
     ) -> bool {
-        match new_unspent_tx_outs.iter().find(|u_tx_out| {
-            u_tx_out.tx_out_id == self.tx_out_id && u_tx_out.tx_out_index == self.tx_out_index
-        }) {
+        match find_unspent_tx_out(&self.tx_out_id, &self.tx_out_index, new_unspent_tx_outs) {
             Some(referenced_u_tx_out) => {
-                let address = &referenced_u_tx_out.address;
+                let checker = TransactionSignatureChecker {
+                    transaction,
+                    tx_in_index,
+                    unspent_tx_outs: new_unspent_tx_outs,
+                };
 
-                if let Ok(public_key) = KeyPair::public_key_from_hex(address) {
-                    if let Ok(signature) = Signature::from_string(&self.signature) {
-                        let r = signature
-                            .verify(transaction.id.as_bytes(), public_key)
-                            .unwrap();
-
-                        if !r {
-                            warn!("failed signature verify for {:?}", self);
-                        }
-
-                        return r;
-                    } else {
-                        error!("error getting signature");
-                        return false;
-                    }
-                }
-                warn!(
-                    "could not parse address to public key address: {}",
-                    referenced_u_tx_out.address
-                );
-
-                if let Err(e) = KeyPair::public_key_from_hex(&referenced_u_tx_out.address) {
-                    warn!("{e}");
+                if let Err(e) = verify_script(
+                    &self.script_sig,
+                    &referenced_u_tx_out.script_pubkey,
+                    &checker,
+                ) {
+                    warn!("failed script verify for {:?}: {}", self, e);
+                    return false;
                 }
 
-                false
+                true
             }
             None => {
                 warn!("referenced txOut not found: {:?}", self);
@@ -296,42 +690,194 @@ impl TxIn {
         }
     }
 
+    /// run the scripts but leave the Schnorr signatures that aren't cached for batch verification
+    /// returns None if the scripts fail
+    fn verify_deferring_schnorr(
+        &self,
+        transaction: &Transaction,
+        tx_in_index: usize,
+        new_unspent_tx_outs: &[UnspentTxOut],
+    ) -> Option<Vec<SchnorrCheck>> {
+        let referenced_u_tx_out =
+            find_unspent_tx_out(&self.tx_out_id, &self.tx_out_index, new_unspent_tx_outs)?;
+
+        let checker = BatchSignatureChecker::new(TransactionSignatureChecker {
+            transaction,
+            tx_in_index,
+            unspent_tx_outs: new_unspent_tx_outs,
+        });
+
+        if let Err(e) = verify_script(
+            &self.script_sig,
+            &referenced_u_tx_out.script_pubkey,
+            &checker,
+        ) {
+            warn!("failed script verify for {:?}: {}", self, e);
+            return None;
+        }
+
+        Some(checker.deferred.into_inner())
+    }
+
     pub fn get_amount(&self, new_unspent_tx_outs: &[UnspentTxOut]) -> u64 {
         find_unspent_tx_out(&self.tx_out_id, &self.tx_out_index, new_unspent_tx_outs)
             .unwrap()
             .amount
     }
 
-    // TODO: fix spagetthi
+    /// create the script_sig that unlocks the referenced output with the private key
+    /// for multisig outputs the signature is added to the ones already in the script_sig of the input
+    /// hash_type selects which parts of the transaction the signature commits to
     pub fn sign(
         tx: Transaction,
         tx_in_index: u64,
         private_key: &PrivateKey,
+        hash_type: u8,
         new_unspent_tx_outs: &[UnspentTxOut],
-    ) -> String {
+    ) -> Option<Script> {
         let tx_in = &tx.tx_ins[tx_in_index as usize];
 
-        let data_to_sign = tx.id;
         let referenced_u_tx_out =
             match find_unspent_tx_out(&tx_in.tx_out_id, &tx_in.tx_out_index, new_unspent_tx_outs) {
                 Some(tx_out) => tx_out,
                 None => {
-                    panic!("could not find referenced txOut");
+                    warn!("could not find referenced txOut");
+                    return None;
                 }
             };
 
-        let referenced_address = referenced_u_tx_out.address;
+        let script_pubkey = referenced_u_tx_out.script_pubkey;
+        let (ecdsa_public_key, schnorr_key) = match private_key
+            .to_public_key()
+            .and_then(|public_key| KeyPair::public_key_to_bytes(&public_key))
+            .and_then(|public_key| Ok((public_key, private_key.to_schnorr_key()?)))
+        {
+            Ok(keys) => keys,
+            Err(e) => {
+                warn!("could not get the public keys: {}", e);
+                return None;
+            }
+        };
+
+        // the output decides which scheme is used by locking to an ECDSA or a Schnorr key
+        let public_key = [ecdsa_public_key, schnorr_key.public_key()]
+            .into_iter()
+            .find(|public_key| {
+                script_pubkey.as_public_key().as_ref() == Some(public_key)
+                    || script_pubkey.as_public_key_hash() == Some(hash160(public_key))
+                    || script_pubkey
+                        .as_multisig()
+                        .is_some_and(|(_, pub_keys)| pub_keys.contains(public_key))
+            });
+        let public_key = match public_key {
+            Some(public_key) => public_key,
+            None => {
+                // not an error, a wallet tries all of its keys
+                debug!(
+                    "the private key can't sign the script that is referenced in {:?}",
+                    tx_in
+                );
+                return None;
+            }
+        };
+
+        let sighash = match tx.signature_hash(tx_in_index as usize, new_unspent_tx_outs, hash_type)
+        {
+            Some(sighash) => sighash,
+            None => {
+                warn!(
+                    "can't sign input {} with sighash {:#x}",
+                    tx_in_index, hash_type
+                );
+                return None;
+            }
+        };
+        let signature = if public_key.len() == SCHNORR_PUBLIC_KEY_SIZE {
+            schnorr_key.sign(&sighash)
+        } else {
+            Signature::from_sign(&sighash, private_key).and_then(|signature| signature.to_der())
+        };
+        let mut signature = match signature {
+            Ok(signature) => signature,
+            Err(e) => {
+                warn!("could not sign input {}: {}", tx_in_index, e);
+                return None;
+            }
+        };
+        signature.push(hash_type);
+
+        if script_pubkey.as_public_key() == Some(public_key.clone()) {
+            Some(Script::new().push_data(&signature))
+        } else if script_pubkey.as_public_key_hash().is_some() {
+            Some(Script::new().push_data(&signature).push_data(&public_key))
+        } else {
+            let checker = TransactionSignatureChecker {
+                transaction: &tx,
+                tx_in_index: tx_in_index as usize,
+                unspent_tx_outs: new_unspent_tx_outs,
+            };
+            script_pubkey.combine_multisig(
+                &[
+                    tx_in.script_sig.clone(),
+                    Script::new().push_data(&signature),
+                ],
+                &checker,
+            )
+        }
+    }
+
+    /// merge the script_sigs of different signers of the same input
+    pub fn combine_script_sigs(
+        tx: &Transaction,
+        tx_in_index: usize,
+        script_sigs: &[Script],
+        new_unspent_tx_outs: &[UnspentTxOut],
+    ) -> Option<Script> {
+        let tx_in = &tx.tx_ins[tx_in_index];
+        let referenced_u_tx_out =
+            find_unspent_tx_out(&tx_in.tx_out_id, &tx_in.tx_out_index, new_unspent_tx_outs)?;
+
+        let checker = TransactionSignatureChecker {
+            transaction: tx,
+            tx_in_index,
+            unspent_tx_outs: new_unspent_tx_outs,
+        };
 
-        let public_key = private_key.to_public_key();
-        if KeyPair::public_key_to_hex(&public_key) != referenced_address {
-            panic!("trying to sign an input with private key that does not match the adress that is referecned in tx_in");
+        if referenced_u_tx_out.script_pubkey.as_multisig().is_some() {
+            return referenced_u_tx_out
+                .script_pubkey
+                .combine_multisig(script_sigs, &checker);
         }
 
-        let signature = Signature::from_sign(data_to_sign.as_bytes(), private_key).unwrap();
-        signature.to_encoded()
+        // only one key can sign other scripts, use the first complete one
+        script_sigs
+            .iter()
+            .find(|script_sig| {
+                verify_script(script_sig, &referenced_u_tx_out.script_pubkey, &checker).is_ok()
+            })
+            .or_else(|| script_sigs.first())
+            .cloned()
+    }
+}
+
+impl TxOut {
+    fn write(&self, data: &mut Vec<u8>) {
+        data.extend(self.amount.to_be_bytes());
+        write_bytes(data, &self.script_pubkey.0);
     }
 }
 
+const U64_SIZE: usize = 8;
+
+fn write_len(data: &mut Vec<u8>, len: usize) {
+    data.extend((len as u64).to_be_bytes());
+}
+
+fn write_bytes(data: &mut Vec<u8>, bytes: &[u8]) {
+    write_len(data, bytes.len());
+    data.extend(bytes);
+}
+
 fn find_unspent_tx_out(
     transaction_id: &String,
     index: &u64,
@@ -339,8 +885,508 @@ fn find_unspent_tx_out(
 ) -> Option<UnspentTxOut> {
     new_unspent_tx_outs
         .iter()
+        .find(|&new_tx_o| &new_tx_o.tx_out_id == transaction_id && &new_tx_o.tx_out_index == index)
         .cloned()
-        .find(|new_tx_o| &new_tx_o.tx_out_id == transaction_id && &new_tx_o.tx_out_index == index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coin_selection::{input_size, SelectionTarget};
+    use crate::crypto::{COMPRESSED_PUBLIC_KEY_SIZE, SCHNORR_SIGNATURE_SIZE};
+    use crate::script::{Instruction, OP_0, OP_1, OP_CHECKSIG, OP_ELSE, OP_ENDIF, OP_NOTIF};
+
+    fn spending_tx(sequence: u32, lock_time: u64) -> Transaction {
+        Transaction {
+            id: String::new(),
+            tx_ins: vec![TxIn {
+                tx_out_id: String::from("parent"),
+                tx_out_index: 0,
+                script_sig: Script::new(),
+                sequence,
+            }],
+            tx_outs: vec![],
+            lock_time,
+        }
+    }
+
+    fn parent_tx_outs() -> Vec<UnspentTxOut> {
+        vec![UnspentTxOut {
+            tx_out_id: String::from("parent"),
+            tx_out_index: 0,
+            address: None,
+            script_pubkey: Script::new(),
+            amount: 10,
+            block_index: 5,
+            timestamp: 1000,
+            is_coinbase: false,
+        }]
+    }
+
+    #[test]
+    fn test_is_final() {
+        assert!(spending_tx(SEQUENCE_FINAL, 0).is_final(0, 0));
+
+        let tx = spending_tx(SEQUENCE_FINAL, 10);
+        assert!(!tx.is_final(9, u64::MAX));
+        assert!(tx.is_final(10, 0));
+
+        let tx = spending_tx(SEQUENCE_FINAL, LOCKTIME_THRESHOLD + 10);
+        assert!(!tx.is_final(u64::MAX, LOCKTIME_THRESHOLD + 9));
+        assert!(tx.is_final(0, LOCKTIME_THRESHOLD + 10));
+    }
+
+    #[test]
+    fn test_sequence_locks() {
+        let u_tx_outs = parent_tx_outs();
+
+        assert!(spending_tx(SEQUENCE_FINAL, 0).has_mature_sequence_locks(&u_tx_outs, 5, 1000));
+
+        // 3 blocks after the parent block
+        let tx = spending_tx(3, 0);
+        assert!(!tx.has_mature_sequence_locks(&u_tx_outs, 7, u64::MAX));
+        assert!(tx.has_mature_sequence_locks(&u_tx_outs, 8, 0));
+
+        // 2 * 512 seconds after the parent block
+        let tx = spending_tx(SEQUENCE_LOCKTIME_TYPE_FLAG | 2, 0);
+        assert!(!tx.has_mature_sequence_locks(&u_tx_outs, u64::MAX, 1000 + 1023));
+        assert!(tx.has_mature_sequence_locks(&u_tx_outs, 0, 1000 + 1024));
+
+        // a parent at the end of time can't overflow the lock
+        let mut late = parent_tx_outs();
+        late[0].timestamp = u64::MAX;
+        late[0].block_index = u64::MAX;
+        assert!(!tx.has_mature_sequence_locks(&late, u64::MAX, u64::MAX));
+        assert!(!spending_tx(3, 0).has_mature_sequence_locks(&late, u64::MAX, u64::MAX));
+
+        // lock_time and sequence are part of the id so they can't be changed after signing
+        assert_ne!(
+            spending_tx(3, 0).get_transaction_id(),
+            spending_tx(4, 0).get_transaction_id()
+        );
+        assert_ne!(
+            spending_tx(3, 0).get_transaction_id(),
+            spending_tx(3, 1).get_transaction_id()
+        );
+    }
+
+    #[test]
+    fn test_sign_and_validate_scripts() {
+        let key = KeyPair::generate().unwrap().private_key;
+        let pub_key = KeyPair::public_key_to_bytes(&key.to_public_key().unwrap()).unwrap();
+        let other = KeyPair::generate().unwrap().private_key;
+
+        for script_pubkey in [
+            Script::pay_to_public_key(&pub_key),
+            Script::pay_to_public_key_hash(&hash160(&pub_key)),
+        ] {
+            let u_tx_outs = vec![UnspentTxOut {
+                tx_out_id: String::from("parent"),
+                tx_out_index: 0,
+                address: None,
+                script_pubkey,
+                amount: 10,
+                block_index: 1,
+                timestamp: 0,
+                is_coinbase: false,
+            }];
+
+            let mut tx = Transaction {
+                id: String::new(),
+                tx_ins: vec![u_tx_outs[0].to_unsigned_tx_in()],
+                tx_outs: vec![TxOut {
+                    script_pubkey: Script::pay_to_public_key(&pub_key),
+                    amount: 10,
+                }],
+                lock_time: 0,
+            };
+            tx.id = tx.get_transaction_id();
+
+            let sighash = tx.signature_hash(0, &u_tx_outs, SIGHASH_ALL).unwrap();
+            let mut forged_sig = Signature::from_sign(&sighash, &other)
+                .unwrap()
+                .to_der()
+                .unwrap();
+            forged_sig.push(SIGHASH_ALL);
+            let mut forged = tx.clone();
+            forged.tx_ins[0].script_sig = Script::new().push_data(&forged_sig).push_data(&pub_key);
+            assert!(!forged.validate(&u_tx_outs));
+
+            assert!(TxIn::sign(tx.clone(), 0, &other, SIGHASH_ALL, &u_tx_outs).is_none());
+            tx.tx_ins[0].script_sig =
+                TxIn::sign(tx.clone(), 0, &key, SIGHASH_ALL, &u_tx_outs).unwrap();
+            assert!(tx.validate(&u_tx_outs));
+        }
+    }
+
+    #[test]
+    fn test_fees() {
+        let key = KeyPair::generate().unwrap().private_key;
+        let pub_key = KeyPair::public_key_to_bytes(&key.to_public_key().unwrap()).unwrap();
+        let script_pubkey = Script::pay_to_public_key_hash(&hash160(&pub_key));
+        let u_tx_outs = vec![UnspentTxOut {
+            script_pubkey: script_pubkey.clone(),
+            ..parent_tx_outs().remove(0)
+        }];
+
+        let mut tx = Transaction {
+            id: String::new(),
+            tx_ins: vec![u_tx_outs[0].to_unsigned_tx_in()],
+            tx_outs: vec![TxOut {
+                script_pubkey: script_pubkey.clone(),
+                amount: 7,
+            }],
+            lock_time: 0,
+        };
+        tx.id = tx.get_transaction_id();
+        tx.tx_ins[0].script_sig = TxIn::sign(tx.clone(), 0, &key, SIGHASH_ALL, &u_tx_outs).unwrap();
+        assert_eq!(tx.get_fee(&u_tx_outs), Some(3));
+        assert!(tx.validate(&u_tx_outs));
+
+        // the wallet doesn't underestimate the size of the signed transaction
+        let target = SelectionTarget::new(&[&script_pubkey], &script_pubkey, 7, 1000);
+        assert!(tx.size() <= target.base_size + input_size(&script_pubkey));
+
+        let mut overspending = tx.clone();
+        overspending.tx_outs[0].amount = 11;
+        overspending.id = overspending.get_transaction_id();
+        assert_eq!(overspending.get_fee(&u_tx_outs), None);
+        assert!(!overspending.validate(&u_tx_outs));
+
+        // the coinbase can claim the fee but not more
+        let coinbase = Transaction::get_coinbase_tx(Script::new(), 6, 3);
+        assert!(Transaction::validate_coinbase_tx(&coinbase, &6, 3));
+        assert!(!Transaction::validate_coinbase_tx(&coinbase, &6, 2));
+    }
+
+    #[test]
+    fn test_sign_and_validate_schnorr() {
+        let key = KeyPair::generate().unwrap().private_key;
+        let schnorr_key = key.to_schnorr_key().unwrap().public_key();
+        let other = KeyPair::generate().unwrap().private_key;
+        let other_key = other.to_schnorr_key().unwrap().public_key();
+
+        for script_pubkey in [
+            Script::pay_to_public_key(&schnorr_key),
+            Script::pay_to_public_key_hash(&hash160(&schnorr_key)),
+            Script::multisig(2, &[schnorr_key.clone(), other_key]),
+        ] {
+            let u_tx_outs = vec![UnspentTxOut {
+                tx_out_id: String::from("parent"),
+                tx_out_index: 0,
+                address: None,
+                script_pubkey: script_pubkey.clone(),
+                amount: 10,
+                block_index: 1,
+                timestamp: 0,
+                is_coinbase: false,
+            }];
+            let mut tx = Transaction {
+                id: String::new(),
+                tx_ins: vec![u_tx_outs[0].to_unsigned_tx_in()],
+                tx_outs: vec![TxOut {
+                    script_pubkey: Script::new(),
+                    amount: 10,
+                }],
+                lock_time: 0,
+            };
+            tx.id = tx.get_transaction_id();
+
+            tx.tx_ins[0].script_sig =
+                TxIn::sign(tx.clone(), 0, &key, SIGHASH_ALL, &u_tx_outs).unwrap();
+            // the 64 byte signature and the sighash type
+            let sig = match &tx.tx_ins[0].script_sig.instructions().unwrap()[0] {
+                Instruction::PushBytes(sig) => sig.clone(),
+                Instruction::Op(_) => vec![],
+            };
+            assert_eq!(sig.len(), SCHNORR_SIGNATURE_SIZE + 1);
+
+            if script_pubkey.as_multisig().is_some() {
+                assert!(!tx.validate(&u_tx_outs));
+                tx.tx_ins[0].script_sig =
+                    TxIn::sign(tx.clone(), 0, &other, SIGHASH_ALL, &u_tx_outs).unwrap();
+            }
+            assert!(tx.validate(&u_tx_outs));
+
+            // the signature doesn't verify for another transaction
+            let mut changed = tx.clone();
+            changed.tx_outs[0].amount = 9;
+            changed.id = changed.get_transaction_id();
+            assert!(!changed.tx_ins[0].validate(&changed, 0, &u_tx_outs));
+        }
+    }
+
+    #[test]
+    fn test_verify_block_scripts() {
+        let key = KeyPair::generate().unwrap().private_key;
+        let ecdsa_key = KeyPair::public_key_to_bytes(&key.to_public_key().unwrap()).unwrap();
+        let schnorr_key = key.to_schnorr_key().unwrap().public_key();
+
+        let u_tx_outs: Vec<UnspentTxOut> = (0..8)
+            .map(|i| UnspentTxOut {
+                tx_out_id: format!("parent{}", i),
+                tx_out_index: 0,
+                address: None,
+                script_pubkey: if i % 4 == 0 {
+                    Script::pay_to_public_key(&ecdsa_key)
+                } else {
+                    Script::pay_to_public_key(&schnorr_key)
+                },
+                amount: 10,
+                block_index: 1,
+                timestamp: 0,
+                is_coinbase: false,
+            })
+            .collect();
+        let mut txs: Vec<Transaction> = u_tx_outs
+            .chunks(2)
+            .map(|parents| {
+                let mut tx = Transaction {
+                    id: String::new(),
+                    tx_ins: parents.iter().map(|u| u.to_unsigned_tx_in()).collect(),
+                    tx_outs: vec![TxOut {
+                        script_pubkey: Script::new(),
+                        amount: 20,
+                    }],
+                    lock_time: 0,
+                };
+                tx.id = tx.get_transaction_id();
+                for index in 0..tx.tx_ins.len() {
+                    tx.tx_ins[index].script_sig =
+                        TxIn::sign(tx.clone(), index as u64, &key, SIGHASH_ALL, &u_tx_outs)
+                            .unwrap();
+                }
+                tx
+            })
+            .collect();
+
+        // a signature with a valid encoding that doesn't verify fails the batch
+        let mut invalid = txs.clone();
+        let mut sig = invalid[1].tx_ins[1].script_sig.0.clone();
+        sig[SCHNORR_SIGNATURE_SIZE - 1] ^= 1;
+        invalid[1].tx_ins[1].script_sig = Script(sig);
+        assert!(!Transaction::verify_scripts(&invalid, &u_tx_outs));
+
+        // the OP_CHECKSIG at the end of the output decides the spend, so it is deferred
+        let deferred = txs[1].tx_ins[1].verify_deferring_schnorr(&txs[1], 1, &u_tx_outs);
+        assert_eq!(deferred.map(|deferred| deferred.len()), Some(1));
+        assert!(Transaction::verify_scripts(&txs, &u_tx_outs));
+
+        // the batch verified signatures are cached and don't get deferred again
+        let deferred = txs[1].tx_ins[1].verify_deferring_schnorr(&txs[1], 1, &u_tx_outs);
+        assert_eq!(deferred, Some(vec![]));
+
+        txs[0].tx_outs[0].amount = 19;
+        assert!(!Transaction::verify_scripts(&txs, &u_tx_outs));
+    }
+
+    #[test]
+    fn test_branch_on_failed_schnorr_checksig() {
+        let key = KeyPair::generate().unwrap().private_key;
+        let schnorr_key = key.to_schnorr_key().unwrap().public_key();
+
+        // spendable with a signature that doesn't verify, the block has to agree with the pool
+        let script_pubkey = Script::new()
+            .push_data(&schnorr_key)
+            .push_opcode(OP_CHECKSIG)
+            .push_opcode(OP_NOTIF)
+            .push_opcode(OP_1)
+            .push_opcode(OP_ELSE)
+            .push_opcode(OP_0)
+            .push_opcode(OP_ENDIF);
+        let u_tx_outs = vec![UnspentTxOut {
+            tx_out_id: String::from("parent"),
+            tx_out_index: 0,
+            address: None,
+            script_pubkey,
+            amount: 10,
+            block_index: 1,
+            timestamp: 0,
+            is_coinbase: false,
+        }];
+        let mut tx = Transaction {
+            id: String::new(),
+            tx_ins: vec![u_tx_outs[0].to_unsigned_tx_in()],
+            tx_outs: vec![TxOut {
+                script_pubkey: Script::new(),
+                amount: 10,
+            }],
+            lock_time: 0,
+        };
+        tx.id = tx.get_transaction_id();
+        let mut sig = vec![1; SCHNORR_SIGNATURE_SIZE];
+        sig.push(SIGHASH_ALL);
+        tx.tx_ins[0].script_sig = Script::new().push_data(&sig);
+
+        assert!(tx.tx_ins[0].validate(&tx, 0, &u_tx_outs));
+        let coinbase = Transaction::get_coinbase_tx(Script::new(), 2, 0);
+        assert!(Transaction::validate_block_transactions(
+            &[coinbase, tx],
+            &u_tx_outs,
+            &2,
+            &0
+        ));
+    }
+
+    #[test]
+    fn test_malformed_keys_and_signatures() {
+        let pub_key = KeyPair::public_key_to_bytes(
+            &KeyPair::generate()
+                .unwrap()
+                .private_key
+                .to_public_key()
+                .unwrap(),
+        )
+        .unwrap();
+        let mut hybrid = pub_key.clone();
+        hybrid[0] = 0x06;
+        let garbage = vec![0xff; COMPRESSED_PUBLIC_KEY_SIZE];
+
+        for (script_pubkey, script_sig) in [
+            // not a DER signature
+            (
+                Script::pay_to_public_key(&pub_key),
+                Script::new().push_data(&[0x30, 0x01, SIGHASH_ALL]),
+            ),
+            (
+                Script::pay_to_public_key(&pub_key),
+                Script::new().push_data(&[]),
+            ),
+            // keys that can't be decoded
+            (
+                Script::pay_to_public_key(&garbage),
+                Script::new().push_data(&[0x30, 0x01, SIGHASH_ALL]),
+            ),
+            (
+                Script::pay_to_public_key_hash(&hash160(&hybrid)),
+                Script::new()
+                    .push_data(&[0x30, 0x01, SIGHASH_ALL])
+                    .push_data(&hybrid),
+            ),
+        ] {
+            let u_tx_outs = vec![UnspentTxOut {
+                tx_out_id: String::from("parent"),
+                tx_out_index: 0,
+                address: None,
+                script_pubkey,
+                amount: 10,
+                block_index: 1,
+                timestamp: 0,
+                is_coinbase: false,
+            }];
+            let mut tx = Transaction {
+                id: String::new(),
+                tx_ins: vec![u_tx_outs[0].to_unsigned_tx_in()],
+                tx_outs: vec![],
+                lock_time: 0,
+            };
+            tx.tx_ins[0].script_sig = script_sig;
+            tx.id = tx.get_transaction_id();
+
+            assert!(!tx.validate(&u_tx_outs));
+        }
+    }
+
+    #[test]
+    fn test_unambiguous_id() {
+        let mut a = spending_tx(SEQUENCE_FINAL, 0);
+        a.tx_ins[0].tx_out_index = 1;
+        a.tx_ins[0].tx_out_id = String::from("23abc");
+        let mut b = spending_tx(SEQUENCE_FINAL, 0);
+        b.tx_ins[0].tx_out_index = 12;
+        b.tx_ins[0].tx_out_id = String::from("3abc");
+
+        assert_ne!(a.get_transaction_id(), b.get_transaction_id());
+    }
+
+    #[test]
+    fn test_sighash_types() {
+        let key = KeyPair::generate().unwrap().private_key;
+        let script_pubkey = Script::pay_to_public_key(
+            &KeyPair::public_key_to_bytes(&key.to_public_key().unwrap()).unwrap(),
+        );
+        let u_tx_out = |id: &str| UnspentTxOut {
+            tx_out_id: id.to_owned(),
+            tx_out_index: 0,
+            address: None,
+            script_pubkey: script_pubkey.clone(),
+            amount: 10,
+            block_index: 1,
+            timestamp: 0,
+            is_coinbase: false,
+        };
+        let u_tx_outs = vec![u_tx_out("first"), u_tx_out("second")];
+        let tx_out = |amount| TxOut {
+            script_pubkey: Script::new(),
+            amount,
+        };
+
+        let mut tx = Transaction {
+            id: String::new(),
+            tx_ins: vec![u_tx_outs[0].to_unsigned_tx_in()],
+            tx_outs: vec![tx_out(10)],
+            lock_time: 0,
+        };
+
+        // the first signer only cares about its own input and the output paying it
+        let hash_type = SIGHASH_SINGLE | SIGHASH_ANYONECANPAY;
+        tx.tx_ins[0].script_sig = TxIn::sign(tx.clone(), 0, &key, hash_type, &u_tx_outs).unwrap();
+
+        // someone else adds an input and an output
+        tx.tx_ins.push(u_tx_outs[1].to_unsigned_tx_in());
+        tx.tx_outs.push(tx_out(10));
+        tx.tx_ins[1].script_sig = TxIn::sign(tx.clone(), 1, &key, SIGHASH_ALL, &u_tx_outs).unwrap();
+        tx.id = tx.get_transaction_id();
+        assert!(tx.validate(&u_tx_outs));
+
+        // changing the output of the first signer breaks its signature
+        let mut changed = tx.clone();
+        changed.tx_outs.swap(0, 1);
+        changed.tx_outs[0].amount = 5;
+        changed.tx_outs[1].amount = 15;
+        changed.id = changed.get_transaction_id();
+        assert!(!changed.tx_ins[0].validate(&changed, 0, &u_tx_outs));
+        assert!(!changed.tx_ins[1].validate(&changed, 1, &u_tx_outs));
+
+        // SIGHASH_SINGLE without a matching output and unknown types can't be signed
+        let single = Transaction {
+            tx_outs: vec![],
+            ..tx.clone()
+        };
+        assert!(TxIn::sign(single, 0, &key, SIGHASH_SINGLE, &u_tx_outs).is_none());
+        assert!(TxIn::sign(tx.clone(), 0, &key, 0x04, &u_tx_outs).is_none());
+
+        // NONE lets anyone change the outputs
+        tx.tx_ins[0].script_sig =
+            TxIn::sign(tx.clone(), 0, &key, SIGHASH_NONE, &u_tx_outs).unwrap();
+        tx.tx_outs[1].amount = 7;
+        tx.tx_outs.push(tx_out(3));
+        assert!(tx.tx_ins[0].validate(&tx, 0, &u_tx_outs));
+    }
+
+    #[test]
+    fn test_coinbase_maturity() {
+        let coinbase = Transaction::get_coinbase_tx(Script::new(), 5, 0);
+        let u_tx_outs =
+            Transaction::update_unspent_tx_out(std::slice::from_ref(&coinbase), &[], &5, &1000);
+        assert!(u_tx_outs[0].is_coinbase);
+
+        let tx = Transaction {
+            id: String::new(),
+            tx_ins: vec![u_tx_outs[0].to_unsigned_tx_in()],
+            tx_outs: vec![TxOut {
+                script_pubkey: Script::new(),
+                amount: COINBASE_AMOUNT,
+            }],
+            lock_time: 0,
+        };
+        assert!(!tx.has_mature_coinbase_inputs(&u_tx_outs, 5 + *COINBASE_MATURITY - 1));
+        assert!(tx.has_mature_coinbase_inputs(&u_tx_outs, 5 + *COINBASE_MATURITY));
+
+        // outputs of normal transactions can be spent right away
+        let u_tx_outs = Transaction::update_unspent_tx_out(&[coinbase, tx], &[], &5, &1000);
+        assert!(!u_tx_outs[1].is_coinbase);
+    }
 }
 
 // #[cfg(test)]
@@ -378,7 +1424,7 @@ fn find_unspent_tx_out(
 //             UnspentTxOut {
 //                 tx_out_id: String::from("f"),
 //                 tx_out_index: 98,
-//                 address: String::new(),
+//                 address: None,
 //                 amount: 100,
 //             },
 //             UnspentTxOut {
@@ -395,7 +1441,7 @@ fn find_unspent_tx_out(
 //             UnspentTxOut {
 //                 tx_out_id: String::from("f"),
 //                 tx_out_index: 98,
-//                 address: String::new(),
+//                 address: None,
 //                 amount: 100,
 //             },
 //             UnspentTxOut {
@@ -417,7 +1463,7 @@ fn find_unspent_tx_out(
 //             UnspentTxOut {
 //                 tx_out_id: String::from("f"),
 //                 tx_out_index: 98,
-//                 address: String::new(),
+//                 address: None,
 //                 amount: 100,
 //             },
 //             UnspentTxOut {
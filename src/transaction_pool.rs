@@ -1,5 +1,6 @@
 use log::{info, warn};
 use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use crate::transaction::{Transaction, TxIn, UnspentTxOut};
 
@@ -11,13 +12,39 @@ impl TransactionPool {
         TransactionPool::default()
     }
 
-    pub fn add(&mut self, tx: Transaction, unspent_tx_outs: &[UnspentTxOut]) -> bool {
+    /// add tx if it is valid for the next block which will have the index block_index
+    pub fn add(
+        &mut self,
+        tx: Transaction,
+        unspent_tx_outs: &[UnspentTxOut],
+        block_index: u64,
+    ) -> bool {
         info!("trying to push tx: {:?} to pool", tx);
 
         if !tx.validate(unspent_tx_outs) {
             return false;
         }
 
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        if !tx.is_final(block_index, now) {
+            warn!("tx {} is locked until {}", tx.id, tx.lock_time);
+            return false;
+        }
+
+        if !tx.has_mature_sequence_locks(unspent_tx_outs, block_index, now) {
+            warn!("tx {} has inputs with immature relative locks", tx.id);
+            return false;
+        }
+
+        if !tx.has_mature_coinbase_inputs(unspent_tx_outs, block_index) {
+            warn!("tx {} spends an immature coinbase output", tx.id);
+            return false;
+        }
+
         for tx_in in tx.tx_ins.iter() {
             if self.contains(tx_in) {
                 warn!("the pool {:?} already contains {:?}", self, tx);
@@ -55,6 +82,14 @@ impl TransactionPool {
         }
     }
 
+    /// what a miner gets for including every transaction of the pool
+    pub fn get_fees(&self, unspent_tx_outs: &[UnspentTxOut]) -> u64 {
+        self.0
+            .iter()
+            .filter_map(|tx| tx.get_fee(unspent_tx_outs))
+            .fold(0, u64::saturating_add)
+    }
+
     fn contains(&self, tx_in: &TxIn) -> bool {
         let pool_tx_ins: Vec<&TxIn> = self.0.iter().flat_map(|tx| &tx.tx_ins).collect();
 
@@ -1,3 +1,4 @@
+pub mod poa;
 pub mod pos;
 pub mod pow;
 
@@ -7,6 +8,7 @@ use std::time::{SystemTime, UNIX_EPOCH};
 use crate::block::Block;
 use crate::chain::BlockChain;
 use crate::difficulter::{simple::SimpleDifficulter, Difficulter};
+use crate::slashing::DoubleSignEvidence;
 use crate::transaction::{Transaction, UnspentTxOut};
 
 pub trait Validator {
@@ -17,7 +19,15 @@ pub trait Validator {
         chain: &BlockChain,
         unspent_tx_outs: &[UnspentTxOut],
     ) -> bool;
-    fn find_block(&self, prev_block: &Block, data: Vec<Transaction>, difficulty: u32) -> Block;
+    /// None if this node can't produce the block
+    fn find_block(
+        &self,
+        chain: &BlockChain,
+        prev_block: &Block,
+        data: Vec<Transaction>,
+        evidence: Vec<DoubleSignEvidence>,
+        difficulty: u32,
+    ) -> Option<Block>;
     // fn has_valid_hash(hash: &str, difficulty: &u32, is_validate: bool) -> bool;
 
     fn has_valid_difficulty(&self, block: &Block, chain: &BlockChain) -> bool {
@@ -0,0 +1,584 @@
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, RwLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    address::Address,
+    block::Block,
+    chain::BlockChain,
+    crypto::PrivateKey,
+    difficulter::simple::START_DIFFICULTY,
+    script::{Instruction, Script, OP_RETURN},
+    slashing::DoubleSignEvidence,
+    transaction::{Transaction, TxOut, UnspentTxOut, SIGHASH_ALL},
+    transaction_pool::TransactionPool,
+    wallet::Wallet,
+};
+
+use super::Validator;
+
+/// marks the OP_RETURN output of a vote
+const VOTE_TAG: &[u8] = b"naivecoin/vote";
+/// every block has the same difficulty, so the chain with the most blocks has the most
+/// accumulated difficulty and an authority can't outweigh a chain with one block
+pub const POA_DIFFICULTY: u32 = START_DIFFICULTY;
+
+/// the authorities take turns, block n is signed by authority n % len. there is no mining, the
+/// wallet of the node signs the block when it is its turn
+pub struct PoaValidator {
+    pub wallet: Arc<RwLock<Wallet>>,
+    pub unspent_tx_outs: Arc<RwLock<Vec<UnspentTxOut>>>,
+    /// the authorities at the genesis block in signing order, votes in the chain change them
+    pub authorities: Vec<Address>,
+}
+
+/// a proposal to add or remove an authority, it passes once more than half of the authorities
+/// voted for it
+#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Debug, Clone)]
+pub struct Vote {
+    pub address: Address,
+    pub add: bool,
+}
+
+impl Vote {
+    /// OP_RETURN <tag> <1 to add, 0 to remove> <address>, nobody can spend it
+    pub fn script(&self) -> Script {
+        Script::new()
+            .push_opcode(OP_RETURN)
+            .push_data(VOTE_TAG)
+            .push_data(&[u8::from(self.add)])
+            .push_data(self.address.to_string().as_bytes())
+    }
+
+    pub fn from_script(script: &Script) -> Option<Self> {
+        match script.instructions().ok()?.as_slice() {
+            [Instruction::Op(OP_RETURN), Instruction::PushBytes(tag), Instruction::PushBytes(add), Instruction::PushBytes(address)]
+                if tag == VOTE_TAG =>
+            {
+                Some(Self {
+                    address: std::str::from_utf8(address).ok()?.parse().ok()?,
+                    add: match add.as_slice() {
+                        [0] => false,
+                        [1] => true,
+                        _ => return None,
+                    },
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// the vote of the transaction and the voter, whose public key is the last push of the first
+    /// input. is_valid checks that the input spends an output of that key
+    pub fn from_transaction(tx: &Transaction) -> Option<(Address, Self)> {
+        let vote = tx
+            .tx_outs
+            .iter()
+            .find_map(|tx_out| Self::from_script(&tx_out.script_pubkey))?;
+        let instructions = tx.tx_ins.first()?.script_sig.instructions().ok()?;
+        let Some(Instruction::PushBytes(public_key)) = instructions.last() else {
+            return None;
+        };
+
+        Some((Address::from_public_key_bytes(public_key), vote))
+    }
+
+    /// spends an output of the voter and pays it back with the vote next to it, the signature of
+    /// the input is the signature of the vote
+    pub fn create_transaction(
+        &self,
+        voter: &Address,
+        private_key: &PrivateKey,
+        unspent_tx_outs: &[UnspentTxOut],
+        pool: &TransactionPool,
+        block_index: u64,
+    ) -> Option<Transaction> {
+        let Some(spent) = unspent_tx_outs.iter().find(|u_tx_out| {
+            u_tx_out.address.as_ref() == Some(voter)
+                && u_tx_out.is_mature(block_index)
+                && !pool.0.iter().flat_map(|tx| tx.tx_ins.iter()).any(|tx_in| {
+                    tx_in.tx_out_id == u_tx_out.tx_out_id
+                        && tx_in.tx_out_index == u_tx_out.tx_out_index
+                })
+        }) else {
+            error!("{} has no spendable output to vote with", voter);
+            return None;
+        };
+
+        let mut tx = Transaction {
+            id: String::new(),
+            tx_ins: vec![spent.to_unsigned_tx_in()],
+            tx_outs: vec![
+                TxOut {
+                    script_pubkey: spent.script_pubkey.clone(),
+                    amount: spent.amount,
+                },
+                TxOut {
+                    script_pubkey: self.script(),
+                    amount: 0,
+                },
+            ],
+            lock_time: 0,
+        };
+        tx.id = tx.get_transaction_id();
+
+        let (tx, signed) = Wallet::sign_transaction(
+            tx,
+            std::slice::from_ref(private_key),
+            SIGHASH_ALL,
+            unspent_tx_outs,
+        );
+
+        (signed == 1).then_some(tx)
+    }
+}
+
+impl PoaValidator {
+    /// the authorities after the votes in the chain, in signing order. votes of others and
+    /// votes that wouldn't change anything are ignored, and the last authority can't be removed
+    pub fn get_authorities(&self, chain: &BlockChain) -> Vec<Address> {
+        let mut authorities = self.authorities.clone();
+        let mut votes: HashMap<Vote, HashSet<Address>> = HashMap::new();
+
+        for (voter, vote) in chain
+            .blocks
+            .iter()
+            .flat_map(|block| block.data.iter())
+            .filter_map(Vote::from_transaction)
+        {
+            if !authorities.contains(&voter)
+                || authorities.contains(&vote.address) == vote.add
+                || (!vote.add && authorities.len() == 1)
+            {
+                continue;
+            }
+
+            let voters = votes.entry(vote.clone()).or_default();
+            voters.insert(voter);
+            if voters.len() <= authorities.len() / 2 {
+                continue;
+            }
+
+            if vote.add {
+                authorities.push(vote.address.clone());
+            } else {
+                authorities.retain(|address| *address != vote.address);
+                // a removed authority doesn't vote anymore
+                for voters in votes.values_mut() {
+                    voters.remove(&vote.address);
+                }
+            }
+            votes.retain(|v, _| v.address != vote.address);
+        }
+
+        authorities
+    }
+
+    /// the authority whose turn it is to sign the block with the given index
+    pub fn get_signer(authorities: &[Address], index: u32) -> Option<Address> {
+        if authorities.is_empty() {
+            return None;
+        }
+
+        Some(authorities[index as usize % authorities.len()].clone())
+    }
+
+    /// only authorities vote, with an output of their own key so the vote is signed by them
+    fn is_valid_vote(
+        tx: &Transaction,
+        authorities: &[Address],
+        unspent_tx_outs: &[UnspentTxOut],
+    ) -> bool {
+        let Some((voter, _)) = Vote::from_transaction(tx) else {
+            // not a vote
+            return true;
+        };
+
+        let tx_in = &tx.tx_ins[0];
+        authorities.contains(&voter)
+            && unspent_tx_outs.iter().any(|u_tx_out| {
+                u_tx_out.tx_out_id == tx_in.tx_out_id
+                    && u_tx_out.tx_out_index == tx_in.tx_out_index
+                    && u_tx_out.address.as_ref() == Some(&voter)
+            })
+    }
+}
+
+impl Validator for PoaValidator {
+    /// unspent_tx_outs is the UTXO set at prev_block, the outputs the votes spend are looked up
+    /// in it
+    fn is_valid(
+        &self,
+        prev_block: &Block,
+        next_block: &Block,
+        chain: &BlockChain,
+        unspent_tx_outs: &[UnspentTxOut],
+    ) -> bool {
+        let authorities = self.get_authorities(chain);
+        let Some(signer) = Self::get_signer(&authorities, next_block.index) else {
+            error!("there are no authorities");
+            return false;
+        };
+        if next_block.miner_address != signer.to_string() {
+            error!("block {} has to be signed by {}", next_block.index, signer);
+            return false;
+        }
+
+        if !next_block.has_valid_signature() {
+            error!("block isn't signed by the authority");
+            return false;
+        }
+
+        if !next_block
+            .data
+            .iter()
+            .all(|tx| Self::is_valid_vote(tx, &authorities, unspent_tx_outs))
+        {
+            error!("block has a vote that isn't signed by an authority");
+            return false;
+        }
+
+        prev_block.index + 1 == next_block.index
+            && prev_block.hash == next_block.previous_hash
+            && next_block.calculate_hash() == next_block.hash
+            && self.has_valid_difficulty(next_block, chain)
+            && self.is_valid_timestamp(next_block, prev_block)
+    }
+
+    /// signs the block if it is the turn of the wallet's address, the wallet has to be unlocked.
+    /// the difficulty is always POA_DIFFICULTY
+    fn find_block(
+        &self,
+        chain: &BlockChain,
+        prev_block: &Block,
+        data: Vec<Transaction>,
+        evidence: Vec<DoubleSignEvidence>,
+        _difficulty: u32,
+    ) -> Option<Block> {
+        let index = prev_block.index + 1;
+        let wallet = self.wallet.read().unwrap();
+        let address = match wallet.get_address() {
+            Ok(address) => address,
+            Err(e) => {
+                error!("can't sign a block: {}", e);
+                return None;
+            }
+        };
+
+        let authorities = self.get_authorities(chain);
+        match Self::get_signer(&authorities, index) {
+            Some(signer) if signer == address => {}
+            Some(signer) => {
+                error!("it is the turn of {} to sign block {}", signer, index);
+                return None;
+            }
+            None => {
+                error!("there are no authorities");
+                return None;
+            }
+        }
+
+        let private_key = match wallet.get_private_key(&address) {
+            Ok(private_key) => private_key,
+            Err(e) => {
+                error!("can't sign a block: {}", e);
+                return None;
+            }
+        };
+        drop(wallet);
+
+        // votes that would make the block invalid are left in the pool
+        let unspent_tx_outs = self.unspent_tx_outs.read().unwrap();
+        let data: Vec<Transaction> = data
+            .into_iter()
+            .filter(|tx| Self::is_valid_vote(tx, &authorities, &unspent_tx_outs))
+            .collect();
+        drop(unspent_tx_outs);
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let miner_address = address.to_string();
+        let hash = Block::calculate_hash_from_data(
+            &index,
+            &prev_block.hash,
+            &timestamp,
+            &data,
+            &POA_DIFFICULTY,
+            &0,
+            &miner_address,
+            &0,
+            &evidence,
+        );
+        let mut block = Block {
+            index,
+            previous_hash: prev_block.hash.clone(),
+            timestamp,
+            data,
+            hash,
+            difficulty: POA_DIFFICULTY,
+            nonce: 0,
+            miner_address,
+            miner_balance: 0,
+            signature: String::new(),
+            evidence,
+        };
+        if let Err(e) = block.sign(&private_key, &address) {
+            error!("could not sign the block: {}", e);
+            return None;
+        }
+
+        Some(block)
+    }
+
+    fn has_valid_difficulty(&self, block: &Block, _chain: &BlockChain) -> bool {
+        let r = block.difficulty == POA_DIFFICULTY;
+        if !r {
+            error!(
+                "proof of authority blocks have difficulty {}",
+                POA_DIFFICULTY
+            )
+        }
+
+        r
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::KeyPair;
+    use crate::test_util::{new_wallet, utxo, PASSPHRASE};
+    use crate::transaction::TxIn;
+
+    fn new_key() -> (Vec<u8>, Address) {
+        let key = KeyPair::generate().unwrap().private_key;
+        let public_key = KeyPair::public_key_to_bytes(&key.to_public_key().unwrap()).unwrap();
+        let address = Address::from_public_key_bytes(&public_key);
+
+        (public_key, address)
+    }
+
+    /// the tally doesn't check signatures, the blocks were validated when they were added
+    fn vote_tx(public_key: &[u8], address: &Address, add: bool) -> Transaction {
+        Transaction {
+            id: String::new(),
+            tx_ins: vec![TxIn {
+                tx_out_id: String::from("spent"),
+                tx_out_index: 0,
+                script_sig: Script::new().push_data(b"signature").push_data(public_key),
+                sequence: u32::MAX,
+            }],
+            tx_outs: vec![TxOut {
+                script_pubkey: Vote {
+                    address: address.clone(),
+                    add,
+                }
+                .script(),
+                amount: 0,
+            }],
+            lock_time: 0,
+        }
+    }
+
+    #[test]
+    fn test_vote_script() {
+        let (_, address) = new_key();
+        for add in [true, false] {
+            let vote = Vote {
+                address: address.clone(),
+                add,
+            };
+            assert_eq!(Vote::from_script(&vote.script()), Some(vote));
+        }
+        assert_eq!(Vote::from_script(&address.script_pubkey()), None);
+        assert_eq!(
+            Vote::from_script(&Script::new().push_opcode(OP_RETURN).push_data(b"data")),
+            None
+        );
+    }
+
+    #[test]
+    fn test_round_robin() {
+        let location = tempfile::NamedTempFile::new().unwrap();
+        let mut wallet = new_wallet(&location);
+        wallet.unlock(PASSPHRASE, None).unwrap();
+        let address = wallet.get_address().unwrap();
+        let (_, other) = new_key();
+        let validator = PoaValidator {
+            wallet: Arc::new(RwLock::new(wallet)),
+            unspent_tx_outs: Arc::default(),
+            authorities: vec![other.clone(), address.clone()],
+        };
+
+        let mut chain = BlockChain::default();
+        let genesis = BlockChain::get_genesis();
+        let signer = |chain: &BlockChain, index| {
+            PoaValidator::get_signer(&validator.get_authorities(chain), index)
+        };
+        assert_eq!(signer(&chain, 1), Some(address.clone()));
+        // the difficulty that is asked for is ignored
+        let block = validator
+            .find_block(&chain, &genesis, vec![], vec![], 64)
+            .unwrap();
+        assert_eq!(block.difficulty, POA_DIFFICULTY);
+        assert!(validator.is_valid(&genesis, &block, &chain, &[]));
+
+        // an authority can't make its block outweigh a longer chain
+        let private_key = validator
+            .wallet
+            .read()
+            .unwrap()
+            .get_private_key(&address)
+            .unwrap();
+        let mut heavy = block.clone();
+        heavy.difficulty = 64;
+        heavy.hash = heavy.calculate_hash();
+        heavy.sign(&private_key, &address).unwrap();
+        assert!(!validator.is_valid(&genesis, &heavy, &chain, &[]));
+
+        // the other authority signs the next block
+        chain.blocks.push(block.clone());
+        assert_eq!(signer(&chain, 2), Some(other));
+        assert!(validator
+            .find_block(&chain, &block, vec![], vec![], 0)
+            .is_none());
+
+        let mut out_of_turn = block.clone();
+        out_of_turn.index = 2;
+        out_of_turn.previous_hash = block.hash.clone();
+        out_of_turn.hash = out_of_turn.calculate_hash();
+        out_of_turn.sign(&private_key, &address).unwrap();
+        assert!(!validator.is_valid(&block, &out_of_turn, &chain, &[]));
+
+        // an unsigned block
+        let mut unsigned = block;
+        unsigned.signature = String::new();
+        assert!(!validator.is_valid(&genesis, &unsigned, &BlockChain::default(), &[]));
+    }
+
+    #[test]
+    fn test_voting() {
+        let (a_key, a) = new_key();
+        let (b_key, b) = new_key();
+        let (c_key, c) = new_key();
+        let (d_key, d) = new_key();
+        let (e_key, _) = new_key();
+        let validator = PoaValidator {
+            // the tally doesn't need the wallet
+            wallet: Arc::new(RwLock::new(Wallet::new(String::new()))),
+            unspent_tx_outs: Arc::default(),
+            authorities: vec![a.clone(), b.clone(), c.clone()],
+        };
+
+        let mut chain = BlockChain::default();
+        let mut push = |txs: Vec<Transaction>| {
+            let mut block = BlockChain::get_genesis();
+            block.data = txs;
+            chain.blocks.push(block);
+            validator.get_authorities(&chain)
+        };
+
+        // one vote out of three isn't a majority
+        assert_eq!(
+            push(vec![vote_tx(&a_key, &d, true)]),
+            vec![a.clone(), b.clone(), c.clone()]
+        );
+        // voting twice doesn't count
+        assert_eq!(push(vec![vote_tx(&a_key, &d, true)]).len(), 3);
+        assert_eq!(
+            push(vec![vote_tx(&b_key, &d, true)]),
+            vec![a.clone(), b.clone(), c.clone(), d.clone()]
+        );
+
+        // others can't vote
+        assert_eq!(push(vec![vote_tx(&e_key, &a, false)]).len(), 4);
+
+        // 3 of 4 remove c
+        assert_eq!(
+            push(vec![
+                vote_tx(&a_key, &c, false),
+                vote_tx(&b_key, &c, false),
+                vote_tx(&c_key, &a, false),
+            ])
+            .len(),
+            4
+        );
+        assert_eq!(
+            push(vec![vote_tx(&d_key, &c, false)]),
+            vec![a.clone(), b.clone(), d.clone()]
+        );
+        // c's vote to remove a went with it
+        assert_eq!(push(vec![vote_tx(&b_key, &a, false)]).len(), 3);
+    }
+
+    #[test]
+    fn test_vote_transaction() {
+        let location = tempfile::NamedTempFile::new().unwrap();
+        let mut wallet = new_wallet(&location);
+        wallet.unlock(PASSPHRASE, None).unwrap();
+        let address = wallet.get_address().unwrap();
+        let private_key = wallet.get_private_key(&address).unwrap();
+        let (_, candidate) = new_key();
+
+        let funding = utxo("funding", &address, 50, 0);
+        let vote = Vote {
+            address: candidate,
+            add: true,
+        };
+        let tx = vote
+            .create_transaction(
+                &address,
+                &private_key,
+                std::slice::from_ref(&funding),
+                &TransactionPool::default(),
+                1,
+            )
+            .unwrap();
+        assert!(tx.validate(std::slice::from_ref(&funding)));
+        assert_eq!(
+            Vote::from_transaction(&tx),
+            Some((address.clone(), vote.clone()))
+        );
+
+        // the vote has to spend an output of an authority
+        let authorities = vec![address.clone()];
+        assert!(PoaValidator::is_valid_vote(
+            &tx,
+            &authorities,
+            std::slice::from_ref(&funding)
+        ));
+        assert!(!PoaValidator::is_valid_vote(
+            &tx,
+            &[],
+            std::slice::from_ref(&funding)
+        ));
+        let (public_key, _) = new_key();
+        let mut forged = tx.clone();
+        forged.tx_ins[0].script_sig = Script::new().push_data(b"any").push_data(&public_key);
+        let forger = Address::from_public_key_bytes(&public_key);
+        assert!(!PoaValidator::is_valid_vote(
+            &forged,
+            &[forger],
+            std::slice::from_ref(&funding)
+        ));
+
+        // nothing left to vote with
+        let mut pool = TransactionPool::default();
+        assert!(pool.add(tx, std::slice::from_ref(&funding), 1));
+        assert!(vote
+            .create_transaction(
+                &address,
+                &private_key,
+                std::slice::from_ref(&funding),
+                &pool,
+                1
+            )
+            .is_none());
+    }
+}
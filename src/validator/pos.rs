@@ -1,31 +1,82 @@
 use log::{error, info};
+use once_cell::sync::Lazy;
 use primitive_types::U512;
 use sha2::{Digest, Sha256};
 use std::{
+    collections::HashSet,
     ops::{Div, Mul},
     sync::{Arc, RwLock},
     thread,
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use crate::{block::Block, crypto::KeyPair, transaction::UnspentTxOut, wallet::Wallet};
+use crate::{
+    address::Address,
+    block::Block,
+    slashing::DoubleSignEvidence,
+    transaction::{Transaction, UnspentTxOut, SIGHASH_ALL},
+    wallet::Wallet,
+    COINBASE_MATURITY,
+};
 
 use super::Validator;
 
 pub struct PosValidator {
     pub wallet: Arc<RwLock<Wallet>>,
     pub unspent_tx_outs: Arc<RwLock<Vec<UnspentTxOut>>>,
+    /// the only addresses that can produce blocks before any output is old enough to stake,
+    /// every node of a network has to use the same list. the kernel is the address then, so
+    /// nobody can try fresh addresses until one wins a slot
+    pub bootstrap_stakers: Vec<Address>,
+}
+
+/// number of blocks an output has to wait before it can be staked, at least the coinbase maturity
+/// so the coinstake can spend it
+pub static STAKE_MIN_AGE: Lazy<u64> = Lazy::new(|| *COINBASE_MATURITY);
+/// older outputs don't weigh more, so coins can't be hoarded to win a run of blocks
+pub const STAKE_MAX_AGE: u64 = 100;
+/// no output can be STAKE_MIN_AGE blocks old before this many blocks, until then blocks are
+/// produced by the bootstrap stakers without a stake and the kernel weighs this much
+const BOOTSTRAP_WEIGHT: u64 = 1;
+
+/// block timestamps are multiples of this many seconds, so a staker gets one try per slot
+pub const STAKE_TIMESTAMP_GRANULARITY: u64 = 4;
+/// how far in the future of the validating node's clock a block timestamp can be. the kernel
+/// hashes the timestamp, so this bounds the tries a staker gets by lying about the time
+pub const MAX_FUTURE_DRIFT: u64 = 60;
+
+fn is_bootstrap(index: u32) -> bool {
+    index as u64 <= *STAKE_MIN_AGE
 }
 
-const ALLOW_WITHOUT_COIN_INDEX: u8 = 10;
+/// coin age of an output staked in the block with the given index: the amount times the number
+/// of blocks since it was created, up to STAKE_MAX_AGE. None if it is younger than STAKE_MIN_AGE
+pub fn stake_weight(u_tx_out: &UnspentTxOut, block_index: u64) -> Option<u64> {
+    let age = block_index.checked_sub(u_tx_out.block_index)?;
+    if age < *STAKE_MIN_AGE {
+        return None;
+    }
+
+    Some(u_tx_out.amount.saturating_mul(age.min(STAKE_MAX_AGE)))
+}
 
-/// does the calculation SHA256(prevhash + address + timestamp) <= 2^256 * balance / diff
+/// the staked output, which can only be used once since the coinstake spends it
+fn stake_kernel(u_tx_out: &UnspentTxOut) -> Vec<u8> {
+    let mut kernel = u_tx_out.tx_out_id.as_bytes().to_vec();
+    kernel.extend(u_tx_out.tx_out_index.to_be_bytes());
+    kernel.extend(u_tx_out.block_index.to_be_bytes());
+
+    kernel
+}
+
+/// does the calculation SHA256(prevhash + kernel + timestamp) <= 2^256 * weight / diff
+/// with the timestamp of the block, so the result doesn't depend on when it is checked
 /// Reference: https://blog.ethereum.org/2014/07/05/stake
 fn check_special_hash(
-    index: u32,
     prev_hash: &[u8],
-    address: &String,
-    balance: u64,
+    kernel: &[u8],
+    timestamp: u64,
+    weight: u64,
     diff: u32,
 ) -> bool {
     // TODO: let validator have a difficulter field
@@ -33,94 +84,250 @@ fn check_special_hash(
         return true;
     }
 
-    let mut balance = balance;
-    if index <= ALLOW_WITHOUT_COIN_INDEX as u32 {
-        balance += 1;
-    }
-
     let mut hasher = Sha256::new();
 
-    let timestamp = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs();
-
     hasher.update(prev_hash);
-    hasher.update(&address);
+    hasher.update(kernel);
     hasher.update(timestamp.to_be_bytes());
 
     let hash: [u8; 32] = *hasher.finalize().as_ref();
 
     let left_side = U512::from_big_endian(&hash);
-    let right_side: U512 = U512::from(2).pow(256.into()).mul(balance).div(diff);
+    let right_side: U512 = U512::from(2).pow(256.into()).mul(weight).div(diff);
+
+    left_side <= right_side
+}
 
-    let valid = left_side <= right_side;
-    if !valid {
-        error!("check_special_hash false");
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+impl PosValidator {
+    /// the output staked by the coinstake, the second transaction of the block, and its weight
+    fn find_stake<'a>(
+        block: &Block,
+        miner_address: &Address,
+        unspent_tx_outs: &'a [UnspentTxOut],
+    ) -> Option<(&'a UnspentTxOut, u64)> {
+        let Some(coinstake) = block.data.get(1).filter(|tx| tx.is_coinstake()) else {
+            error!("block doesn't have a coinstake transaction");
+            return None;
+        };
+
+        let tx_in = &coinstake.tx_ins[0];
+        let Some(staked) = unspent_tx_outs.iter().find(|u_tx_out| {
+            u_tx_out.tx_out_id == tx_in.tx_out_id && u_tx_out.tx_out_index == tx_in.tx_out_index
+        }) else {
+            error!("the staked output is spent or doesn't exist");
+            return None;
+        };
+
+        if staked.address.as_ref() != Some(miner_address) {
+            error!("the staked output doesn't belong to the miner");
+            return None;
+        }
+
+        match stake_weight(staked, block.index as u64) {
+            Some(weight) => Some((staked, weight)),
+            None => {
+                error!(
+                    "the staked output is younger than {} blocks",
+                    *STAKE_MIN_AGE
+                );
+                None
+            }
+        }
     }
-    valid
 }
 
 impl Validator for PosValidator {
+    /// unspent_tx_outs is the UTXO set at prev_block, the stake is looked up in it
     fn is_valid(
         &self,
         prev_block: &crate::block::Block,
         next_block: &crate::block::Block,
         chain: &crate::chain::BlockChain,
-        _unspent_tx_outs: &[UnspentTxOut],
+        unspent_tx_outs: &[UnspentTxOut],
     ) -> bool {
         info!("prev_block: {:?}", prev_block);
         info!("next_block: {:?}", next_block);
 
-        check_special_hash(
-            next_block.index,
+        let miner_address = match next_block.miner_address.parse::<Address>() {
+            Ok(address) => address,
+            Err(e) => {
+                error!("invalid miner address {}: {}", next_block.miner_address, e);
+                return false;
+            }
+        };
+
+        let (kernel, weight) = if is_bootstrap(next_block.index) {
+            if !self.bootstrap_stakers.contains(&miner_address) {
+                error!("{} is not a bootstrap staker", miner_address);
+                return false;
+            }
+            (
+                next_block.miner_address.as_bytes().to_vec(),
+                BOOTSTRAP_WEIGHT,
+            )
+        } else {
+            match Self::find_stake(next_block, &miner_address, unspent_tx_outs) {
+                Some((staked, weight)) => (stake_kernel(staked), weight),
+                None => return false,
+            }
+        };
+
+        if next_block.miner_balance != weight {
+            error!(
+                "block claims a stake of {} but the staked output weighs {}",
+                next_block.miner_balance, weight
+            );
+            return false;
+        }
+
+        if !next_block.has_valid_signature() {
+            error!("block isn't signed by the miner");
+            return false;
+        }
+
+        if !check_special_hash(
             prev_block.hash.as_bytes(),
-            &next_block.miner_address,
-            next_block.miner_balance,
+            &kernel,
+            next_block.timestamp,
+            weight,
             next_block.difficulty,
-        ) && prev_block.index + 1 == next_block.index
+        ) {
+            error!("the stake kernel doesn't meet the target");
+            return false;
+        }
+
+        prev_block.index + 1 == next_block.index
             && prev_block.hash == next_block.previous_hash
             && next_block.calculate_hash() == next_block.hash
             && self.has_valid_difficulty(next_block, chain)
             && self.is_valid_timestamp(next_block, prev_block)
     }
 
+    /// the kernel hashes the timestamp, so it has to be on the granularity, can't go back from
+    /// the previous block and can't be too far in the future
+    fn is_valid_timestamp(&self, next_block: &Block, prev_block: &Block) -> bool {
+        let r = next_block
+            .timestamp
+            .is_multiple_of(STAKE_TIMESTAMP_GRANULARITY)
+            && next_block.timestamp >= prev_block.timestamp
+            && next_block.timestamp <= now() + MAX_FUTURE_DRIFT;
+        if !r {
+            error!("block doesn't have a valid timestamp");
+        }
+
+        r
+    }
+
+    /// tries every output of the wallet's address that is old enough in each timestamp slot.
+    /// the wallet has to be unlocked to sign the coinstake and the block
     fn find_block(
         &self,
+        _: &crate::chain::BlockChain,
         prev_block: &crate::block::Block,
         data: Vec<crate::transaction::Transaction>,
+        evidence: Vec<DoubleSignEvidence>,
         difficulty: u32,
-    ) -> Block {
-        let pub_key = &self.wallet.read().unwrap().get_public_key();
-        let my_addr = KeyPair::public_key_to_hex(pub_key);
-        info!("my_addr: {}", my_addr);
-        let my_balance = Wallet::get_balance(&my_addr, &self.unspent_tx_outs.read().unwrap());
+    ) -> Option<Block> {
+        let wallet = self.wallet.read().unwrap();
+        let address = match wallet.get_address() {
+            Ok(address) => address,
+            Err(e) => {
+                error!("can't sign a block: {}", e);
+                return None;
+            }
+        };
+        info!("my_addr: {}", address);
+        let private_key = match wallet.get_private_key(&address) {
+            Ok(private_key) => private_key,
+            Err(e) => {
+                error!("can't sign a block: {}", e);
+                return None;
+            }
+        };
+        drop(wallet);
+        let unspent_tx_outs = self.unspent_tx_outs.read().unwrap().clone();
+        let index = prev_block.index + 1;
+        let my_addr = address.to_string();
+
+        // (staked output, kernel, weight)
+        let candidates: Vec<(Option<&UnspentTxOut>, Vec<u8>, u64)> = if is_bootstrap(index) {
+            if !self.bootstrap_stakers.contains(&address) {
+                error!("only the bootstrap stakers produce the first blocks");
+                return None;
+            }
+            vec![(None, my_addr.as_bytes().to_vec(), BOOTSTRAP_WEIGHT)]
+        } else {
+            // outputs the transactions of the block spend can't be staked too
+            let spent: HashSet<(&str, u64)> = data
+                .iter()
+                .flat_map(|tx| tx.tx_ins.iter())
+                .map(|tx_in| (tx_in.tx_out_id.as_str(), tx_in.tx_out_index))
+                .collect();
+
+            unspent_tx_outs
+                .iter()
+                .filter(|u_tx_out| u_tx_out.address.as_ref() == Some(&address))
+                .filter(|u_tx_out| {
+                    !spent.contains(&(u_tx_out.tx_out_id.as_str(), u_tx_out.tx_out_index))
+                })
+                .filter_map(|u_tx_out| {
+                    let weight = stake_weight(u_tx_out, index as u64)?;
+                    Some((Some(u_tx_out), stake_kernel(u_tx_out), weight))
+                })
+                .collect()
+        };
+        if candidates.is_empty() {
+            error!("no outputs are old enough to stake");
+            return None;
+        }
 
         loop {
-            let timestamp = SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_secs();
-
-            let hash = Block::calculate_hash_from_data(
-                &(prev_block.index + 1),
-                &prev_block.hash,
-                &timestamp,
-                &data,
-                &difficulty,
-                &0,
-                &my_addr,
-            );
+            let now = now();
+            let timestamp = (now - now % STAKE_TIMESTAMP_GRANULARITY).max(prev_block.timestamp);
 
-            if check_special_hash(
-                prev_block.index + 1,
-                prev_block.hash.as_bytes(),
-                &my_addr,
-                my_balance,
-                difficulty,
-            ) {
-                return Block {
-                    index: (prev_block.index + 1),
+            let found = candidates.iter().find(|(_, kernel, weight)| {
+                check_special_hash(
+                    prev_block.hash.as_bytes(),
+                    kernel,
+                    timestamp,
+                    *weight,
+                    difficulty,
+                )
+            });
+
+            if let Some((staked, _, weight)) = found {
+                let mut data = data;
+                if let Some(staked) = staked {
+                    let (coinstake, _) = Wallet::sign_transaction(
+                        Transaction::get_coinstake_tx(staked),
+                        std::slice::from_ref(&private_key),
+                        SIGHASH_ALL,
+                        &unspent_tx_outs,
+                    );
+                    // right after the coinbase
+                    data.insert(data.len().min(1), coinstake);
+                }
+
+                let hash = Block::calculate_hash_from_data(
+                    &index,
+                    &prev_block.hash,
+                    &timestamp,
+                    &data,
+                    &difficulty,
+                    &0,
+                    &my_addr,
+                    weight,
+                    &evidence,
+                );
+                let mut block = Block {
+                    index,
                     previous_hash: prev_block.hash.clone(),
                     timestamp,
                     data,
@@ -128,21 +335,192 @@ impl Validator for PosValidator {
                     difficulty,
                     nonce: 0,
                     miner_address: my_addr,
-                    miner_balance: my_balance,
+                    miner_balance: *weight,
+                    signature: String::new(),
+                    evidence,
                 };
+                if let Err(e) = block.sign(&private_key, &address) {
+                    error!("could not sign the block: {}", e);
+                    return None;
+                }
+
+                return Some(block);
             }
 
-            thread::sleep(Duration::from_secs_f32(0.9)); // wait a little less than 1 second
+            // the next slot
+            thread::sleep(Duration::from_secs(
+                timestamp + STAKE_TIMESTAMP_GRANULARITY - now,
+            ));
         }
     }
 }
 
-// #[cfg(test)]
-// mod tests {
-//     use super::*;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::BlockChain;
+    use crate::crypto::KeyPair;
+    use crate::test_util::{new_wallet, utxo, PASSPHRASE};
 
-//     #[test]
-//     fn test_check_special_hash() {
-//         todo!()
-//     }
-// }
+    #[test]
+    fn test_coin_age_stake() {
+        let location = tempfile::NamedTempFile::new().unwrap();
+        let wallet = new_wallet(&location);
+        let address = wallet.get_address().unwrap();
+        let wallet = Arc::new(RwLock::new(wallet));
+
+        let funding = utxo("funding", &address, 50, 0);
+        let unspent_tx_outs = vec![funding.clone()];
+        let validator = PosValidator {
+            wallet: wallet.clone(),
+            unspent_tx_outs: Arc::new(RwLock::new(unspent_tx_outs.clone())),
+            bootstrap_stakers: vec![],
+        };
+        let chain = BlockChain::default();
+        let prev = Block {
+            index: 20,
+            ..BlockChain::get_genesis()
+        };
+        let coinbase = Transaction::get_coinbase_tx(address.script_pubkey(), 21, 0);
+
+        assert_eq!(stake_weight(&funding, *STAKE_MIN_AGE - 1), None);
+        assert_eq!(stake_weight(&funding, 21), Some(50 * 21));
+        assert_eq!(stake_weight(&funding, 1000), Some(50 * STAKE_MAX_AGE));
+
+        // a locked wallet can't sign
+        assert!(validator
+            .find_block(&chain, &prev, vec![coinbase.clone()], vec![], 0)
+            .is_none());
+        wallet.write().unwrap().unlock(PASSPHRASE, None).unwrap();
+        let block = validator
+            .find_block(&chain, &prev, vec![coinbase.clone()], vec![], 0)
+            .unwrap();
+        assert_eq!(block.miner_balance, 50 * 21);
+        assert!(block.data[1].is_coinstake());
+        assert!(validator.is_valid(&prev, &block, &chain, &unspent_tx_outs));
+
+        // the coinstake spends the staked output, so its age starts over
+        let next_unspent_tx_outs =
+            Transaction::process_transaction(&block.data, &unspent_tx_outs, &21, &block.timestamp)
+                .unwrap();
+        let restaked: Vec<&UnspentTxOut> = next_unspent_tx_outs
+            .iter()
+            .filter(|u_tx_out| u_tx_out.tx_out_id == block.data[1].id)
+            .collect();
+        assert_eq!(restaked.len(), 1);
+        assert_eq!(restaked[0].amount, 50);
+        assert_eq!(stake_weight(restaked[0], 22), None);
+
+        // the stake is checked against the UTXO set at the parent
+        assert!(!validator.is_valid(&prev, &block, &chain, &next_unspent_tx_outs));
+        let mut inflated = block.clone();
+        inflated.miner_balance = 1000 * 21;
+        inflated.hash = inflated.calculate_hash();
+        let private_key = wallet.read().unwrap().get_private_key(&address).unwrap();
+        inflated.sign(&private_key, &address).unwrap();
+        assert!(!validator.is_valid(&prev, &inflated, &chain, &unspent_tx_outs));
+
+        // only the miner can sign
+        let mut forged = block;
+        let other = KeyPair::generate().unwrap().private_key;
+        forged.sign(&other, &address).unwrap();
+        assert!(!validator.is_valid(&prev, &forged, &chain, &unspent_tx_outs));
+
+        // young outputs can't be staked
+        *validator.unspent_tx_outs.write().unwrap() = vec![UnspentTxOut {
+            block_index: 15,
+            ..funding
+        }];
+        assert!(validator
+            .find_block(&chain, &prev, vec![coinbase], vec![], 0)
+            .is_none());
+    }
+
+    #[test]
+    fn test_bootstrap_stakers() {
+        let locations = [(); 2].map(|_| tempfile::NamedTempFile::new().unwrap());
+        let wallets: Vec<Arc<RwLock<Wallet>>> = locations
+            .iter()
+            .map(|location| {
+                let mut wallet = new_wallet(location);
+                wallet.unlock(PASSPHRASE, None).unwrap();
+                Arc::new(RwLock::new(wallet))
+            })
+            .collect();
+        let staker = wallets[0].read().unwrap().get_address().unwrap();
+        let fresh = wallets[1].read().unwrap().get_address().unwrap();
+        let validator =
+            |wallet: &Arc<RwLock<Wallet>>, bootstrap_stakers: Vec<Address>| PosValidator {
+                wallet: wallet.clone(),
+                unspent_tx_outs: Arc::default(),
+                bootstrap_stakers,
+            };
+
+        let chain = BlockChain::default();
+        let prev = BlockChain::get_genesis();
+        let coinbase =
+            |address: &Address| vec![Transaction::get_coinbase_tx(address.script_pubkey(), 1, 0)];
+
+        let block = validator(&wallets[0], vec![staker.clone()])
+            .find_block(&chain, &prev, coinbase(&staker), vec![], 0)
+            .unwrap();
+        assert!(validator(&wallets[0], vec![staker.clone()]).is_valid(&prev, &block, &chain, &[]));
+
+        // a fresh address can neither produce the first blocks nor get them accepted
+        assert!(validator(&wallets[1], vec![staker.clone()])
+            .find_block(&chain, &prev, coinbase(&fresh), vec![], 0)
+            .is_none());
+        let block = validator(&wallets[1], vec![fresh.clone()])
+            .find_block(&chain, &prev, coinbase(&fresh), vec![], 0)
+            .unwrap();
+        assert!(!validator(&wallets[1], vec![staker]).is_valid(&prev, &block, &chain, &[]));
+    }
+
+    #[test]
+    fn test_deterministic_kernel() {
+        let prev_hash = BlockChain::get_genesis().hash;
+        let kernel = Address::from_public_key_bytes(&[2; 33]).to_string();
+
+        // with half of the target some slots are eligible and some aren't, and checking a slot
+        // again later gives the same answer
+        let slots: Vec<bool> = (0..64)
+            .map(|slot| {
+                let timestamp = 1_700_000_000 + slot * STAKE_TIMESTAMP_GRANULARITY;
+                let eligible =
+                    check_special_hash(prev_hash.as_bytes(), kernel.as_bytes(), timestamp, 1, 2);
+                assert_eq!(
+                    eligible,
+                    check_special_hash(prev_hash.as_bytes(), kernel.as_bytes(), timestamp, 1, 2)
+                );
+                eligible
+            })
+            .collect();
+        assert!(slots.contains(&true) && slots.contains(&false));
+
+        let validator = PosValidator {
+            wallet: Arc::new(RwLock::new(Wallet::new(String::new()))),
+            unspent_tx_outs: Arc::default(),
+            bootstrap_stakers: vec![],
+        };
+        let prev = Block {
+            timestamp: 1_700_000_000,
+            ..BlockChain::get_genesis()
+        };
+        let at = |timestamp| Block {
+            timestamp,
+            ..prev.clone()
+        };
+        assert!(validator.is_valid_timestamp(&at(prev.timestamp), &prev));
+        assert!(
+            validator.is_valid_timestamp(&at(now() - now() % STAKE_TIMESTAMP_GRANULARITY), &prev)
+        );
+        // off the granularity, before the previous block or too far in the future
+        assert!(!validator.is_valid_timestamp(&at(prev.timestamp + 1), &prev));
+        assert!(
+            !validator.is_valid_timestamp(&at(prev.timestamp - STAKE_TIMESTAMP_GRANULARITY), &prev)
+        );
+        let future = now() + MAX_FUTURE_DRIFT + STAKE_TIMESTAMP_GRANULARITY;
+        assert!(!validator
+            .is_valid_timestamp(&at(future - future % STAKE_TIMESTAMP_GRANULARITY), &prev));
+    }
+}
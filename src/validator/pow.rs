@@ -1,7 +1,10 @@
 use log::error;
 use std::time::{SystemTime, UNIX_EPOCH};
 
-use crate::{block::Block, chain::BlockChain, transaction::UnspentTxOut, validator::Validator};
+use crate::{
+    block::Block, chain::BlockChain, slashing::DoubleSignEvidence, transaction::UnspentTxOut,
+    validator::Validator,
+};
 
 #[derive(Debug)]
 pub struct PowValidator {}
@@ -75,10 +78,12 @@ impl Validator for PowValidator {
 
     fn find_block(
         &self,
+        _: &BlockChain,
         prev_block: &Block,
         data: Vec<crate::transaction::Transaction>,
+        evidence: Vec<DoubleSignEvidence>,
         difficulty: u32,
-    ) -> Block {
+    ) -> Option<Block> {
         let index = prev_block.index + 1;
         let previous_hash = prev_block.hash.clone();
         let timestamp = SystemTime::now()
@@ -96,10 +101,12 @@ impl Validator for PowValidator {
                 &difficulty,
                 &nonce,
                 &String::new(),
+                &0,
+                &evidence,
             );
 
             if PowValidator::has_valid_hash(&hash, &difficulty, false) {
-                return Block {
+                return Some(Block {
                     index,
                     previous_hash,
                     timestamp,
@@ -109,7 +116,9 @@ impl Validator for PowValidator {
                     nonce,
                     miner_balance: 0,
                     miner_address: String::new(),
-                };
+                    signature: String::new(),
+                    evidence,
+                });
             }
             nonce += 1;
         }
@@ -9,26 +9,31 @@ use tempfile::NamedTempFile;
 
 use naivecoin_rs::block::Block;
 use naivecoin_rs::transaction::Transaction;
+use naivecoin_rs::wallet::Wallet;
 
 const HTTP_PORT_0: &str = "8000";
 const HTTP_PORT_1: &str = "8001";
 const HTTP_PORT_2: &str = "8002";
 
 const SLEEP_DURATION: f32 = 0.5;
+const PASSPHRASE: &str = "correct horse battery staple";
 
 struct InstanceConfig {
     pub http_port: String,
     pub p2p_port: String,
     pub key_loc: String,
     pub initial: Vec<String>,
+    pub bootstrap_stakers: Vec<String>,
 }
 
 fn start_instance(config: &InstanceConfig) -> Child {
     Command::new("./target/debug/naivecoin-rs")
         .env("HTTP_PORT", &config.http_port)
         .env("KEY_LOC", &config.key_loc)
-        .env("INITIAL", &config.initial.join(","))
+        .env("WALLET_PASSPHRASE", PASSPHRASE)
+        .env("INITIAL", config.initial.join(","))
         .env("P2P_PORT", &config.p2p_port)
+        .env("BOOTSTRAP_STAKERS", config.bootstrap_stakers.join(","))
         .env("RUST_LOG", String::from("INFO"))
         .stdin(Stdio::null())
         .stdout(Stdio::null())
@@ -46,27 +51,44 @@ fn get_tmp_key_loc() -> String {
         .into()
 }
 
+/// create the wallet before the node starts so its address is known up front
+fn get_staker_key_loc() -> (String, String) {
+    let key_loc = get_tmp_key_loc();
+    let wallet = Wallet::new(key_loc.clone());
+    wallet.init(Some(PASSPHRASE), None).unwrap();
+    let address = wallet.get_address().unwrap().to_string();
+
+    (key_loc, address)
+}
+
 fn start_instances() -> Vec<Child> {
     println!("start");
     let mut children = vec![];
+    // nodes 0 and 1 produce the first blocks
+    let (key_loc_0, address_0) = get_staker_key_loc();
+    let (key_loc_1, address_1) = get_staker_key_loc();
+    let bootstrap_stakers = vec![address_0, address_1];
     let configs = vec![
         InstanceConfig {
             http_port: HTTP_PORT_0.into(),
             p2p_port: "5000".into(),
             initial: vec![],
-            key_loc: get_tmp_key_loc(),
+            key_loc: key_loc_0,
+            bootstrap_stakers: bootstrap_stakers.clone(),
         },
         InstanceConfig {
             http_port: HTTP_PORT_1.into(),
             p2p_port: "5001".into(),
             initial: vec![String::from("0.0.0.0:5000")],
-            key_loc: get_tmp_key_loc(),
+            key_loc: key_loc_1,
+            bootstrap_stakers: bootstrap_stakers.clone(),
         },
         InstanceConfig {
             http_port: HTTP_PORT_2.into(),
             p2p_port: "5002".into(),
             initial: vec![String::from("0.0.0.0:5000")],
             key_loc: get_tmp_key_loc(),
+            bootstrap_stakers,
         },
     ];
     for config in &configs {
@@ -78,15 +100,18 @@ fn start_instances() -> Vec<Child> {
     children
 }
 
-async fn mine_block(client: &Client, port: &str) {
+async fn mine_block(client: &Client, port: &str) -> u16 {
     println!("start mine");
-    client
+    let status = client
         .post(format!("http://localhost:{}/mineBlock", port))
         .send()
         .await
-        .unwrap();
+        .unwrap()
+        .status()
+        .as_u16();
 
     println!("end mine");
+    status
 }
 
 async fn get_balance(client: &Client, port: &str) -> u32 {
@@ -101,6 +126,23 @@ async fn get_balance(client: &Client, port: &str) -> u32 {
     text.parse().unwrap()
 }
 
+async fn get_immature_balance(client: &Client, port: &str) -> u32 {
+    let req = client
+        .get(format!("http://localhost:{}/immatureBalance", port))
+        .send()
+        .await
+        .unwrap();
+
+    let text = req.text().await.unwrap();
+
+    text.parse().unwrap()
+}
+
+/// spendable and immature balance together
+async fn get_total_balance(client: &Client, port: &str) -> u32 {
+    get_balance(client, port).await + get_immature_balance(client, port).await
+}
+
 async fn get_blocks(client: &Client, port: &str) -> Vec<Block> {
     let req = client
         .get(format!("http://localhost:{}/blocks", port))
@@ -122,7 +164,26 @@ async fn get_addr(client: &Client, port: &str) -> String {
     req.text().await.unwrap()
 }
 
-async fn mine_transaction(client: &Client, port: &str, addr: &String, amount: u64) {
+async fn unlock(client: &Client, port: &str, passphrase: &str) -> u16 {
+    client
+        .post(format!("http://localhost:{}/unlock", port))
+        .body(format!("{{\"passphrase\":\"{}\"}}", passphrase))
+        .send()
+        .await
+        .unwrap()
+        .status()
+        .as_u16()
+}
+
+async fn lock(client: &Client, port: &str) {
+    client
+        .post(format!("http://localhost:{}/lock", port))
+        .send()
+        .await
+        .unwrap();
+}
+
+async fn mine_transaction(client: &Client, port: &str, addr: &String, amount: u64) -> u16 {
     client
         .post(format!("http://localhost:{}/mineTransaction", port))
         .body(format!(
@@ -131,10 +192,12 @@ async fn mine_transaction(client: &Client, port: &str, addr: &String, amount: u6
         ))
         .send()
         .await
-        .unwrap();
+        .unwrap()
+        .status()
+        .as_u16()
 }
 
-async fn send_transaction(client: &Client, port: &str, addr: &String, amount: u64) {
+async fn send_transaction(client: &Client, port: &str, addr: &String, amount: u64) -> u16 {
     client
         .post(format!("http://localhost:{}/sendTransaction", port))
         .body(format!(
@@ -143,7 +206,9 @@ async fn send_transaction(client: &Client, port: &str, addr: &String, amount: u6
         ))
         .send()
         .await
-        .unwrap();
+        .unwrap()
+        .status()
+        .as_u16()
 }
 
 async fn get_pool(client: &Client, port: &str) -> Vec<Transaction> {
@@ -176,15 +241,34 @@ async fn test_all() {
 
     let client = Client::new();
 
+    // the wallet starts locked and can't sign blocks until it is unlocked
+    assert_eq!(mine_block(&client, HTTP_PORT_1).await, 403);
+    assert_eq!(unlock(&client, HTTP_PORT_0, PASSPHRASE).await, 200);
+    assert_eq!(unlock(&client, HTTP_PORT_1, PASSPHRASE).await, 200);
+
     // test mining and getting balance
+    // the bootstrap stakers produce the first 10 blocks without a stake, after that an output has to be
+    // 10 blocks old to be staked, so node 1 stakes the coinbases of blocks 1 and 2
     mine_block(&client, HTTP_PORT_1).await;
     mine_block(&client, HTTP_PORT_1).await;
-    for _ in 0..15 {
+    std::thread::sleep(Duration::from_secs_f32(SLEEP_DURATION));
+    for _ in 0..8 {
         mine_block(&client, HTTP_PORT_0).await;
         std::thread::sleep(Duration::from_secs_f32(0.1));
     }
+    std::thread::sleep(Duration::from_secs_f32(SLEEP_DURATION));
+    assert_eq!(mine_block(&client, HTTP_PORT_1).await, 200);
+    assert_eq!(mine_block(&client, HTTP_PORT_1).await, 200);
+    std::thread::sleep(Duration::from_secs_f32(SLEEP_DURATION));
+    for _ in 0..7 {
+        assert_eq!(mine_block(&client, HTTP_PORT_0).await, 200);
+        std::thread::sleep(Duration::from_secs_f32(0.1));
+    }
+    // coinbase outputs of the last COINBASE_MATURITY - 1 blocks can't be spent yet
     let balance = get_balance(&client, HTTP_PORT_0).await;
-    assert_eq!(balance, 750_u32);
+    assert_eq!(balance, 400_u32);
+    let immature_balance = get_immature_balance(&client, HTTP_PORT_0).await;
+    assert_eq!(immature_balance, 350_u32);
 
     std::thread::sleep(Duration::from_secs_f32(SLEEP_DURATION));
 
@@ -201,17 +285,28 @@ async fn test_all() {
 
     // test if mining transactions work
     let addr2 = get_addr(&client, HTTP_PORT_2).await;
+    // a locked wallet can't sign until it is unlocked
+    lock(&client, HTTP_PORT_0).await;
+    assert_eq!(
+        mine_transaction(&client, HTTP_PORT_0, &addr2, 100).await,
+        403
+    );
+    assert_eq!(unlock(&client, HTTP_PORT_0, "wrong passphrase").await, 403);
+    assert_eq!(unlock(&client, HTTP_PORT_0, PASSPHRASE).await, 200);
     mine_transaction(&client, HTTP_PORT_0, &addr2, 100).await;
-    let balance0 = get_balance(&client, HTTP_PORT_0).await;
-    let balance2 = get_balance(&client, HTTP_PORT_2).await;
+    let balance0 = get_total_balance(&client, HTTP_PORT_0).await;
+    let balance2 = get_total_balance(&client, HTTP_PORT_2).await;
     assert_eq!(balance0, 700_u32);
     assert_eq!(balance2, 100_u32);
 
     // test if sending transactions to pool work
     send_transaction(&client, HTTP_PORT_0, &addr2, 50).await;
     send_transaction(&client, HTTP_PORT_0, &addr2, 50).await;
-    let balance0 = get_balance(&client, HTTP_PORT_0).await;
-    let balance2 = get_balance(&client, HTTP_PORT_2).await;
+    // the checksum catches a typo in the address
+    let typo = addr2[..addr2.len() - 1].to_owned() + if addr2.ends_with('2') { "3" } else { "2" };
+    assert_eq!(send_transaction(&client, HTTP_PORT_0, &typo, 50).await, 400);
+    let balance0 = get_total_balance(&client, HTTP_PORT_0).await;
+    let balance2 = get_total_balance(&client, HTTP_PORT_2).await;
     assert_eq!(balance0, 700_u32);
     assert_eq!(balance2, 100_u32);
 
@@ -221,9 +316,9 @@ async fn test_all() {
 
     std::thread::sleep(Duration::from_secs_f32(SLEEP_DURATION));
 
-    let balance0 = get_balance(&client, HTTP_PORT_0).await;
-    let balance1 = get_balance(&client, HTTP_PORT_1).await - 100;
-    let balance2 = get_balance(&client, HTTP_PORT_2).await;
+    let balance0 = get_total_balance(&client, HTTP_PORT_0).await;
+    let balance1 = get_total_balance(&client, HTTP_PORT_1).await - 200;
+    let balance2 = get_total_balance(&client, HTTP_PORT_2).await;
     assert_eq!(balance0, 600_u32);
     assert_eq!(balance1, 50_u32);
     assert_eq!(balance2, 200_u32);